@@ -13,15 +13,22 @@ enum class SortDirection {
     Descending
 }
 
+enum class CollisionPolicy {
+    Fail,
+    Overwrite,
+    KeepBoth
+}
+
 @InvokeArg
 class GetImagesArgs(
         val limit: Int,
         val offset: Int,
         val source: String,
         val sortColumn: SortColumn?,
-        val sortDirection: SortDirection?
+        val sortDirection: SortDirection?,
+        val contentUris: List<String>?
 ) {
-    constructor() : this(10, 0, "", SortColumn.DateAdded, SortDirection.Ascending)
+    constructor() : this(10, 0, "", SortColumn.DateAdded, SortDirection.Ascending, null)
 }
 
 @InvokeArg
@@ -43,3 +50,31 @@ class GetImageArgs(val uri: String) {
 class DeleteImageArgs(val uri: String) {
     constructor() : this("")
 }
+
+@InvokeArg
+class TransferImageArgs(
+        val uri: String,
+        val source: String,
+        val album: String?,
+        val fileName: String?,
+        val collisionPolicy: CollisionPolicy?
+) {
+    constructor() : this("", "", null, null, null)
+}
+
+@InvokeArg
+class RenameImageArgs(val uri: String, val fileName: String, val collisionPolicy: CollisionPolicy?) {
+    constructor() : this("", "", null)
+}
+
+@InvokeArg
+class SaveImageArgs(
+        val source: String,
+        val album: String?,
+        val fileName: String,
+        val content: String?,
+        val path: String?,
+        val collisionPolicy: CollisionPolicy?
+) {
+    constructor() : this("", null, "", null, null, null)
+}
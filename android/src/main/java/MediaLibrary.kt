@@ -3,6 +3,7 @@ package de.universalappfactory.medialibrary
 import android.app.Activity
 import android.content.ContentResolver
 import android.content.ContentUris
+import android.content.ContentValues
 import android.database.Cursor
 import android.graphics.Bitmap
 import android.net.Uri
@@ -13,8 +14,11 @@ import android.provider.MediaStore
 import android.util.Base64
 import android.util.Log
 import android.util.Size
+import android.webkit.MimeTypeMap
 import app.tauri.plugin.JSObject
 import java.io.ByteArrayOutputStream
+import java.io.File
+import java.io.OutputStream
 
 // Available media library sources
 // @see https://developer.android.com/training/data-storage/shared/media
@@ -119,11 +123,19 @@ class MediaLibrary(
             offset: Int,
             imageSource: String,
             sortColumn: SortColumn?,
-            sortDirection: SortDirection?
+            sortDirection: SortDirection?,
+            contentUris: List<String>? = null
     ): Cursor? {
         val projection = getImageProjection()
         val imageCollection = getImageSource(imageSource) ?: return null
 
+        // the ids are numbers parsed from the uris, so they can be safely inlined
+        val selection =
+                contentUris?.let { uris ->
+                    val ids = uris.mapNotNull { uri -> runCatching { ContentUris.parseId(Uri.parse(uri)) }.getOrNull() }
+                    "${MediaStore.Images.Media._ID} IN (${ids.joinToString(",")})"
+                }
+
         // https://developer.android.com/reference/android/content/ContentProvider#query(android.net.Uri,%20java.lang.String[],%20android.os.Bundle,%20android.os.CancellationSignal)
         // Not sure which api level is correct here, query using bundle is added in 26 but limit and
         // offset does not seem to work
@@ -143,6 +155,7 @@ class MediaLibrary(
 
                         putInt(ContentResolver.QUERY_ARG_LIMIT, limit)
                         putInt(ContentResolver.QUERY_ARG_OFFSET, offset)
+                        selection?.let { putString(ContentResolver.QUERY_ARG_SQL_SELECTION, it) }
                     }
 
             contentResolver.query(
@@ -156,7 +169,7 @@ class MediaLibrary(
             contentResolver.query(
                     imageCollection,
                     projection,
-                    null,
+                    selection,
                     null,
                     "$sort LIMIT $limit OFFSET $offset"
             )
@@ -252,7 +265,7 @@ class MediaLibrary(
         val imageList = mutableListOf<JSObject>()
         val source = MediaLibrarySource.valueOf(args.source)
 
-        getQuery(args.limit, args.offset, args.source, args.sortColumn, args.sortDirection)?.use {
+        getQuery(args.limit, args.offset, args.source, args.sortColumn, args.sortDirection, args.contentUris)?.use {
                 cursor ->
             while (cursor.moveToNext()) {
                 val ret = createImageJSObjectFromCursor(cursor, source)
@@ -283,6 +296,176 @@ class MediaLibrary(
         return null
     }
 
+    private fun requireScopedStorage() {
+        if (Build.VERSION.SDK_INT < Build.VERSION_CODES.Q) {
+            throw UnsupportedOperationException("requires android 10 or newer")
+        }
+    }
+
+    // albums are relative to the storage root, e.g. "Pictures/Holidays"
+    private fun toRelativePath(album: String?): String {
+        val path = album?.trim('/')?.takeIf { it.isNotEmpty() } ?: Environment.DIRECTORY_PICTURES
+        return "$path/"
+    }
+
+    private fun findImage(collection: Uri, relativePath: String, displayName: String): Long? {
+        val selection =
+                "${MediaStore.Images.Media.RELATIVE_PATH} = ? AND ${MediaStore.Images.Media.DISPLAY_NAME} = ?"
+        contentResolver.query(
+                        collection,
+                        arrayOf(MediaStore.Images.Media._ID),
+                        selection,
+                        arrayOf(relativePath, displayName),
+                        null
+                )
+                ?.use { cursor ->
+                    if (cursor.moveToFirst()) {
+                        return cursor.getLong(0)
+                    }
+                }
+        return null
+    }
+
+    private fun queryLocation(uri: Uri): Pair<String, String> {
+        val projection =
+                arrayOf(MediaStore.Images.Media.RELATIVE_PATH, MediaStore.Images.Media.DISPLAY_NAME)
+        contentResolver.query(uri, projection, null, null, null)?.use { cursor ->
+            if (cursor.moveToFirst()) {
+                return Pair(cursor.getString(0) ?: "", cursor.getString(1) ?: "")
+            }
+        }
+        throw IllegalArgumentException("image not found: $uri")
+    }
+
+    // The media store appends a counter to duplicate display names on its own, which is what
+    // KeepBoth asks for, so only Fail and Overwrite need to be handled here.
+    private fun prepareTarget(
+            collection: Uri,
+            relativePath: String,
+            displayName: String,
+            policy: CollisionPolicy?,
+            currentId: Long?
+    ) {
+        val existingId = findImage(collection, relativePath, displayName) ?: return
+        if (existingId == currentId) {
+            return
+        }
+        when (policy ?: CollisionPolicy.Fail) {
+            CollisionPolicy.Fail -> throw IllegalStateException("file already exists: $relativePath$displayName")
+            CollisionPolicy.Overwrite ->
+                    contentResolver.delete(ContentUris.withAppendedId(collection, existingId), null, null)
+            CollisionPolicy.KeepBoth -> {}
+        }
+    }
+
+    private fun insertImage(
+            source: String,
+            album: String?,
+            displayName: String,
+            mimeType: String?,
+            policy: CollisionPolicy?,
+            write: (OutputStream) -> Unit
+    ): JSObject? {
+        requireScopedStorage()
+        val collection = getImageSource(source) ?: throw IllegalArgumentException("unknown source: $source")
+        val relativePath = toRelativePath(album)
+        prepareTarget(collection, relativePath, displayName, policy, null)
+
+        val values =
+                ContentValues().apply {
+                    put(MediaStore.Images.Media.DISPLAY_NAME, displayName)
+                    put(MediaStore.Images.Media.RELATIVE_PATH, relativePath)
+                    put(MediaStore.Images.Media.IS_PENDING, 1)
+                    mimeType?.let { put(MediaStore.Images.Media.MIME_TYPE, it) }
+                }
+
+        val newUri = contentResolver.insert(collection, values) ?: throw IllegalStateException("insert failed")
+        try {
+            contentResolver.openOutputStream(newUri)?.use(write)
+                    ?: throw IllegalStateException("unable to open $newUri")
+
+            // the image becomes visible to other apps once it is no longer pending
+            val published = ContentValues().apply { put(MediaStore.Images.Media.IS_PENDING, 0) }
+            contentResolver.update(newUri, published, null, null)
+        } catch (e: Exception) {
+            contentResolver.delete(newUri, null, null)
+            throw e
+        }
+
+        return getImage(
+                ContentUris.withAppendedId(MediaStore.Images.Media.EXTERNAL_CONTENT_URI, ContentUris.parseId(newUri))
+                        .toString()
+        )
+    }
+
+    fun moveImage(args: TransferImageArgs): JSObject? {
+        requireScopedStorage()
+        val uri = Uri.parse(args.uri)
+        val collection = getImageSource(args.source) ?: throw IllegalArgumentException("unknown source: ${args.source}")
+        val (_, currentName) = queryLocation(uri)
+        val displayName = args.fileName ?: currentName
+        val relativePath = toRelativePath(args.album)
+
+        prepareTarget(collection, relativePath, displayName, args.collisionPolicy, ContentUris.parseId(uri))
+
+        val values =
+                ContentValues().apply {
+                    put(MediaStore.Images.Media.DISPLAY_NAME, displayName)
+                    put(MediaStore.Images.Media.RELATIVE_PATH, relativePath)
+                }
+        contentResolver.update(uri, values, null, null)
+        return getImage(args.uri)
+    }
+
+    fun copyImage(args: TransferImageArgs): JSObject? {
+        val uri = Uri.parse(args.uri)
+        val (_, currentName) = queryLocation(uri)
+        return insertImage(
+                args.source,
+                args.album,
+                args.fileName ?: currentName,
+                contentResolver.getType(uri),
+                args.collisionPolicy
+        ) { outputStream ->
+            contentResolver.openInputStream(uri)?.use { inputStream -> inputStream.copyTo(outputStream) }
+                    ?: throw IllegalStateException("unable to open $uri")
+        }
+    }
+
+    fun saveImage(args: SaveImageArgs): JSObject? {
+        val extension = args.fileName.substringAfterLast('.', "").lowercase()
+        val mimeType = MimeTypeMap.getSingleton().getMimeTypeFromExtension(extension)
+
+        return insertImage(args.source, args.album, args.fileName, mimeType, args.collisionPolicy) {
+                outputStream ->
+            when {
+                args.content != null ->
+                        outputStream.write(Base64.decode(args.content, Base64.DEFAULT))
+                args.path != null ->
+                        File(args.path).inputStream().use { inputStream -> inputStream.copyTo(outputStream) }
+                else -> throw IllegalArgumentException("no image content provided")
+            }
+        }
+    }
+
+    fun renameImage(args: RenameImageArgs): JSObject? {
+        requireScopedStorage()
+        val uri = Uri.parse(args.uri)
+        val (relativePath, _) = queryLocation(uri)
+
+        prepareTarget(
+                MediaStore.Images.Media.EXTERNAL_CONTENT_URI,
+                relativePath,
+                args.fileName,
+                args.collisionPolicy,
+                ContentUris.parseId(uri)
+        )
+
+        val values = ContentValues().apply { put(MediaStore.Images.Media.DISPLAY_NAME, args.fileName) }
+        contentResolver.update(uri, values, null, null)
+        return getImage(args.uri)
+    }
+
     fun deleteImage(contentUriString: String): JSObject {
         val uri = Uri.parse(contentUriString)
         val ret = JSObject()
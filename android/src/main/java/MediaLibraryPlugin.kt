@@ -104,6 +104,50 @@ class MediaLibraryPlugin(private val activity: Activity) : Plugin(activity) {
         }
     }
 
+    @Command
+    fun moveImage(invoke: Invoke) {
+        val args = invoke.parseArgs(TransferImageArgs::class.java)
+        try {
+            val mediaLibaray = MediaLibrary(activity.contentResolver, activity)
+            invoke.resolve(mediaLibaray.moveImage(args))
+        } catch (e: Exception) {
+            invoke.reject("Failed to move image: ${e.message}")
+        }
+    }
+
+    @Command
+    fun copyImage(invoke: Invoke) {
+        val args = invoke.parseArgs(TransferImageArgs::class.java)
+        try {
+            val mediaLibaray = MediaLibrary(activity.contentResolver, activity)
+            invoke.resolve(mediaLibaray.copyImage(args))
+        } catch (e: Exception) {
+            invoke.reject("Failed to copy image: ${e.message}")
+        }
+    }
+
+    @Command
+    fun renameImage(invoke: Invoke) {
+        val args = invoke.parseArgs(RenameImageArgs::class.java)
+        try {
+            val mediaLibaray = MediaLibrary(activity.contentResolver, activity)
+            invoke.resolve(mediaLibaray.renameImage(args))
+        } catch (e: Exception) {
+            invoke.reject("Failed to rename image: ${e.message}")
+        }
+    }
+
+    @Command
+    fun saveImage(invoke: Invoke) {
+        val args = invoke.parseArgs(SaveImageArgs::class.java)
+        try {
+            val mediaLibaray = MediaLibrary(activity.contentResolver, activity)
+            invoke.resolve(mediaLibaray.saveImage(args))
+        } catch (e: Exception) {
+            invoke.reject("Failed to save image: ${e.message}")
+        }
+    }
+
     @Command
     fun getThumbnailAsBase64(invoke: Invoke) {
         val args = invoke.parseArgs(GetThumbnailArgs::class.java)
@@ -132,11 +176,46 @@ class MediaLibraryPlugin(private val activity: Activity) : Plugin(activity) {
         invoke.resolve(ret)
     }
 
+    /** Read and write state of every media library source. */
+    @SuppressLint("ObsoleteSdkInt")
+    private fun permissionResponse(): JSObject {
+        val read =
+                if (Build.VERSION.SDK_INT < Build.VERSION_CODES.TIRAMISU) {
+                    getPermissionState(EXTERNAL_STORAGE_ALIAS)
+                } else {
+                    getPermissionState(MEDIA_IMAGES_ALIAS)
+                }
+        // since Android 10 images are written through the media store, other apps' images
+        // are changed after confirming a request of the system
+        val write =
+                if (Build.VERSION.SDK_INT < Build.VERSION_CODES.Q) {
+                    getPermissionState(WRITE_EXTERNAL_STORAGE_ALIAS)
+                } else {
+                    read
+                }
+
+        val sources = JSObject()
+        for (source in listOf("ExternalStorage", "VolumeExternalPrimary")) {
+            val permission = JSObject()
+            permission.put("read", read.toString())
+            permission.put("write", write.toString())
+            sources.put(source, permission)
+        }
+
+        val response = JSObject()
+        response.put("sources", sources)
+        response.put("sandboxed", false)
+        return response
+    }
+
     @PermissionCallback
     private fun storagePermissionCallback(invoke: Invoke) {
-        val permissionsResultJSON = JSObject()
-        permissionsResultJSON.put("postNotification", getPermissionState(EXTERNAL_STORAGE_ALIAS))
-        invoke.resolve(permissionsResultJSON)
+        invoke.resolve(permissionResponse())
+    }
+
+    @Command
+    override fun checkPermissions(invoke: Invoke) {
+        invoke.resolve(permissionResponse())
     }
 
     @SuppressLint("ObsoleteSdkInt")
@@ -1,10 +1,31 @@
 const COMMANDS: &[&str] = &[
     "get_images",
+    "get_images_stream",
     "get_thumbnail",
     "get_available_sources",
     "request_permissions",
+    "check_permissions",
     "get_image",
     "delete_image",
+    "move_image",
+    "copy_image",
+    "rename_image",
+    "save_image",
+    "import_folder",
+    "set_favorite",
+    "add_tags",
+    "remove_tags",
+    "list_tags",
+    "get_xmp_metadata",
+    "set_xmp_metadata",
+    "find_duplicates",
+    "find_similar",
+    "find_similar_groups",
+    "cancel_job",
+    "prioritize_thumbnails",
+    "get_cache_stats",
+    "purge_thumbnail_cache",
+    "invalidate_thumbnails",
 ];
 
 #[derive(schemars::JsonSchema)]
@@ -55,6 +76,11 @@ enum MediaLibraryScopeEntry {
         /// - "https://myapi.service.com/users/*": allows access to any URLs that begins with "https://myapi.service.com/users/"
         source: MediaLibrarySource,
     },
+    /// A folder `importFolder` may import from, including its subfolders.
+    ImportFolder {
+        #[serde(rename = "importFolder")]
+        import_folder: std::path::PathBuf,
+    },
 }
 
 fn main() {
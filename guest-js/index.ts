@@ -1,18 +1,38 @@
-import { invoke } from "@tauri-apps/api/core";
+import { Channel, invoke } from "@tauri-apps/api/core";
+import { listen, UnlistenFn } from "@tauri-apps/api/event";
 
-export type ErrorKind = "XdgPicturesDirNotSet";
+export type ErrorKind =
+  | "NotFound"
+  | "AlreadyExists"
+  | "Forbidden"
+  | "SourceNotSupported"
+  | "PermissionDenied"
+  | "InvalidUri"
+  | "InvalidArgument"
+  | "ThumbnailUnavailable"
+  | "ImageProcessing"
+  | "Metadata"
+  | "Cancelled"
+  | "Unsupported"
+  | "XdgPicturesDirNotSet"
+  | "Io"
+  | "Internal";
 
 type InternalPluginError = {
   kind: ErrorKind;
   message: string;
+  /** Values of the error like the `path`, `uri`, `source` or `jobId`, null if there are none */
+  details?: Record<string, unknown> | null;
 };
 
 export class PluginError extends Error {
-  errorKind: string;
+  errorKind: ErrorKind;
+  details: Record<string, unknown> | null;
   constructor(error: InternalPluginError) {
     super(error.message);
     this.name = "CustomError";
     this.errorKind = error.kind;
+    this.details = error.details ?? null;
   }
 }
 
@@ -28,7 +48,7 @@ async function invokeCommand<T>(func: () => Promise<T>): Promise<T> {
   } catch (error) {
     console.error(error);
     if (typeof error === "string") {
-      throw new PluginError({ kind: error as ErrorKind, message: "" });
+      throw new PluginError({ kind: "Internal", message: error });
     }
     if (isInternalError(error)) {
       throw new PluginError(error);
@@ -44,12 +64,127 @@ export interface ImageInfo {
   metaData?: Record<MetadataField, string>;
   imageUri: string;
   thumbnailUri: string;
+  /** the file extension does not match the type detected from the content */
+  extensionMismatch: boolean;
+  favorite: boolean;
+  tags: string[];
+}
+
+export interface TagInfo {
+  name: string;
+  count: number;
+}
+
+export interface FindDuplicatesRequest {
+  source: MediaLibrarySource;
+  /** passed along with the progress events */
+  jobId?: string;
+}
+
+export enum JobKind {
+  Listing = "Listing",
+  /** content hashes to find duplicates */
+  Hashing = "Hashing",
+  /** perceptual hashes to find similar images */
+  Indexing = "Indexing",
+  Import = "Import",
+}
+
+export interface JobProgress {
+  jobId?: string;
+  kind: JobKind;
+  /** image files found so far */
+  filesSeen: number;
+  /** total size of the files found so far */
+  bytes: number;
+  currentFolder: string;
+  /** set on the last event of the job */
+  finished: boolean;
+  cancelled: boolean;
+}
+
+export interface ScanProgress {
+  jobId?: string;
+  total: number;
+  processed: number;
+  currentPath: string;
+}
+
+export interface DuplicateGroup {
+  hash: string;
+  /** size of a single file in bytes */
+  size: number;
+  /** content uri of the image we suggest to keep, it is also the first of the items */
+  suggestedKeeper: string;
+  items: ImageInfo[];
+}
+
+export interface FindDuplicatesResult {
+  jobId?: string;
+  /** sorted by the space which could be freed, largest first */
+  groups: DuplicateGroup[];
+}
+
+export interface SimilarImage {
+  /** number of differing bits of the perceptual hashes (0 - 64) */
+  distance: number;
+  image: ImageInfo;
+}
+
+export interface FindSimilarGroupsRequest {
+  source: MediaLibrarySource;
+  /** maximum distance of similar images, defaults to 10 */
+  threshold?: number;
+  /** passed along with the progress events */
+  jobId?: string;
+}
+
+export interface SimilarGroup {
+  items: ImageInfo[];
+}
+
+export interface FindSimilarGroupsResult {
+  jobId?: string;
+  /** sorted by the number of images, largest first */
+  groups: SimilarGroup[];
+}
+
+export interface XmpMetadata {
+  rating?: number;
+  label?: string;
+  keywords: string[];
+  description?: string;
+  title?: string;
+}
+
+/** fields which are not set are kept, empty values remove the property */
+export interface SetXmpMetadataRequest {
+  uri: string;
+  rating?: number;
+  label?: string;
+  keywords?: string[];
+  description?: string;
+  title?: string;
 }
 
 export interface GetImagesResult {
   items: ImageInfo[];
 }
 
+export interface CacheStats {
+  /** Directory of the disk cache, not set if it is disabled */
+  directory?: string;
+  thumbnailCount: number;
+  thumbnailBytes: number;
+  maxBytes: number;
+  memoryCount: number;
+  memoryBytes: number;
+  /** Cached renditions of the `image://` protocol */
+  renditionCount: number;
+  renditionBytes: number;
+  renditionMaxBytes: number;
+}
+
 export interface GetThumbnailResponse {
   content: string;
 }
@@ -61,6 +196,102 @@ export interface GetLibraryContentRequest {
   sortColumn?: SortColumn;
   sortDirection?: SortDirection;
   includeFileMetadata?: boolean;
+  /** only return images marked as favorite */
+  favoritesOnly?: boolean;
+  /** only return images which carry all of the given tags */
+  tags?: string[];
+  /** read rating, label, keywords, description and title from XMP (desktop only) */
+  includeXmpMetadata?: boolean;
+  /** read the date taken and camera settings from EXIF, also of RAW files (desktop only) */
+  includeExifMetadata?: boolean;
+  /** detect the type of files by their content, defaults to Fallback */
+  contentSniffing?: ContentSniffing;
+  /** id to receive progress events and to cancel the listing (desktop only) */
+  jobId?: string;
+}
+
+export enum ContentSniffing {
+  /** only the file extension is used */
+  Never = "Never",
+  /** the content is only read for files without a known image extension */
+  Fallback = "Fallback",
+  /** the content of every file is read */
+  Always = "Always",
+}
+
+export enum CollisionPolicy {
+  Fail = "Fail",
+  Overwrite = "Overwrite",
+  KeepBoth = "KeepBoth",
+}
+
+export interface TransferImageRequest {
+  uri: string;
+  source: MediaLibrarySource;
+  album?: string;
+  fileName?: string;
+  collisionPolicy?: CollisionPolicy;
+}
+
+export interface RenameImageRequest {
+  uri: string;
+  fileName: string;
+  collisionPolicy?: CollisionPolicy;
+}
+
+export interface SaveImageRequest {
+  source: MediaLibrarySource;
+  album?: string;
+  fileName: string;
+  /** base64 encoded image data */
+  content?: string;
+  /** path of a file in the temporary or the app cache directory containing the image data */
+  path?: string;
+  collisionPolicy?: CollisionPolicy;
+}
+
+export enum ImportMode {
+  Copy = "Copy",
+  Move = "Move",
+}
+
+export interface ImportRequest {
+  /** the folder to import from */
+  folder: string;
+  source: MediaLibrarySource;
+  album?: string;
+  /** strftime like template, defaults to "%Y/%m" */
+  folderTemplate?: string;
+  mode?: ImportMode;
+  collisionPolicy?: CollisionPolicy;
+  /** passed along with the progress events */
+  jobId?: string;
+}
+
+export interface ImportProgress {
+  jobId?: string;
+  total: number;
+  processed: number;
+  currentPath: string;
+}
+
+export interface ImportSkipped {
+  path: string;
+  duplicateOf: string;
+}
+
+export interface ImportFailure {
+  path: string;
+  error: string;
+}
+
+export interface ImportReport {
+  jobId?: string;
+  imported: ImageInfo[];
+  skipped: ImportSkipped[];
+  failed: ImportFailure[];
+  /** the import was cancelled, the report contains the files handled until then */
+  cancelled: boolean;
 }
 
 export interface GetPermissionsRequest {
@@ -73,8 +304,15 @@ export type PermissionState =
   | "prompt"
   | "prompt-with-rationale";
 
+export interface SourcePermission {
+  read: PermissionState;
+  write: PermissionState;
+}
+
 export interface PermissionResponse {
-  postNotification: PermissionState;
+  sources: Partial<Record<MediaLibrarySource, SourcePermission>>;
+  /** the app runs in a sandbox like Flatpak or Snap */
+  sandboxed: boolean;
 }
 
 export async function getImages(
@@ -87,6 +325,27 @@ export async function getImages(
   });
 }
 
+export type ImageStreamEvent =
+  | { event: "batch"; data: { items: ImageInfo[] } }
+  | { event: "finished"; data: { total: number; batches: number } };
+
+/** Lists a source like getImages, the images are passed to onEvent in batches as they are found. */
+export async function getImagesStream(
+  request: GetLibraryContentRequest,
+  onEvent: (event: ImageStreamEvent) => void,
+  batchSize?: number,
+): Promise<void> {
+  const channel = new Channel<ImageStreamEvent>();
+  channel.onmessage = onEvent;
+  return await invokeCommand<void>(async () => {
+    return await invoke("plugin:medialibrary|get_images_stream", {
+      request: request,
+      batchSize: batchSize,
+      onEvent: channel,
+    });
+  });
+}
+
 export async function getImage(contentUri: string): Promise<ImageInfo | null> {
   return await invokeCommand<ImageInfo | null>(async () => {
     return await invoke("plugin:medialibrary|get_image", {
@@ -103,6 +362,243 @@ export async function deleteImage(contentUri: string): Promise<void> {
   });
 }
 
+export async function moveImage(
+  request: TransferImageRequest,
+): Promise<ImageInfo> {
+  return await invokeCommand<ImageInfo>(async () => {
+    return await invoke("plugin:medialibrary|move_image", {
+      request: request,
+    });
+  });
+}
+
+export async function copyImage(
+  request: TransferImageRequest,
+): Promise<ImageInfo> {
+  return await invokeCommand<ImageInfo>(async () => {
+    return await invoke("plugin:medialibrary|copy_image", {
+      request: request,
+    });
+  });
+}
+
+export async function renameImage(
+  request: RenameImageRequest,
+): Promise<ImageInfo> {
+  return await invokeCommand<ImageInfo>(async () => {
+    return await invoke("plugin:medialibrary|rename_image", {
+      request: request,
+    });
+  });
+}
+
+export async function saveImage(request: SaveImageRequest): Promise<ImageInfo> {
+  return await invokeCommand<ImageInfo>(async () => {
+    return await invoke("plugin:medialibrary|save_image", {
+      request: request,
+    });
+  });
+}
+
+export async function importFolder(
+  request: ImportRequest,
+): Promise<ImportReport> {
+  return await invokeCommand<ImportReport>(async () => {
+    return await invoke("plugin:medialibrary|import_folder", {
+      request: request,
+    });
+  });
+}
+
+export async function onImportProgress(
+  handler: (progress: ImportProgress) => void,
+): Promise<UnlistenFn> {
+  return await listen<ImportProgress>("medialibrary://import-progress", (event) =>
+    handler(event.payload),
+  );
+}
+
+export async function onImportFinished(
+  handler: (report: ImportReport) => void,
+): Promise<UnlistenFn> {
+  return await listen<ImportReport>("medialibrary://import-finished", (event) =>
+    handler(event.payload),
+  );
+}
+
+export async function setFavorite(
+  uri: string,
+  favorite: boolean,
+): Promise<void> {
+  return await invokeCommand<void>(async () => {
+    return await invoke("plugin:medialibrary|set_favorite", {
+      uri: uri,
+      favorite: favorite,
+    });
+  });
+}
+
+export async function addTags(uri: string, tags: string[]): Promise<string[]> {
+  return await invokeCommand<string[]>(async () => {
+    return await invoke("plugin:medialibrary|add_tags", {
+      uri: uri,
+      tags: tags,
+    });
+  });
+}
+
+export async function removeTags(
+  uri: string,
+  tags: string[],
+): Promise<string[]> {
+  return await invokeCommand<string[]>(async () => {
+    return await invoke("plugin:medialibrary|remove_tags", {
+      uri: uri,
+      tags: tags,
+    });
+  });
+}
+
+export async function listTags(): Promise<TagInfo[]> {
+  return await invokeCommand<TagInfo[]>(async () => {
+    return await invoke("plugin:medialibrary|list_tags");
+  });
+}
+
+export async function findDuplicates(
+  request: FindDuplicatesRequest,
+): Promise<FindDuplicatesResult> {
+  return await invokeCommand<FindDuplicatesResult>(async () => {
+    return await invoke("plugin:medialibrary|find_duplicates", {
+      request: request,
+    });
+  });
+}
+
+export async function onDuplicatesProgress(
+  handler: (progress: ScanProgress) => void,
+): Promise<UnlistenFn> {
+  return await listen<ScanProgress>(
+    "medialibrary://duplicates-progress",
+    (event) => handler(event.payload),
+  );
+}
+
+export async function onDuplicatesFinished(
+  handler: (result: FindDuplicatesResult) => void,
+): Promise<UnlistenFn> {
+  return await listen<FindDuplicatesResult>(
+    "medialibrary://duplicates-finished",
+    (event) => handler(event.payload),
+  );
+}
+
+export async function findSimilar(
+  uri: string,
+  threshold?: number,
+): Promise<SimilarImage[]> {
+  return await invokeCommand<SimilarImage[]>(async () => {
+    return await invoke("plugin:medialibrary|find_similar", {
+      uri: uri,
+      threshold: threshold,
+    });
+  });
+}
+
+export async function findSimilarGroups(
+  request: FindSimilarGroupsRequest,
+): Promise<FindSimilarGroupsResult> {
+  return await invokeCommand<FindSimilarGroupsResult>(async () => {
+    return await invoke("plugin:medialibrary|find_similar_groups", {
+      request: request,
+    });
+  });
+}
+
+export async function onSimilarityProgress(
+  handler: (progress: ScanProgress) => void,
+): Promise<UnlistenFn> {
+  return await listen<ScanProgress>(
+    "medialibrary://similarity-progress",
+    (event) => handler(event.payload),
+  );
+}
+
+export async function onSimilarityFinished(
+  handler: (result: FindSimilarGroupsResult) => void,
+): Promise<UnlistenFn> {
+  return await listen<FindSimilarGroupsResult>(
+    "medialibrary://similarity-finished",
+    (event) => handler(event.payload),
+  );
+}
+
+/** Creates the thumbnails of these images next in the background (desktop only). */
+export async function prioritizeThumbnails(contentUris: string[]): Promise<void> {
+  return await invokeCommand<void>(async () => {
+    return await invoke("plugin:medialibrary|prioritize_thumbnails", {
+      uris: contentUris,
+    });
+  });
+}
+
+export async function getCacheStats(): Promise<CacheStats> {
+  return await invokeCommand<CacheStats>(async () => {
+    return await invoke("plugin:medialibrary|get_cache_stats");
+  });
+}
+
+/** Removes all cached thumbnails from disk and memory, as well as the cached renditions. */
+export async function purgeThumbnailCache(): Promise<void> {
+  return await invokeCommand<void>(async () => {
+    return await invoke("plugin:medialibrary|purge_thumbnail_cache");
+  });
+}
+
+/** Removes the cached thumbnails of these images, e.g. after editing them in place. */
+export async function invalidateThumbnails(contentUris: string[]): Promise<void> {
+  return await invokeCommand<void>(async () => {
+    return await invoke("plugin:medialibrary|invalidate_thumbnails", {
+      uris: contentUris,
+    });
+  });
+}
+
+/** Returns false if there is no running job with this id. */
+export async function cancelJob(jobId: string): Promise<boolean> {
+  return await invokeCommand<boolean>(async () => {
+    return await invoke("plugin:medialibrary|cancel_job", {
+      jobId: jobId,
+    });
+  });
+}
+
+export async function onJobProgress(
+  handler: (progress: JobProgress) => void,
+): Promise<UnlistenFn> {
+  return await listen<JobProgress>("medialibrary://job-progress", (event) =>
+    handler(event.payload),
+  );
+}
+
+export async function getXmpMetadata(uri: string): Promise<XmpMetadata> {
+  return await invokeCommand<XmpMetadata>(async () => {
+    return await invoke("plugin:medialibrary|get_xmp_metadata", {
+      uri: uri,
+    });
+  });
+}
+
+export async function setXmpMetadata(
+  request: SetXmpMetadataRequest,
+): Promise<XmpMetadata> {
+  return await invokeCommand<XmpMetadata>(async () => {
+    return await invoke("plugin:medialibrary|set_xmp_metadata", {
+      request: request,
+    });
+  });
+}
+
 export async function getThumbnail(
   uri: string,
 ): Promise<GetThumbnailResponse | null> {
@@ -138,7 +634,19 @@ export type MetadataField =
   | "fileSize"
   | "fileName"
   | "fileExtension"
-  | "fileReadOnly";
+  | "fileReadOnly"
+  | "rating"
+  | "colorLabel"
+  | "keywords"
+  | "description"
+  | "title"
+  | "cameraMake"
+  | "cameraModel"
+  | "lensModel"
+  | "exposureTime"
+  | "fNumber"
+  | "focalLength"
+  | "iso";
 
 export async function getAvailableSources(): Promise<
   MediaLibrarySource[] | null
@@ -147,6 +655,13 @@ export async function getAvailableSources(): Promise<
   return result as MediaLibrarySource[];
 }
 
+/** Returns the read and write access to every source. */
+export async function checkPermissions(): Promise<PermissionResponse> {
+  return await invokeCommand<PermissionResponse>(async () => {
+    return await invoke("plugin:medialibrary|check_permissions");
+  });
+}
+
 export async function requestPermissions(
   request: GetPermissionsRequest,
 ): Promise<PermissionResponse | null> {
@@ -0,0 +1,95 @@
+use std::{fs, io, path::Path};
+
+use tauri::plugin::PermissionState;
+
+use crate::SourcePermission;
+
+/// Sandbox the app is running in, folders outside of it are only accessible once granted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sandbox {
+    Flatpak,
+    Snap,
+    /// macOS App Sandbox
+    MacOs,
+}
+
+/// Detects the sandbox from the files and variables its runtime provides.
+pub(crate) fn detect_sandbox() -> Option<Sandbox> {
+    if Path::new("/.flatpak-info").exists() || std::env::var_os("FLATPAK_ID").is_some() {
+        Some(Sandbox::Flatpak)
+    } else if std::env::var_os("SNAP").is_some() {
+        Some(Sandbox::Snap)
+    } else if std::env::var_os("APP_SANDBOX_CONTAINER_ID").is_some() {
+        Some(Sandbox::MacOs)
+    } else {
+        None
+    }
+}
+
+/// Determines the access to a root directory by listing it and asking the OS whether it is writable.
+/// Inaccessible folders of a sandboxed app can still be granted by the user, so they are
+/// reported as `prompt` instead of `denied`.
+pub(crate) fn source_permission(root: &Path, sandboxed: bool) -> SourcePermission {
+    let state = |result: io::Result<()>| match result {
+        Ok(()) => PermissionState::Granted,
+        Err(_) if sandboxed => PermissionState::Prompt,
+        Err(_) => PermissionState::Denied,
+    };
+
+    let read = state(fs::read_dir(root).map(|_| ()));
+    let write = match read {
+        PermissionState::Granted => state(check_writable(root)),
+        _ => read,
+    };
+    SourcePermission { read, write }
+}
+
+/// Checks the write access without creating a file in the folder of the user.
+#[cfg(unix)]
+fn check_writable(dir: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    // SAFETY: `path` is a nul terminated string which outlives the call
+    let result =
+        unsafe { libc::faccessat(libc::AT_FDCWD, path.as_ptr(), libc::W_OK, libc::AT_EACCESS) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn check_writable(dir: &Path) -> io::Result<()> {
+    if fs::metadata(dir)?.permissions().readonly() {
+        Err(io::ErrorKind::PermissionDenied.into())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_permission() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let permission = source_permission(dir, false);
+        assert_eq!(permission.read, PermissionState::Granted);
+        assert_eq!(permission.write, PermissionState::Granted);
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 0);
+
+        let missing = dir.join("missing");
+        assert_eq!(
+            source_permission(&missing, false).read,
+            PermissionState::Denied
+        );
+        let sandboxed = source_permission(&missing, true);
+        assert_eq!(sandboxed.read, PermissionState::Prompt);
+        assert_eq!(sandboxed.write, PermissionState::Prompt);
+    }
+}
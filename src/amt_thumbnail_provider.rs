@@ -2,22 +2,49 @@ use allmytoes::{AMTConfiguration, ThumbSize, AMT};
 use std::fs;
 use std::path::Path;
 
-use crate::{thumbnail_provider::ThumbnailProvider, Error, Thumbnail};
+use crate::{thumbnail_provider::ThumbnailProvider, Config, Error, Thumbnail};
 
-pub struct AmtThumbnailProvider;
+pub struct AmtThumbnailProvider {
+    size: u32,
+}
+
+impl AmtThumbnailProvider {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            size: config.thumbnail_size,
+        }
+    }
+
+    /// The smallest freedesktop size which is at least the configured size.
+    fn thumb_size(&self) -> ThumbSize {
+        match self.size {
+            0..=128 => ThumbSize::Normal,
+            129..=256 => ThumbSize::Large,
+            257..=512 => ThumbSize::XLarge,
+            _ => ThumbSize::XXLarge,
+        }
+    }
+}
 
 impl ThumbnailProvider for AmtThumbnailProvider {
-    fn get_thumbnail(path: &Path) -> crate::Result<Thumbnail> {
+    fn name(&self) -> &str {
+        "allmytoes"
+    }
+
+    fn get_thumbnail(&self, path: &Path) -> crate::Result<Thumbnail> {
         let configuration = AMTConfiguration::default();
         let amt = AMT::new(&configuration);
 
-        let thumb_size = ThumbSize::Large;
-        match amt.get(path, thumb_size) {
+        match amt.get(path, self.thumb_size()) {
             Ok(thumb) => {
                 let bytes = fs::read(&thumb.path)?;
-                Ok(bytes.into())
+                // freedesktop thumbnails may have been created from the raw pixel data
+                Ok(crate::orientation::upright_thumbnail(path, bytes.into()))
             }
-            Err(error) => Err(Error::AllMyToes(format!("get_thumbnail error: {error:?}"))),
+            Err(error) => Err(Error::ThumbnailProvider {
+                provider: self.name().to_string(),
+                message: format!("{error:?}"),
+            }),
         }
     }
 }
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::sync::Semaphore;
+
+/// Limits the number of blocking filesystem and decoding tasks running at the same time,
+/// so large scans do not occupy all threads of the blocking pool.
+#[derive(Clone)]
+pub(crate) struct BlockingPool {
+    permits: Arc<Semaphore>,
+}
+
+impl BlockingPool {
+    pub fn new(max_tasks: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_tasks.max(1))),
+        }
+    }
+
+    /// Runs the task on the blocking pool as soon as a slot is free.
+    pub async fn run<T, F>(&self, task: F) -> crate::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> crate::Result<T> + Send + 'static,
+    {
+        // the semaphore is never closed
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("blocking pool semaphore closed");
+        tauri::async_runtime::spawn_blocking(move || {
+            let _permit = permit;
+            task()
+        })
+        .await?
+    }
+}
+
+/// Runs the task on the blocking pool of the plugin.
+pub(crate) async fn run<R, T, F>(app: &AppHandle<R>, task: F) -> crate::Result<T>
+where
+    R: Runtime,
+    T: Send + 'static,
+    F: FnOnce(AppHandle<R>) -> crate::Result<T> + Send + 'static,
+{
+    let pool = app.state::<BlockingPool>().inner().clone();
+    let app = app.clone();
+    pool.run(move || task(app)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_concurrency_limit() {
+        let pool = BlockingPool::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let pool = pool.clone();
+                let running = running.clone();
+                let max_running = max_running.clone();
+                tauri::async_runtime::spawn(async move {
+                    pool.run(move || {
+                        let current = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_running.fetch_max(current, Ordering::SeqCst);
+                        std::thread::sleep(std::time::Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        Ok(())
+                    })
+                    .await
+                })
+            })
+            .collect();
+
+        tauri::async_runtime::block_on(async {
+            for task in tasks {
+                task.await.unwrap().unwrap();
+            }
+        });
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+}
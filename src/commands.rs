@@ -1,9 +1,80 @@
+use crate::blocking;
+use crate::jobs::Jobs;
 use crate::scope::Entry;
 use crate::MedialibraryExt;
 use crate::Result;
 use crate::{models::*, Error};
-use tauri::ipc::{CommandScope, GlobalScope};
-use tauri::{command, AppHandle, Runtime};
+use tauri::ipc::{Channel, CommandScope, GlobalScope};
+use tauri::{command, AppHandle, Runtime, State};
+
+fn ensure_source_allowed(
+    global_scope: &GlobalScope<Entry>,
+    command_scope: &CommandScope<Entry>,
+    source: &MediaLibrarySource,
+) -> Result<()> {
+    if global_scope
+        .allows()
+        .iter()
+        .chain(command_scope.allows())
+        .any(|c| c.source.as_ref() == Some(source))
+    {
+        Ok(())
+    } else {
+        Err(Error::MediaLibrarySourceForbidden(source.clone()))
+    }
+}
+
+/// `import_folder` can move the imported files, so it only reads folders allowed by an
+/// `importFolder` scope entry.
+fn ensure_import_folder_allowed(
+    global_scope: &GlobalScope<Entry>,
+    command_scope: &CommandScope<Entry>,
+    folder: &str,
+) -> Result<()> {
+    let path = std::fs::canonicalize(folder)?;
+    if global_scope
+        .allows()
+        .iter()
+        .chain(command_scope.allows())
+        .filter_map(|c| c.import_folder.as_ref()?.canonicalize().ok())
+        .any(|allowed| path.starts_with(allowed))
+    {
+        Ok(())
+    } else {
+        Err(Error::ImportFolderForbidden(folder.to_string()))
+    }
+}
+
+/// Resolves the sources of the uris on the file system, so it has to run on the blocking pool.
+fn ensure_uris_allowed<R: Runtime>(
+    app: &AppHandle<R>,
+    global_scope: &GlobalScope<Entry>,
+    command_scope: &CommandScope<Entry>,
+    uris: &[String],
+) -> Result<()> {
+    for uri in uris {
+        let source = app.medialibrary().get_source_of_uri(uri)?;
+        ensure_source_allowed(global_scope, command_scope, &source)?;
+    }
+    Ok(())
+}
+
+/// `save_image` only copies files from the temporary and the app cache directory, otherwise any
+/// file the app can read could be copied into a source and read back through `image://`.
+fn ensure_temporary_file<R: Runtime>(app: &AppHandle<R>, path: &str) -> Result<String> {
+    use tauri::Manager;
+
+    let path = std::fs::canonicalize(path)?;
+    let allowed = [app.path().temp_dir(), app.path().app_cache_dir()]
+        .into_iter()
+        .filter_map(|dir| dir.ok()?.canonicalize().ok())
+        .any(|dir| path.starts_with(dir));
+    if allowed {
+        Ok(path.to_string_lossy().to_string())
+    } else {
+        Err(Error::PathNotAllowed(path.to_string_lossy().to_string()))
+    }
+}
 
 #[command]
 pub(crate) async fn get_images<R: Runtime>(
@@ -12,21 +83,47 @@ pub(crate) async fn get_images<R: Runtime>(
     command_scope: CommandScope<Entry>,
     request: GetLibraryContentRequest,
 ) -> Result<GetImagesResult> {
-    if !global_scope
-        .allows()
-        .iter()
-        .chain(command_scope.allows())
-        .any(|c| c.source.eq(&request.source))
-    {
-        return Err(Error::MediaLibrarySourceForbidden(request.source));
-    }
+    ensure_source_allowed(&global_scope, &command_scope, &request.source)?;
 
     let include_file_metadata = request.include_file_metadata.unwrap_or_default();
 
-    match app.medialibrary().get_images(request) {
-        Ok(images) => Ok(images.with_file_metadata(include_file_metadata)),
-        Err(err) => Err(err),
-    }
+    blocking::run(&app, move |app| {
+        app.medialibrary()
+            .get_images(request)
+            .map(|images| images.with_file_metadata(include_file_metadata))
+    })
+    .await
+}
+
+/// Number of images per message of `get_images_stream` if the caller does not provide it.
+const DEFAULT_STREAM_BATCH_SIZE: usize = 100;
+
+#[command]
+pub(crate) async fn get_images_stream<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    request: GetLibraryContentRequest,
+    batch_size: Option<usize>,
+    on_event: Channel<ImageStreamEvent>,
+) -> Result<()> {
+    ensure_source_allowed(&global_scope, &command_scope, &request.source)?;
+
+    let include_file_metadata = request.include_file_metadata.unwrap_or_default();
+    let batch_size = batch_size.unwrap_or(DEFAULT_STREAM_BATCH_SIZE);
+
+    blocking::run(&app, move |app| {
+        let mut batches = 0;
+        let total = app
+            .medialibrary()
+            .stream_images(request, batch_size, |result| {
+                batches += 1;
+                let items = result.with_file_metadata(include_file_metadata).items;
+                Ok(on_event.send(ImageStreamEvent::Batch { items })?)
+            })?;
+        Ok(on_event.send(ImageStreamEvent::Finished { total, batches })?)
+    })
+    .await
 }
 
 #[command]
@@ -34,7 +131,12 @@ pub(crate) async fn request_permissions<R: Runtime>(
     app: AppHandle<R>,
     args: RequestPermissionsArgs,
 ) -> Result<PermissionResponse> {
-    app.medialibrary().request_permissions(args)
+    app.medialibrary().request_permissions(args).await
+}
+
+#[command]
+pub(crate) async fn check_permissions<R: Runtime>(app: AppHandle<R>) -> Result<PermissionResponse> {
+    blocking::run(&app, move |app| app.medialibrary().check_permissions()).await
 }
 
 #[command]
@@ -50,7 +152,7 @@ pub(crate) async fn get_image<R: Runtime>(
     app: AppHandle<R>,
     uri: String,
 ) -> Result<Option<ImageInfo>> {
-    app.medialibrary().get_image(uri.into())
+    blocking::run(&app, move |app| app.medialibrary().get_image(uri.into())).await
 }
 
 #[command]
@@ -62,7 +164,7 @@ pub(crate) async fn get_available_sources<R: Runtime>(
     let allowed_sources = global_scope
         .allows()
         .iter()
-        .map(|f| f.source.clone())
+        .filter_map(|f| f.source.clone())
         .collect();
 
     Ok(allowed_sources)
@@ -70,5 +172,260 @@ pub(crate) async fn get_available_sources<R: Runtime>(
 
 #[command]
 pub(crate) async fn delete_image<R: Runtime>(app: AppHandle<R>, uri: String) -> Result<()> {
-    app.medialibrary().delete_image(uri.into())
+    blocking::run(&app, move |app| app.medialibrary().delete_image(uri.into())).await
+}
+
+#[command]
+pub(crate) async fn move_image<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    request: TransferImageRequest,
+) -> Result<ImageInfo> {
+    ensure_source_allowed(&global_scope, &command_scope, &request.source)?;
+
+    blocking::run(&app, move |app| {
+        let current_source = app.medialibrary().get_source_of_uri(&request.uri)?;
+        ensure_source_allowed(&global_scope, &command_scope, &current_source)?;
+        app.medialibrary().move_image(request)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn copy_image<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    request: TransferImageRequest,
+) -> Result<ImageInfo> {
+    ensure_source_allowed(&global_scope, &command_scope, &request.source)?;
+
+    blocking::run(&app, move |app| {
+        let current_source = app.medialibrary().get_source_of_uri(&request.uri)?;
+        ensure_source_allowed(&global_scope, &command_scope, &current_source)?;
+        app.medialibrary().copy_image(request)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn rename_image<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    request: RenameImageRequest,
+) -> Result<ImageInfo> {
+    blocking::run(&app, move |app| {
+        let current_source = app.medialibrary().get_source_of_uri(&request.uri)?;
+        ensure_source_allowed(&global_scope, &command_scope, &current_source)?;
+        app.medialibrary().rename_image(request)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn save_image<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    mut request: SaveImageRequest,
+) -> Result<ImageInfo> {
+    ensure_source_allowed(&global_scope, &command_scope, &request.source)?;
+
+    blocking::run(&app, move |app| {
+        if request.content.is_none() {
+            if let Some(path) = &request.path {
+                request.path = Some(ensure_temporary_file(&app, path)?);
+            }
+        }
+        app.medialibrary().save_image(request)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn import_folder<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    request: ImportRequest,
+) -> Result<ImportReport> {
+    ensure_source_allowed(&global_scope, &command_scope, &request.source)?;
+
+    blocking::run(&app, move |app| {
+        ensure_import_folder_allowed(&global_scope, &command_scope, &request.folder)?;
+        app.medialibrary().import_folder(request)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn set_favorite<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    uri: String,
+    favorite: bool,
+) -> Result<()> {
+    blocking::run(&app, move |app| {
+        let source = app.medialibrary().get_source_of_uri(&uri)?;
+        ensure_source_allowed(&global_scope, &command_scope, &source)?;
+        app.medialibrary().set_favorite(&uri, favorite)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn add_tags<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    uri: String,
+    tags: Vec<String>,
+) -> Result<Vec<String>> {
+    blocking::run(&app, move |app| {
+        let source = app.medialibrary().get_source_of_uri(&uri)?;
+        ensure_source_allowed(&global_scope, &command_scope, &source)?;
+        app.medialibrary().add_tags(&uri, &tags)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn remove_tags<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    uri: String,
+    tags: Vec<String>,
+) -> Result<Vec<String>> {
+    blocking::run(&app, move |app| {
+        let source = app.medialibrary().get_source_of_uri(&uri)?;
+        ensure_source_allowed(&global_scope, &command_scope, &source)?;
+        app.medialibrary().remove_tags(&uri, &tags)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn list_tags<R: Runtime>(app: AppHandle<R>) -> Result<Vec<TagInfo>> {
+    app.medialibrary().list_tags()
+}
+
+#[command]
+pub(crate) async fn get_xmp_metadata<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    uri: String,
+) -> Result<XmpMetadata> {
+    blocking::run(&app, move |app| {
+        let source = app.medialibrary().get_source_of_uri(&uri)?;
+        ensure_source_allowed(&global_scope, &command_scope, &source)?;
+        app.medialibrary().get_xmp_metadata(&uri)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn set_xmp_metadata<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    request: SetXmpMetadataRequest,
+) -> Result<XmpMetadata> {
+    blocking::run(&app, move |app| {
+        let source = app.medialibrary().get_source_of_uri(&request.uri)?;
+        ensure_source_allowed(&global_scope, &command_scope, &source)?;
+        app.medialibrary().set_xmp_metadata(request)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn find_duplicates<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    request: FindDuplicatesRequest,
+) -> Result<FindDuplicatesResult> {
+    ensure_source_allowed(&global_scope, &command_scope, &request.source)?;
+
+    blocking::run(&app, move |app| app.medialibrary().find_duplicates(request)).await
+}
+
+#[command]
+pub(crate) async fn find_similar<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    uri: String,
+    threshold: Option<u32>,
+) -> Result<Vec<SimilarImage>> {
+    blocking::run(&app, move |app| {
+        let source = app.medialibrary().get_source_of_uri(&uri)?;
+        ensure_source_allowed(&global_scope, &command_scope, &source)?;
+        app.medialibrary().find_similar(&uri, threshold)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn find_similar_groups<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    request: FindSimilarGroupsRequest,
+) -> Result<FindSimilarGroupsResult> {
+    ensure_source_allowed(&global_scope, &command_scope, &request.source)?;
+
+    blocking::run(&app, move |app| {
+        app.medialibrary().find_similar_groups(request)
+    })
+    .await
+}
+
+/// Cancels a running listing, scan or import, returns `false` if there is no job with this id.
+#[command]
+pub(crate) async fn cancel_job(jobs: State<'_, Jobs>, job_id: String) -> Result<bool> {
+    Ok(jobs.cancel(&job_id))
+}
+
+#[command]
+pub(crate) async fn prioritize_thumbnails<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    uris: Vec<String>,
+) -> Result<()> {
+    blocking::run(&app, move |app| {
+        ensure_uris_allowed(&app, &global_scope, &command_scope, &uris)?;
+        app.medialibrary().prioritize_thumbnails(uris)
+    })
+    .await
+}
+
+#[command]
+pub(crate) async fn get_cache_stats<R: Runtime>(app: AppHandle<R>) -> Result<CacheStats> {
+    blocking::run(&app, move |app| app.medialibrary().get_cache_stats()).await
+}
+
+#[command]
+pub(crate) async fn purge_thumbnail_cache<R: Runtime>(app: AppHandle<R>) -> Result<()> {
+    blocking::run(&app, move |app| app.medialibrary().purge_thumbnail_cache()).await
+}
+
+#[command]
+pub(crate) async fn invalidate_thumbnails<R: Runtime>(
+    app: AppHandle<R>,
+    global_scope: GlobalScope<Entry>,
+    command_scope: CommandScope<Entry>,
+    uris: Vec<String>,
+) -> Result<()> {
+    blocking::run(&app, move |app| {
+        ensure_uris_allowed(&app, &global_scope, &command_scope, &uris)?;
+        app.medialibrary().invalidate_thumbnails(uris)
+    })
+    .await
 }
@@ -0,0 +1,102 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::MediaLibrarySource;
+
+/// Configuration of the plugin, read from `plugins.medialibrary` in `tauri.conf.json`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    /// Directories of the sources, replacing the platform directories (desktop only)
+    pub roots: HashMap<MediaLibrarySource, PathBuf>,
+    /// Lowercase file extensions (without the dot) which are listed, all supported images if not set
+    pub allowed_extensions: Option<Vec<String>>,
+    /// List files and folders whose name starts with a dot
+    pub include_hidden: bool,
+    pub follow_symlinks: bool,
+    /// Maximum depth of folders below the root of a source, unlimited if not set
+    pub max_depth: Option<usize>,
+    /// Size of the longer side of generated thumbnails in pixels
+    pub thumbnail_size: u32,
+    /// JPEG quality (1 - 100) of generated thumbnails
+    pub thumbnail_quality: u8,
+    /// Directory for renditions, defaults to `medialibrary` in the app cache directory
+    pub cache_dir: Option<PathBuf>,
+    /// Persist content and perceptual hashes, otherwise they are kept until the app exits
+    pub index: bool,
+    /// Maximum size in bytes of the thumbnail cache on disk, 0 disables the disk cache
+    pub thumbnail_cache_size: u64,
+    /// Maximum size in bytes of the rendition cache on disk, 0 disables it
+    pub rendition_cache_size: u64,
+    /// Maximum size in bytes of the thumbnails kept in memory, 0 disables the memory cache
+    pub thumbnail_memory_cache_size: usize,
+    /// Create the thumbnails of a source in the background once it was listed
+    pub pregenerate_thumbnails: bool,
+    /// Maximum number of directory walks, thumbnail generations and file reads running at the
+    /// same time, defaults to the number of CPUs
+    pub max_blocking_tasks: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            roots: HashMap::new(),
+            allowed_extensions: None,
+            include_hidden: false,
+            follow_symlinks: false,
+            max_depth: None,
+            // the freedesktop "large" size
+            thumbnail_size: 256,
+            thumbnail_quality: 85,
+            cache_dir: None,
+            index: true,
+            thumbnail_cache_size: 512 * 1024 * 1024,
+            rendition_cache_size: 1024 * 1024 * 1024,
+            thumbnail_memory_cache_size: 64 * 1024 * 1024,
+            pregenerate_thumbnails: true,
+            max_blocking_tasks: std::thread::available_parallelism().map_or(4, |n| n.get()),
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn is_extension_allowed(&self, path: &std::path::Path) -> bool {
+        let Some(allowed) = &self.allowed_extensions else {
+            return true;
+        };
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        allowed.iter().any(|allowed| {
+            allowed
+                .trim_start_matches('.')
+                .eq_ignore_ascii_case(&extension)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_deserialize() {
+        let config: Config = serde_json::from_str(
+            r#"{ "allowedExtensions": ["jpg", ".PNG"], "maxDepth": 2, "thumbnailSize": 512 }"#,
+        )
+        .unwrap();
+        assert_eq!(config.max_depth, Some(2));
+        assert_eq!(config.thumbnail_size, 512);
+        assert_eq!(config.thumbnail_quality, 85);
+        assert!(config.index);
+
+        assert!(config.is_extension_allowed(Path::new("a.JPG")));
+        assert!(config.is_extension_allowed(Path::new("a.png")));
+        assert!(!config.is_extension_allowed(Path::new("a.gif")));
+        assert!(!config.is_extension_allowed(Path::new("a")));
+        assert!(Config::default().is_extension_allowed(Path::new("a")));
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    fs::{File, Metadata},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{json_file, similarity::PerceptualHash};
+
+const INDEX_FILE_NAME: &str = "hash-index.json";
+
+/// Returns the hex encoded BLAKE3 hash of a file's content.
+pub(crate) fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexEntry {
+    size: u64,
+    /// Modification time in nanoseconds since the unix epoch
+    modified: u128,
+    #[serde(default)]
+    hash: Option<String>,
+    #[serde(default)]
+    perceptual_hash: Option<PerceptualHash>,
+}
+
+fn modified_nanos(metadata: &Metadata) -> u128 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default()
+}
+
+/// Caches content and perceptual hashes keyed by path, an entry is only used while size and
+/// modification time of the file are unchanged.
+pub(crate) struct HashIndex {
+    file: Option<PathBuf>,
+    entries: Mutex<HashMap<String, IndexEntry>>,
+}
+
+impl HashIndex {
+    /// Loads the index from `dir`, an index without a directory is kept in memory only.
+    pub fn load(dir: Option<&Path>) -> Self {
+        let file = dir.map(|dir| dir.join(INDEX_FILE_NAME));
+        let entries = file.as_deref().map(json_file::load).unwrap_or_default();
+
+        Self {
+            file,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn save(&self) -> crate::Result<()> {
+        match &self.file {
+            Some(file) => json_file::save(file, &*self.entries.lock().unwrap()),
+            None => Ok(()),
+        }
+    }
+
+    /// Saves the index, failures are only logged since the hashes can be computed again.
+    pub fn save_or_log(&self) {
+        if let Err(err) = self.save() {
+            log::warn!("unable to save the hash index: {err}");
+        }
+    }
+
+    /// Returns a cached value of the file or computes (and caches) it if the entry is stale.
+    fn cached<T, G, S, C>(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        get: G,
+        set: S,
+        compute: C,
+    ) -> io::Result<T>
+    where
+        G: FnOnce(&IndexEntry) -> Option<T>,
+        S: FnOnce(&mut IndexEntry, T),
+        C: FnOnce() -> io::Result<T>,
+        T: Clone,
+    {
+        let key = path.to_string_lossy().to_string();
+        let size = metadata.len();
+        let modified = modified_nanos(metadata);
+        let is_current = |entry: &IndexEntry| entry.size == size && entry.modified == modified;
+
+        if let Some(value) = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|entry| is_current(entry))
+            .and_then(get)
+        {
+            return Ok(value);
+        }
+
+        // the lock is not held while computing, so scans of different sources do not block
+        let value = compute()?;
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key).or_default();
+        if !is_current(entry) {
+            *entry = IndexEntry {
+                size,
+                modified,
+                ..Default::default()
+            };
+        }
+        set(entry, value.clone());
+        Ok(value)
+    }
+
+    /// Returns the content hash of a file, the file is only read if the cached hash is stale.
+    pub fn hash(&self, path: &Path, metadata: &Metadata) -> io::Result<String> {
+        self.cached(
+            path,
+            metadata,
+            |entry| entry.hash.clone(),
+            |entry, hash| entry.hash = Some(hash),
+            || hash_file(path),
+        )
+    }
+
+    /// Returns the perceptual hash of an image, it is only computed if the cached one is stale.
+    pub fn perceptual_hash<C>(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        compute: C,
+    ) -> io::Result<PerceptualHash>
+    where
+        C: FnOnce() -> io::Result<PerceptualHash>,
+    {
+        self.cached(
+            path,
+            metadata,
+            |entry| entry.perceptual_hash,
+            |entry, hash| entry.perceptual_hash = Some(hash),
+            compute,
+        )
+    }
+
+    /// Removes the entries below `root` which are not contained in `existing`.
+    pub fn prune(&self, root: &Path, existing: &[PathBuf]) {
+        let existing: std::collections::HashSet<String> = existing
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|key, _| !Path::new(key).starts_with(root) || existing.contains(key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_hash_index_detects_changes() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let file = dir.join("a.jpg");
+        fs::write(&file, b"first").unwrap();
+
+        let index = HashIndex::load(Some(dir));
+        let first = index.hash(&file, &fs::metadata(&file).unwrap()).unwrap();
+        assert_eq!(first, hash_file(&file).unwrap());
+        index.save().unwrap();
+
+        let index = HashIndex::load(Some(dir));
+        fs::write(&file, b"second").unwrap();
+        let second = index.hash(&file, &fs::metadata(&file).unwrap()).unwrap();
+        assert_ne!(first, second);
+
+        index.prune(dir, &[]);
+        assert!(index.entries.lock().unwrap().is_empty());
+    }
+}
@@ -1,134 +1,877 @@
-use std::io::Read;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use serde::de::DeserializeOwned;
-use tauri::{plugin::PluginApi, AppHandle, Runtime};
+use tauri::{
+    plugin::{PermissionState, PluginApi},
+    AppHandle, Emitter, Runtime,
+};
 
-use crate::directory_reader::DirectoryReader;
-use crate::thumbnail_provider::ThumbnailProvider;
+use crate::access;
+use crate::directory_reader::{DirectoryReader, FileFilter};
+use crate::image_transform::RenditionCache;
+#[cfg(feature = "portal")]
+use crate::portal::{GrantedRoots, Portal};
+use crate::thumbnail_cache::ThumbnailCache;
+use crate::thumbnail_memory_cache::{ThumbnailKey, ThumbnailMemoryCache};
+use crate::thumbnail_provider::ThumbnailProviders;
+use crate::thumbnail_queue::ThumbnailQueue;
 #[cfg(not(feature = "xdg"))]
 use crate::walkdir_reader::WalkdirReader;
 
-#[cfg(all(not(feature = "thumb_cache"), not(feature = "amt")))]
-use crate::thumbnail_provider::EmptyThumbnailProvider;
-
-#[cfg(feature = "thumb_cache")]
-use crate::thumbcache_thumbnail_provider::ThumbCacheThumbnailProvider;
 #[cfg(feature = "xdg")]
 use crate::xdg_directory_reader::XdgDirectoryReader;
 
-#[cfg(feature = "amt")]
-use crate::amt_thumbnail_provider::AmtThumbnailProvider;
+use base64::{engine::general_purpose, Engine};
 
-use crate::{models::*, uri::uri_to_path};
+use crate::content_hash::HashIndex;
+use crate::duplicates::{self, DUPLICATES_FINISHED_EVENT, DUPLICATES_PROGRESS_EVENT};
+use crate::file_operations::{
+    is_within, resolve_album_path, transfer_image, validate_file_name, write_new_file, TransferMode,
+};
+use crate::import::{self, ImportOptions, IMPORT_FINISHED_EVENT, IMPORT_PROGRESS_EVENT};
+use crate::jobs::{Job, Jobs, JOB_PROGRESS_EVENT};
+use crate::similarity::{
+    self, PerceptualHash, SIMILARITY_FINISHED_EVENT, SIMILARITY_PROGRESS_EVENT,
+};
+use crate::user_data_store::{media_identity, media_key, MediaKey, UserDataStore};
+use crate::walkdir_reader::{is_image_file, read_image_info};
+use crate::MedialibraryExt;
+use crate::{models::*, uri::uri_to_path, xmp, Config, Error};
 
 pub fn init<R: Runtime, C: DeserializeOwned>(
     app: &AppHandle<R>,
     _api: PluginApi<R, C>,
+    config: &Config,
+    extensions: Extensions,
 ) -> crate::Result<Medialibrary<R>> {
-    Ok(Medialibrary(app.clone()))
+    let data_dir = crate::plugin_data_dir(app);
+    let index_dir = data_dir.as_deref().filter(|_| config.index);
+    let cache_dir = crate::plugin_cache_dir(app, config);
+    let thumbnail_dir = cache_dir.as_ref().map(|dir| dir.join("thumbnails"));
+
+    let worker_app = app.clone();
+    let thumbnail_queue = ThumbnailQueue::start(
+        config.clone(),
+        extensions.file_filter.clone(),
+        move |path| {
+            use tauri::Manager;
+            if let Some(medialibrary) = worker_app.try_state::<Medialibrary<R>>() {
+                medialibrary.pregenerate_thumbnail(path);
+            }
+        },
+    );
+
+    let store = UserDataStore::load(data_dir.as_deref());
+    if let Err(err) = store.reconcile() {
+        log::warn!("failed to reconcile the user data: {err}");
+    }
+
+    Ok(Medialibrary {
+        app: app.clone(),
+        store,
+        #[cfg(feature = "portal")]
+        granted_roots: GrantedRoots::load(data_dir.as_deref()),
+        hash_index: HashIndex::load(index_dir),
+        thumbnails: extensions.thumbnails,
+        thumbnail_cache: ThumbnailCache::new(
+            thumbnail_dir.as_deref(),
+            config.thumbnail_size,
+            config.thumbnail_cache_size,
+        ),
+        memory_cache: ThumbnailMemoryCache::new(config.thumbnail_memory_cache_size),
+        rendition_cache: cache_dir
+            .filter(|_| config.rendition_cache_size > 0)
+            .map(|dir| RenditionCache::new(&dir.join("renditions"), config.rendition_cache_size)),
+        thumbnail_queue,
+        directory_readers: extensions.directory_readers,
+        file_filter: extensions.file_filter,
+    })
+}
+
+/// Extensions registered with the plugin builder.
+pub(crate) struct Extensions {
+    pub thumbnails: ThumbnailProviders,
+    pub directory_readers: HashMap<MediaLibrarySource, Box<dyn DirectoryReader + Send + Sync>>,
+    pub file_filter: Option<FileFilter>,
 }
 
 /// Access to the medialibrary APIs.
-pub struct Medialibrary<R: Runtime>(AppHandle<R>);
+pub struct Medialibrary<R: Runtime> {
+    app: AppHandle<R>,
+    store: UserDataStore,
+    /// Folders granted through the xdg-desktop-portal
+    #[cfg(feature = "portal")]
+    granted_roots: GrantedRoots,
+    hash_index: HashIndex,
+    thumbnails: ThumbnailProviders,
+    thumbnail_cache: ThumbnailCache,
+    memory_cache: ThumbnailMemoryCache,
+    /// Transformed images served by the `image://` protocol
+    rendition_cache: Option<RenditionCache>,
+    thumbnail_queue: ThumbnailQueue,
+    directory_readers: HashMap<MediaLibrarySource, Box<dyn DirectoryReader + Send + Sync>>,
+    file_filter: Option<FileFilter>,
+}
 
 impl<R: Runtime> Medialibrary<R> {
     pub fn get_images(&self, request: GetLibraryContentRequest) -> crate::Result<GetImagesResult> {
+        let include_xmp_metadata = request.include_xmp_metadata.unwrap_or_default();
+        let include_exif_metadata = request.include_exif_metadata.unwrap_or_default();
+
+        let result = match self.directory_readers.get(&request.source) {
+            Some(reader) => reader.read_directory(&request),
+            None => self
+                .with_builtin_reader(&request, |reader| reader.read_directory(&request))
+                .inspect(|_| self.pregenerate_thumbnails(&request.source)),
+        };
+        result
+            .map(|result| self.with_user_data(result))
+            .map(|result| result.with_xmp_metadata(include_xmp_metadata))
+            .map(|result| result.with_exif_metadata(include_exif_metadata))
+    }
+
+    /// Lists a source like `get_images`, but passes the images to `on_batch` as they are found.
+    /// Returns the number of images.
+    pub fn stream_images<F>(
+        &self,
+        request: GetLibraryContentRequest,
+        batch_size: usize,
+        mut on_batch: F,
+    ) -> crate::Result<usize>
+    where
+        F: FnMut(GetImagesResult) -> crate::Result<()>,
+    {
+        let include_xmp_metadata = request.include_xmp_metadata.unwrap_or_default();
+        let include_exif_metadata = request.include_exif_metadata.unwrap_or_default();
+
+        let mut on_batch = |items| {
+            let result = self
+                .with_user_data(GetImagesResult { items })
+                .with_xmp_metadata(include_xmp_metadata)
+                .with_exif_metadata(include_exif_metadata);
+            on_batch(result)
+        };
+        match self.directory_readers.get(&request.source) {
+            Some(reader) => reader.stream_directory(&request, batch_size, &mut on_batch),
+            None => self
+                .with_builtin_reader(&request, |reader| {
+                    reader.stream_directory(&request, batch_size, &mut on_batch)
+                })
+                .inspect(|_| self.pregenerate_thumbnails(&request.source)),
+        }
+    }
+
+    /// Runs `read` with the built-in reader of the source.
+    fn with_builtin_reader<T>(
+        &self,
+        request: &GetLibraryContentRequest,
+        read: impl FnOnce(&dyn DirectoryReader) -> crate::Result<T>,
+    ) -> crate::Result<T> {
+        let path = self.get_source_path(&request.source)?;
+        // the walk would silently find nothing in a folder the sandbox hides
+        if access::detect_sandbox().is_some() && std::fs::read_dir(&path).is_err() {
+            return Err(Error::AccessNotGranted(request.source.clone()));
+        }
+
+        let filter = self.file_filter(request);
+        let config = self.config();
+        let job = self.start_job(request.job_id.clone(), JobKind::Listing)?;
+
         #[cfg(feature = "xdg")]
         {
-            let reader = XdgDirectoryReader;
-            return reader.read_directory(&request);
+            let mut config = config.inner().clone();
+            config.roots.insert(request.source.clone(), path);
+            return read(
+                &XdgDirectoryReader::default()
+                    .with_filter(filter)
+                    .with_config(config)
+                    .with_job(job),
+            );
         }
+
         #[cfg(not(feature = "xdg"))]
         {
-            use tauri::Manager;
-            match self.0.path().picture_dir() {
-                Ok(path) => {
-                    let reader = WalkdirReader::new(&path);
-                    reader.read_directory(&request)
+            read(
+                &WalkdirReader::new(&path)
+                    .with_filter(filter)
+                    .with_config(&config)
+                    .with_job(job),
+            )
+        }
+    }
+
+    pub fn get_image(&self, request: GetImageRequest) -> crate::Result<Option<ImageInfo>> {
+        Ok(read_image_info(&uri_to_path(&request.uri)?).map(|info| self.with_item_user_data(info)))
+    }
+
+    pub fn set_favorite(&self, uri: &str, favorite: bool) -> crate::Result<()> {
+        self.store
+            .set_favorite(self.get_media_key(uri)?, favorite)
+            .map(|_| ())
+    }
+
+    pub fn add_tags(&self, uri: &str, tags: &[String]) -> crate::Result<Vec<String>> {
+        let data = self.store.add_tags(self.get_media_key(uri)?, tags)?;
+        Ok(data.tags.into_iter().collect())
+    }
+
+    pub fn remove_tags(&self, uri: &str, tags: &[String]) -> crate::Result<Vec<String>> {
+        let data = self.store.remove_tags(self.get_media_key(uri)?, tags)?;
+        Ok(data.tags.into_iter().collect())
+    }
+
+    pub fn list_tags(&self) -> crate::Result<Vec<TagInfo>> {
+        Ok(self.store.list_tags())
+    }
+
+    pub fn get_xmp_metadata(&self, uri: &str) -> crate::Result<XmpMetadata> {
+        let path = uri_to_path(uri)?;
+        if !path.is_file() {
+            return Err(Error::GetDataError(uri.to_string()));
+        }
+        Ok(xmp::read(&path))
+    }
+
+    pub fn set_xmp_metadata(&self, request: SetXmpMetadataRequest) -> crate::Result<XmpMetadata> {
+        let path = uri_to_path(&request.uri)?;
+        if !path.is_file() {
+            return Err(Error::GetDataError(request.uri));
+        }
+        xmp::write(&path, &request)
+    }
+
+    fn get_media_key(&self, uri: &str) -> crate::Result<MediaKey> {
+        let path = uri_to_path(uri)?;
+        let metadata = std::fs::metadata(&path)?;
+        Ok(media_key(&path, &metadata))
+    }
+
+    fn user_data_filter(&self, request: &GetLibraryContentRequest) -> Option<FileFilter> {
+        if !request.has_user_data_filter() {
+            return None;
+        }
+
+        let identities = self.store.find(
+            request.favorites_only.unwrap_or_default(),
+            request.tags.as_deref().unwrap_or_default(),
+        );
+        Some(Arc::new(move |path, _metadata| {
+            identities.contains(&media_identity(path))
+        }))
+    }
+
+    /// Combines the filter registered with the plugin builder with the user data filter.
+    fn file_filter(&self, request: &GetLibraryContentRequest) -> Option<FileFilter> {
+        match (self.file_filter.clone(), self.user_data_filter(request)) {
+            (Some(hook), Some(user_data)) => Some(Arc::new(move |path, metadata| {
+                hook(path, metadata) && user_data(path, metadata)
+            })),
+            (hook, user_data) => hook.or(user_data),
+        }
+    }
+
+    fn with_user_data(&self, result: GetImagesResult) -> GetImagesResult {
+        GetImagesResult {
+            items: result
+                .items
+                .into_iter()
+                .map(|item| self.with_item_user_data(item))
+                .collect(),
+        }
+    }
+
+    fn with_item_user_data(&self, item: ImageInfo) -> ImageInfo {
+        match self.store.get(&media_identity(Path::new(&item.path))) {
+            Some(data) => ImageInfo {
+                favorite: data.favorite,
+                tags: data.tags.into_iter().collect(),
+                ..item
+            },
+            None => item,
+        }
+    }
+
+    /// Returns the root directory of a media library source.
+    pub fn get_source_path(&self, source: &MediaLibrarySource) -> crate::Result<PathBuf> {
+        if let Some(root) = self.config().roots.get(source) {
+            return Ok(root.clone());
+        }
+        #[cfg(feature = "portal")]
+        if let Some(root) = self.granted_roots.path(source) {
+            return Ok(root);
+        }
+
+        match source {
+            MediaLibrarySource::PictureDir => {
+                #[cfg(feature = "xdg")]
+                return crate::xdg_directory_reader::pictures_dir()
+                    .ok_or_else(|| Error::MediaLibrarySourceForbidden(source.clone()));
+
+                #[cfg(not(feature = "xdg"))]
+                {
+                    use tauri::Manager;
+                    self.app.path().picture_dir().map_err(Into::into)
                 }
-                Err(e) => Err(e.into()),
             }
         }
     }
 
-    pub fn get_image(&self, _request: GetImageRequest) -> crate::Result<Option<ImageInfo>> {
-        unimplemented!()
+    /// Returns the media library source the given uri belongs to.
+    pub fn get_source_of_uri(&self, uri: &str) -> crate::Result<MediaLibrarySource> {
+        let path = uri_to_path(uri)?;
+        [MediaLibrarySource::PictureDir]
+            .into_iter()
+            .find(|source| {
+                self.get_source_path(source)
+                    .map(|root| is_within(&root, &path))
+                    .unwrap_or(false)
+            })
+            .ok_or_else(|| Error::PathNotInMediaLibrary(path.to_string_lossy().to_string()))
     }
 
-    pub fn delete_image(&self, request: DeleteImageRequest) -> crate::Result<()> {
-        match uri_to_path(&request.uri) {
-            Ok(path) => match std::fs::remove_file(&path) {
-                Ok(_) => Ok(()),
-                Err(err) => Err(err.into()),
+    pub fn move_image(&self, request: TransferImageRequest) -> crate::Result<ImageInfo> {
+        self.transfer_image(request, TransferMode::Move)
+    }
+
+    pub fn copy_image(&self, request: TransferImageRequest) -> crate::Result<ImageInfo> {
+        self.transfer_image(request, TransferMode::Copy)
+    }
+
+    pub fn rename_image(&self, request: RenameImageRequest) -> crate::Result<ImageInfo> {
+        let path = uri_to_path(&request.uri)?;
+        let target_dir = path
+            .parent()
+            .ok_or_else(|| Error::InvalidFileName(request.uri.clone()))?;
+        let file_name = validate_image_file_name(&request.file_name)?;
+        let identity = media_identity(&path);
+
+        let target = transfer_image(
+            &path,
+            target_dir,
+            file_name,
+            request.collision_policy.unwrap_or_default(),
+            TransferMode::Move,
+        )?;
+        self.moved(&identity, &target)
+    }
+
+    /// Keeps the user data attached to an image after it has been moved.
+    fn moved(&self, identity: &str, target: &Path) -> crate::Result<ImageInfo> {
+        if let Ok(metadata) = std::fs::metadata(target) {
+            self.store.rekey(identity, media_key(target, &metadata))?;
+        }
+        self.get_image_info(target)
+    }
+
+    pub fn save_image(&self, request: SaveImageRequest) -> crate::Result<ImageInfo> {
+        let target_dir = self.create_album_dir(&request.source, request.album.as_deref())?;
+        let file_name = validate_image_file_name(&request.file_name)?;
+        let policy = request.collision_policy.unwrap_or_default();
+
+        let target = match (&request.content, &request.path) {
+            (Some(content), _) => {
+                let content = general_purpose::STANDARD.decode(content)?;
+                write_new_file(&target_dir, file_name, policy, |file| {
+                    file.write_all(&content)
+                })?
+            }
+            (None, Some(path)) => {
+                let mut source = std::fs::File::open(path)?;
+                write_new_file(&target_dir, file_name, policy, |file| {
+                    std::io::copy(&mut source, file).map(|_| ())
+                })?
+            }
+            (None, None) => return Err(Error::MissingImageContent),
+        };
+        self.get_image_info(&target)
+    }
+
+    /// Imports a folder into a source, emits `medialibrary://import-progress` events while
+    /// running and a `medialibrary://import-finished` event with the report at the end.
+    pub fn import_folder(&self, request: ImportRequest) -> crate::Result<ImportReport> {
+        let library_root = self.get_source_path(&request.source)?;
+        // the library files would be excluded from the duplicate check and imported again
+        let folder = std::fs::canonicalize(&request.folder)?;
+        if let Ok(root) = std::fs::canonicalize(&library_root) {
+            if folder.starts_with(&root) || root.starts_with(&folder) {
+                return Err(Error::ImportFolderOverlapsLibrary(request.folder));
+            }
+        }
+        let target_dir = self.create_album_dir(&request.source, request.album.as_deref())?;
+        let mode = match request.mode.unwrap_or_default() {
+            ImportMode::Copy => TransferMode::Copy,
+            ImportMode::Move => TransferMode::Move,
+        };
+
+        let job = self.start_job(request.job_id.clone(), JobKind::Import)?;
+        let config = self.config();
+        let options = ImportOptions {
+            job: &job,
+            config: &config,
+            index: &self.hash_index,
+            target_dir: &target_dir,
+            folder_template: import::folder_template(request.folder_template.as_deref()),
+            mode,
+            collision_policy: request
+                .collision_policy
+                .unwrap_or(CollisionPolicy::KeepBoth),
+        };
+
+        let report = import::import_folder(&folder, &library_root, options, |progress| {
+            if let Err(err) = self.app.emit(IMPORT_PROGRESS_EVENT, progress) {
+                log::warn!("unable to emit import progress: {err}");
+            }
+        })?;
+
+        self.app.emit(IMPORT_FINISHED_EVENT, &report)?;
+        Ok(report)
+    }
+
+    /// Searches a source for images with identical content, emits
+    /// `medialibrary://duplicates-progress` events while hashing and a
+    /// `medialibrary://duplicates-finished` event with the result at the end.
+    pub fn find_duplicates(
+        &self,
+        request: FindDuplicatesRequest,
+    ) -> crate::Result<FindDuplicatesResult> {
+        let root = self.get_source_path(&request.source)?;
+        let job = self.start_job(request.job_id, JobKind::Hashing)?;
+        let result = duplicates::find_duplicates(
+            &root,
+            &self.config(),
+            self.file_filter.as_ref(),
+            &self.hash_index,
+            &job,
+            |progress| {
+                if let Err(err) = self.app.emit(DUPLICATES_PROGRESS_EVENT, progress) {
+                    log::warn!("unable to emit duplicates progress: {err}");
+                }
             },
-            Err(err) => Err(err),
+        )?;
+        let result = FindDuplicatesResult {
+            groups: result
+                .groups
+                .into_iter()
+                .map(|group| DuplicateGroup {
+                    items: group
+                        .items
+                        .into_iter()
+                        .map(|item| self.with_item_user_data(item))
+                        .collect(),
+                    ..group
+                })
+                .collect(),
+            ..result
+        };
+
+        self.app.emit(DUPLICATES_FINISHED_EVENT, &result)?;
+        Ok(result)
+    }
+
+    /// Returns the images of the same source which look similar to the given one, the most
+    /// similar first. Emits `medialibrary://similarity-progress` events while hashing.
+    pub fn find_similar(
+        &self,
+        uri: &str,
+        threshold: Option<u32>,
+    ) -> crate::Result<Vec<SimilarImage>> {
+        let path = uri_to_path(uri)?;
+        let root = self.get_source_path(&self.get_source_of_uri(uri)?)?;
+        let target = similarity::hash_image(&path, &self.hash_index, &self.analysis_thumbnail())?;
+
+        let hashes = self.perceptual_hashes(&root, None)?;
+        let threshold = threshold.unwrap_or(similarity::DEFAULT_THRESHOLD);
+        Ok(similarity::find_similar(&target, &hashes, threshold)
+            .into_iter()
+            .filter(|(similar, _)| similar != &path)
+            .filter_map(|(similar, distance)| {
+                Some(SimilarImage {
+                    distance,
+                    image: self.with_item_user_data(read_image_info(&similar)?),
+                })
+            })
+            .collect())
+    }
+
+    /// Groups the visually similar images of a source, emits `medialibrary://similarity-progress`
+    /// events while hashing and a `medialibrary://similarity-finished` event at the end.
+    pub fn find_similar_groups(
+        &self,
+        request: FindSimilarGroupsRequest,
+    ) -> crate::Result<FindSimilarGroupsResult> {
+        let root = self.get_source_path(&request.source)?;
+        let hashes = self.perceptual_hashes(&root, request.job_id.clone())?;
+        let threshold = request.threshold.unwrap_or(similarity::DEFAULT_THRESHOLD);
+
+        let groups = similarity::cluster(&hashes, threshold)
+            .into_iter()
+            .map(|paths| SimilarGroup {
+                items: paths
+                    .iter()
+                    .filter_map(|path| read_image_info(path))
+                    .map(|item| self.with_item_user_data(item))
+                    .collect(),
+            })
+            .filter(|group| group.items.len() > 1)
+            .collect();
+
+        let result = FindSimilarGroupsResult {
+            job_id: request.job_id,
+            groups,
+        };
+        self.app.emit(SIMILARITY_FINISHED_EVENT, &result)?;
+        Ok(result)
+    }
+
+    fn perceptual_hashes(
+        &self,
+        root: &Path,
+        job_id: Option<String>,
+    ) -> crate::Result<Vec<(PathBuf, PerceptualHash)>> {
+        let job = self.start_job(job_id, JobKind::Indexing)?;
+        similarity::hash_images(
+            root,
+            &self.config(),
+            self.file_filter.as_ref(),
+            &self.hash_index,
+            &self.analysis_thumbnail(),
+            &job,
+            |progress| {
+                if let Err(err) = self.app.emit(SIMILARITY_PROGRESS_EVENT, progress) {
+                    log::warn!("unable to emit similarity progress: {err}");
+                }
+            },
+        )
+    }
+
+    /// Creates (if necessary) and returns the directory of an album within a source.
+    fn create_album_dir(
+        &self,
+        source: &MediaLibrarySource,
+        album: Option<&str>,
+    ) -> crate::Result<PathBuf> {
+        let root = self.get_source_path(source)?;
+        let album_dir = resolve_album_path(&root, album)?;
+        std::fs::create_dir_all(&album_dir)?;
+
+        // the album may be a symlink pointing somewhere outside of the library
+        if is_within(&root, &album_dir) {
+            Ok(album_dir)
+        } else {
+            Err(Error::PathNotInMediaLibrary(
+                album_dir.to_string_lossy().to_string(),
+            ))
         }
     }
 
+    fn transfer_image(
+        &self,
+        request: TransferImageRequest,
+        mode: TransferMode,
+    ) -> crate::Result<ImageInfo> {
+        let path = uri_to_path(&request.uri)?;
+        // checked before the transfer, a moved file could not be reported afterwards
+        if crate::media_type::detect(&path, crate::ContentSniffing::default()).is_none() {
+            return Err(Error::PathNotInMediaLibrary(
+                path.to_string_lossy().to_string(),
+            ));
+        }
+        let target_dir = self.create_album_dir(&request.source, request.album.as_deref())?;
+
+        let file_name = match &request.file_name {
+            Some(file_name) => validate_image_file_name(file_name)?,
+            None => path
+                .file_name()
+                .ok_or_else(|| Error::InvalidFileName(request.uri.clone()))?,
+        };
+
+        let identity = media_identity(&path);
+        let target = transfer_image(
+            &path,
+            &target_dir,
+            file_name,
+            request.collision_policy.unwrap_or_default(),
+            mode,
+        )?;
+
+        match mode {
+            TransferMode::Move => self.moved(&identity, &target),
+            TransferMode::Copy => self.get_image_info(&target),
+        }
+    }
+
+    fn get_image_info(&self, path: &Path) -> crate::Result<ImageInfo> {
+        read_image_info(path)
+            .map(|info| self.with_item_user_data(info))
+            .ok_or_else(|| Error::PathNotInMediaLibrary(path.to_string_lossy().to_string()))
+    }
+
+    pub fn delete_image(&self, request: DeleteImageRequest) -> crate::Result<()> {
+        let path = uri_to_path(&request.uri)?;
+        let identity = media_identity(&path);
+        std::fs::remove_file(&path)?;
+        self.store.remove(&identity)
+    }
+
+    /// Checks the access to the root directory of every source.
     pub fn check_permissions(&self) -> crate::Result<PermissionResponse> {
-        Ok(PermissionResponse::granted())
+        let sandboxed = access::detect_sandbox().is_some();
+        let sources = [MediaLibrarySource::PictureDir]
+            .into_iter()
+            .map(|source| {
+                let permission = match self.get_source_path(&source) {
+                    Ok(root) => access::source_permission(&root, sandboxed),
+                    Err(_) => SourcePermission {
+                        read: PermissionState::Denied,
+                        write: PermissionState::Denied,
+                    },
+                };
+                (source, permission)
+            })
+            .collect();
+        Ok(PermissionResponse { sources, sandboxed })
     }
 
-    pub fn request_permissions(
+    /// In a Flatpak or Snap sandbox the user is asked to choose the folder of an inaccessible
+    /// source through the xdg-desktop-portal, it is the root of the source from then on.
+    /// Otherwise the current access is returned.
+    pub async fn request_permissions(
         &self,
-        _args: RequestPermissionsArgs,
+        args: RequestPermissionsArgs,
     ) -> crate::Result<PermissionResponse> {
-        Ok(PermissionResponse::granted())
+        #[cfg(feature = "portal")]
+        if matches!(
+            access::detect_sandbox(),
+            Some(access::Sandbox::Flatpak | access::Sandbox::Snap)
+        ) && !self.is_source_readable(&args.source)
+        {
+            self.grant_root_through_portal(args.source).await?;
+        }
+        #[cfg(not(feature = "portal"))]
+        let _ = args;
+
+        crate::blocking::run(&self.app, |app| app.medialibrary().check_permissions()).await
     }
 
-    pub async fn get_thumbnail_data(&self, uri: String) -> crate::Result<Thumbnail> {
-        match uri_to_path(&uri) {
-            Ok(path) => {
-                #[cfg(feature = "amt")]
-                return AmtThumbnailProvider::get_thumbnail(&path);
+    #[cfg(feature = "portal")]
+    fn is_source_readable(&self, source: &MediaLibrarySource) -> bool {
+        self.get_source_path(source)
+            .is_ok_and(|root| std::fs::read_dir(root).is_ok())
+    }
 
-                #[cfg(feature = "thumb_cache")]
-                return ThumbCacheThumbnailProvider::get_thumbnail(&path);
+    /// Uses the folder granted before if the document portal still exports it, possibly below a
+    /// new mount point, otherwise opens the file chooser. Cancelling the chooser leaves the
+    /// source inaccessible.
+    #[cfg(feature = "portal")]
+    async fn grant_root_through_portal(&self, source: MediaLibrarySource) -> crate::Result<()> {
+        let granted = self.granted_roots.get(&source);
+        if granted.as_ref().is_some_and(|root| root.is_accessible()) {
+            return Ok(());
+        }
 
-                #[cfg(all(not(feature = "thumb_cache"), not(feature = "amt")))]
-                EmptyThumbnailProvider::get_thumbnail(&path)
+        let portal = Portal::session().await?;
+        if let Some(granted) = granted {
+            if let Some(root) = portal.restore_root(&granted).await? {
+                self.granted_roots.set(source, root)?;
+                return Ok(());
             }
-            Err(err) => Err(err),
         }
+        let title = format!("Choose the folder of {source}");
+        if let Some(path) = portal.choose_folder(&title).await? {
+            let root = portal.granted_root(path).await?;
+            self.granted_roots.set(source, root)?;
+        }
+        Ok(())
     }
 
-    pub async fn get_image_data(&self, uri: String) -> crate::Result<Image> {
-        match uri_to_path(&uri) {
-            Ok(path) => {
-                let mut file = std::fs::File::open(&path)?;
-                let file_len = file.metadata()?.len();
-
-                let mut buf = Vec::with_capacity(file_len as usize);
-                file.read_to_end(&mut buf)?;
+    pub async fn get_thumbnail_data(&self, uri: String) -> crate::Result<Thumbnail> {
+        let path = uri_to_path(&uri)?;
+        let key = {
+            let path = path.clone();
+            let size = self.config().thumbnail_size;
+            crate::blocking::run(&self.app, move |_| Ok(ThumbnailKey::new(&path, size)?)).await
+        };
 
-                Ok(buf.into())
+        let thumbnail = match key {
+            Ok(key) => {
+                let path = path.clone();
+                self.memory_cache
+                    .get_or_generate(key, || {
+                        crate::blocking::run(&self.app, move |app| {
+                            app.medialibrary().cached_thumbnail(&path)
+                        })
+                    })
+                    .await
             }
             Err(err) => Err(err),
+        };
+        // placeholders are not kept in memory, the next request tries to create the thumbnail again
+        match thumbnail {
+            Err(err) => {
+                crate::blocking::run(&self.app, move |app| {
+                    app.medialibrary()
+                        .thumbnails
+                        .get_placeholder(&path)
+                        .ok_or(err)
+                })
+                .await
+            }
+            thumbnail => thumbnail,
+        }
+    }
+
+    /// Creates these thumbnails next in the background, e.g. of the visible images.
+    pub fn prioritize_thumbnails(&self, uris: Vec<String>) -> crate::Result<()> {
+        let paths = uris
+            .iter()
+            .map(|uri| uri_to_path(uri))
+            .collect::<crate::Result<Vec<_>>>()?;
+        if self.thumbnail_cache.is_enabled() {
+            self.thumbnail_queue.prioritize(paths);
+        }
+        Ok(())
+    }
+
+    pub fn get_cache_stats(&self) -> crate::Result<CacheStats> {
+        let (directory, thumbnail_count, thumbnail_bytes) = self.thumbnail_cache.stats();
+        let (memory_count, memory_bytes) = self.memory_cache.stats();
+        let (rendition_count, rendition_bytes) = self
+            .rendition_cache
+            .as_ref()
+            .map_or((0, 0), RenditionCache::stats);
+        Ok(CacheStats {
+            directory: directory.map(|dir| dir.to_string_lossy().to_string()),
+            thumbnail_count,
+            thumbnail_bytes,
+            max_bytes: self.thumbnail_cache.max_bytes(),
+            memory_count,
+            memory_bytes,
+            rendition_count,
+            rendition_bytes,
+            rendition_max_bytes: self.config().rendition_cache_size,
+        })
+    }
+
+    /// Removes all thumbnails from the disk and the memory cache, as well as the renditions.
+    pub fn purge_thumbnail_cache(&self) -> crate::Result<()> {
+        self.thumbnail_cache.purge();
+        self.memory_cache.clear();
+        if let Some(cache) = &self.rendition_cache {
+            cache.purge();
+        }
+        Ok(())
+    }
+
+    /// Removes the cached thumbnails of these images, they are created again on the next request.
+    pub fn invalidate_thumbnails(&self, uris: Vec<String>) -> crate::Result<()> {
+        for uri in uris {
+            let path = uri_to_path(&uri)?;
+            self.thumbnail_cache.invalidate(&path);
+            self.memory_cache.remove_path(&path);
         }
+        Ok(())
+    }
+
+    /// Queues the thumbnails of the source once it was listed with the built-in reader.
+    fn pregenerate_thumbnails(&self, source: &MediaLibrarySource) {
+        if !self.config().pregenerate_thumbnails || !self.thumbnail_cache.is_enabled() {
+            return;
+        }
+        match self.get_source_path(source) {
+            Ok(root) => self.thumbnail_queue.enqueue_root(root),
+            Err(err) => log::warn!("unable to pregenerate the thumbnails of {source}: {err}"),
+        }
+    }
+
+    fn pregenerate_thumbnail(&self, path: &Path) {
+        if self.thumbnail_cache.contains(path) {
+            return;
+        }
+        if let Err(err) = self.cached_thumbnail(path) {
+            log::debug!(
+                "unable to pregenerate the thumbnail of {}: {err}",
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    /// Returns the cached thumbnail or creates and caches it, without placeholders.
+    fn cached_thumbnail(&self, path: &Path) -> crate::Result<Thumbnail> {
+        if let Some(thumbnail) = self.thumbnail_cache.get(path) {
+            return Ok(thumbnail);
+        }
+        let thumbnail = self.thumbnails.get_thumbnail(path, false)?;
+        self.thumbnail_cache.put(path, &thumbnail);
+        Ok(thumbnail)
+    }
+
+    pub async fn get_image_data(&self, uri: String) -> crate::Result<Image> {
+        let path = uri_to_path(&uri)?;
+        crate::blocking::run(&self.app, move |_| {
+            let mut file = std::fs::File::open(&path)?;
+            let file_len = file.metadata()?.len();
+
+            let mut buf = Vec::with_capacity(file_len as usize);
+            file.read_to_end(&mut buf)?;
+
+            Ok(buf.into())
+        })
+        .await
+    }
+
+    /// Disk cache of the renditions, not set if it is disabled.
+    pub(crate) fn rendition_cache(&self) -> Option<&RenditionCache> {
+        self.rendition_cache.as_ref()
+    }
+
+    /// Identifies the current state of an image, used to invalidate cached renditions.
+    pub fn get_image_version(&self, uri: &str) -> Option<String> {
+        let metadata = std::fs::metadata(uri_to_path(uri).ok()?).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+        Some(format!("{}-{}", metadata.len(), modified.as_nanos()))
     }
 
     pub async fn get_thumbnail(&self, uri: String) -> crate::Result<GetThumbnailResponse> {
-        match uri_to_path(&uri) {
-            Ok(path) => {
-                #[cfg(feature = "amt")]
-                {
-                    return Ok(GetThumbnailResponse::from(
-                        AmtThumbnailProvider::get_thumbnail(&path)?,
-                    ));
-                }
-                #[cfg(feature = "thumb_cache")]
-                {
-                    return Ok(GetThumbnailResponse::from(
-                        ThumbCacheThumbnailProvider::get_thumbnail(&path)?,
-                    ));
-                }
+        self.get_thumbnail_data(uri)
+            .await
+            .map(GetThumbnailResponse::from)
+    }
+
+    /// Starts a job which emits `medialibrary://job-progress` events.
+    fn start_job(&self, id: Option<String>, kind: JobKind) -> crate::Result<Job> {
+        use tauri::Manager;
 
-                #[cfg(all(not(feature = "thumb_cache"), not(feature = "amt")))]
-                Ok(GetThumbnailResponse::from(
-                    EmptyThumbnailProvider::get_thumbnail(&path)?,
-                ))
+        let app = self.app.clone();
+        self.app.state::<Jobs>().start(id, kind, move |progress| {
+            if let Err(err) = app.emit(JOB_PROGRESS_EVENT, progress) {
+                log::warn!("unable to emit job progress: {err}");
             }
-            Err(err) => Err(err),
-        }
+        })
+    }
+
+    fn config(&self) -> tauri::State<'_, Config> {
+        use tauri::Manager;
+        self.app.state::<Config>()
+    }
+
+    /// Thumbnails used to analyze images, without placeholders.
+    fn analysis_thumbnail(&self) -> impl Fn(&Path) -> crate::Result<Thumbnail> + '_ {
+        |path| self.cached_thumbnail(path)
+    }
+}
+
+fn validate_image_file_name(file_name: &str) -> crate::Result<&std::ffi::OsStr> {
+    let name = validate_file_name(file_name)?;
+    if is_image_file(Path::new(name)) {
+        Ok(name)
+    } else {
+        Err(Error::InvalidFileName(file_name.to_string()))
     }
 }
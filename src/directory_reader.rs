@@ -1,5 +1,29 @@
-use crate::{Error, GetImagesResult, GetLibraryContentRequest};
+use std::{fs::Metadata, path::Path, sync::Arc};
 
+use crate::{Error, GetImagesResult, GetLibraryContentRequest, ImageInfo};
+
+/// Decides whether a file is part of a directory listing.
+pub type FileFilter = Arc<dyn Fn(&Path, &Metadata) -> bool + Send + Sync>;
+
+/// Lists the images of a source. Custom readers are registered per source with the plugin
+/// [`Builder`](crate::Builder).
 pub trait DirectoryReader {
     fn read_directory(&self, request: &GetLibraryContentRequest) -> Result<GetImagesResult, Error>;
+
+    /// Passes the images of the request to `on_batch` in batches of at most `batch_size` images
+    /// and returns their number. By default the page is read and sent at once.
+    fn stream_directory(
+        &self,
+        request: &GetLibraryContentRequest,
+        batch_size: usize,
+        on_batch: &mut dyn FnMut(Vec<ImageInfo>) -> Result<(), Error>,
+    ) -> Result<usize, Error> {
+        let items = self.read_directory(request)?.items;
+        let total = items.len();
+        let mut items = items.into_iter().peekable();
+        while items.peek().is_some() {
+            on_batch(items.by_ref().take(batch_size.max(1)).collect())?;
+        }
+        Ok(total)
+    }
 }
@@ -0,0 +1,154 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::warn;
+
+use crate::{
+    content_hash::HashIndex, directory_reader::FileFilter, import::image_files, jobs::Job,
+    walkdir_reader::read_image_info, Config, DuplicateGroup, FindDuplicatesResult, ScanProgress,
+};
+
+pub(crate) const DUPLICATES_PROGRESS_EVENT: &str = "medialibrary://duplicates-progress";
+pub(crate) const DUPLICATES_FINISHED_EVENT: &str = "medialibrary://duplicates-finished";
+
+/// Orders the files of a group so the one we suggest to keep comes first:
+/// the oldest file, for equally old files the one with the shortest path.
+fn keeper_order(paths: &mut [PathBuf]) {
+    let modified = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    };
+    paths.sort_by_cached_key(|path| {
+        (
+            modified(path),
+            path.as_os_str().len(),
+            path.to_string_lossy().to_string(),
+        )
+    });
+}
+
+/// Finds all images below `root` with identical content. Only files which share their size
+/// with another file are hashed, the hashes are cached in `index`.
+pub(crate) fn find_duplicates<F>(
+    root: &Path,
+    config: &Config,
+    filter: Option<&FileFilter>,
+    index: &HashIndex,
+    job: &Job,
+    mut on_progress: F,
+) -> crate::Result<FindDuplicatesResult>
+where
+    F: FnMut(&ScanProgress),
+{
+    let job_id = job.id();
+    let files: Vec<(PathBuf, u64)> = image_files(root, config, filter, job).collect();
+    job.check()?;
+
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for (path, size) in &files {
+        by_size.entry(*size).or_default().push(path.clone());
+    }
+    let candidates: Vec<(u64, PathBuf)> = by_size
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .flat_map(|(size, paths)| paths.into_iter().map(move |path| (size, path)))
+        .collect();
+
+    let total = candidates.len();
+    let mut by_hash: HashMap<(u64, String), Vec<PathBuf>> = HashMap::new();
+    for (processed, (size, path)) in candidates.into_iter().enumerate() {
+        if job.is_cancelled() {
+            // keep the hashes computed so far
+            index.save_or_log();
+            job.check()?;
+        }
+
+        on_progress(&ScanProgress {
+            job_id: job_id.clone(),
+            total,
+            processed,
+            current_path: path.to_string_lossy().to_string(),
+        });
+
+        match std::fs::metadata(&path).and_then(|metadata| index.hash(&path, &metadata)) {
+            Ok(hash) => by_hash.entry((size, hash)).or_default().push(path),
+            Err(err) => warn!("unable to hash {}: {err}", path.to_string_lossy()),
+        }
+    }
+
+    on_progress(&ScanProgress {
+        job_id: job_id.clone(),
+        total,
+        processed: total,
+        current_path: String::new(),
+    });
+
+    let existing: Vec<PathBuf> = files.into_iter().map(|(path, _)| path).collect();
+    index.prune(root, &existing);
+    index.save_or_log();
+
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .filter_map(|((size, hash), mut paths)| {
+            keeper_order(&mut paths);
+            let items: Vec<_> = paths
+                .iter()
+                .filter_map(|path| read_image_info(path))
+                .collect();
+            Some(DuplicateGroup {
+                hash,
+                size,
+                suggested_keeper: items.first()?.content_uri.clone(),
+                items,
+            })
+        })
+        .collect();
+    groups.sort_by_key(|group| {
+        std::cmp::Reverse(group.size * (group.items.len() as u64).saturating_sub(1))
+    });
+
+    Ok(FindDuplicatesResult { job_id, groups })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jobs::Jobs, JobKind};
+    use std::fs;
+
+    #[test]
+    fn test_find_duplicates() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("import").join("again")).unwrap();
+
+        fs::write(dir.join("a.jpg"), b"same").unwrap();
+        fs::write(dir.join("import").join("a.jpg"), b"same").unwrap();
+        fs::write(dir.join("import").join("again").join("a.jpg"), b"same").unwrap();
+        // same size, different content
+        fs::write(dir.join("b.jpg"), b"diff").unwrap();
+        fs::write(dir.join("c.jpg"), b"unique content").unwrap();
+
+        let index = HashIndex::load(None);
+        let mut progress = Vec::new();
+        let job = Jobs::default()
+            .start(None, JobKind::Hashing, |_| {})
+            .unwrap();
+        let result = find_duplicates(dir, &Config::default(), None, &index, &job, |p| {
+            progress.push(p.processed)
+        })
+        .unwrap();
+
+        assert_eq!(result.groups.len(), 1);
+        let group = &result.groups[0];
+        assert_eq!(group.items.len(), 3);
+        assert_eq!(group.size, 4);
+        assert_eq!(group.suggested_keeper, group.items[0].content_uri);
+        assert_eq!(progress, vec![0, 1, 2, 3, 4]);
+    }
+}
@@ -1,4 +1,4 @@
-use serde::{ser::Serializer, Serialize};
+use serde::{ser::SerializeStruct, ser::Serializer, Serialize};
 
 use crate::MediaLibrarySource;
 
@@ -18,26 +18,185 @@ pub enum Error {
     XdgPicturesDirNotSet,
     #[error("unsupported media library source: {0}")]
     MediaLibrarySourceNotSupported(MediaLibrarySource),
+    #[error("access to {0} was not granted, it has to be requested first")]
+    AccessNotGranted(MediaLibrarySource),
+    #[cfg(feature = "portal")]
+    #[error(transparent)]
+    Portal(#[from] zbus::Error),
     #[cfg(mobile)]
     #[error(transparent)]
     PluginInvoke(#[from] tauri::plugin::mobile::PluginInvokeError),
-    #[error("amytoes error: {0}")]
-    AllMyToes(String),
+    #[error("thumbnail provider {provider} failed: {message}")]
+    ThumbnailProvider { provider: String, message: String },
+    #[error("no thumbnail provider could create a thumbnail of {0}")]
+    NoThumbnailProvider(String),
     #[error("cannot parse uri: {0}")]
     ParseUriError(String),
     #[error("invalid uri scheme: {0}")]
     InvalidUriScheme(String),
     #[error("base64 decode error: {0}")]
     Base64(#[from] base64::DecodeError),
-    #[error("get data error")]
+    #[error("unable to get the data of {0}")]
     GetDataError(String),
+    #[error("file already exists: {0}")]
+    FileExists(String),
+    #[error("invalid file name: {0}")]
+    InvalidFileName(String),
+    #[error("path is not part of the media library: {0}")]
+    PathNotInMediaLibrary(String),
+    #[error("only files in the temporary or cache directory can be saved: {0}")]
+    PathNotAllowed(String),
+    #[error("the folder is not allowed by an importFolder scope entry: {0}")]
+    ImportFolderForbidden(String),
+    #[error("the import folder and the media library overlap: {0}")]
+    ImportFolderOverlapsLibrary(String),
+    #[error("no image content provided")]
+    MissingImageContent,
+    #[error("invalid folder template: {0}")]
+    InvalidFolderTemplate(String),
+    #[error("unable to determine the capture date of {0}")]
+    CaptureDateUnavailable(String),
+    #[error("xmp error: {0}")]
+    Xmp(String),
+    #[error("invalid image transformation: {0}")]
+    InvalidImageTransform(String),
+    #[error("unable to transform image: {0}")]
+    ImageTransform(String),
+    #[error("job cancelled: {0}")]
+    JobCancelled(String),
+    #[error("a job with this id is already running: {0}")]
+    JobAlreadyRunning(String),
+    #[error("operation not supported on this platform: {0}")]
+    UnsupportedOperation(String),
+}
+
+/// Stable discriminant of an error, lets the frontend react to certain errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ErrorKind {
+    NotFound,
+    AlreadyExists,
+    /// The path or source is not part of the media library
+    Forbidden,
+    SourceNotSupported,
+    /// The operating system denied the access
+    PermissionDenied,
+    InvalidUri,
+    InvalidArgument,
+    ThumbnailUnavailable,
+    ImageProcessing,
+    Metadata,
+    Cancelled,
+    Unsupported,
+    XdgPicturesDirNotSet,
+    Io,
+    Internal,
 }
 
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(err) => match err.kind() {
+                std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+                std::io::ErrorKind::AlreadyExists => ErrorKind::AlreadyExists,
+                std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+                _ => ErrorKind::Io,
+            },
+            Error::GetDataError(_) => ErrorKind::NotFound,
+            Error::FileExists(_) | Error::JobAlreadyRunning(_) => ErrorKind::AlreadyExists,
+            Error::MediaLibrarySourceForbidden(_)
+            | Error::PathNotInMediaLibrary(_)
+            | Error::PathNotAllowed(_)
+            | Error::ImportFolderForbidden(_) => ErrorKind::Forbidden,
+            Error::MediaLibrarySourceNotSupported(_) => ErrorKind::SourceNotSupported,
+            Error::AccessNotGranted(_) => ErrorKind::PermissionDenied,
+            Error::ParseUriError(_) | Error::InvalidUriScheme(_) => ErrorKind::InvalidUri,
+            Error::Base64(_)
+            | Error::InvalidFileName(_)
+            | Error::MissingImageContent
+            | Error::InvalidFolderTemplate(_)
+            | Error::InvalidImageTransform(_)
+            | Error::ImportFolderOverlapsLibrary(_) => ErrorKind::InvalidArgument,
+            Error::ThumbnailProvider { .. } | Error::NoThumbnailProvider(_) => {
+                ErrorKind::ThumbnailUnavailable
+            }
+            Error::ImageTransform(_) => ErrorKind::ImageProcessing,
+            Error::Xmp(_) | Error::CaptureDateUnavailable(_) => ErrorKind::Metadata,
+            Error::JobCancelled(_) => ErrorKind::Cancelled,
+            Error::UnsupportedOperation(_) => ErrorKind::Unsupported,
+            Error::XdgPicturesDirNotSet => ErrorKind::XdgPicturesDirNotSet,
+            Error::Json(_) | Error::Tauri(_) => ErrorKind::Internal,
+            #[cfg(mobile)]
+            Error::PluginInvoke(_) => ErrorKind::Internal,
+            #[cfg(feature = "portal")]
+            Error::Portal(_) => ErrorKind::Internal,
+        }
+    }
+
+    /// Values of the variant which the frontend may need besides the message.
+    fn details(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        match self {
+            Error::Io(err) => json!({ "osError": err.raw_os_error() }),
+            Error::MediaLibrarySourceForbidden(source)
+            | Error::MediaLibrarySourceNotSupported(source)
+            | Error::AccessNotGranted(source) => json!({ "source": source }),
+            Error::ParseUriError(uri) | Error::GetDataError(uri) => json!({ "uri": uri }),
+            Error::InvalidUriScheme(scheme) => json!({ "scheme": scheme }),
+            Error::FileExists(path)
+            | Error::PathNotInMediaLibrary(path)
+            | Error::PathNotAllowed(path)
+            | Error::ImportFolderForbidden(path)
+            | Error::ImportFolderOverlapsLibrary(path)
+            | Error::CaptureDateUnavailable(path)
+            | Error::NoThumbnailProvider(path) => json!({ "path": path }),
+            Error::InvalidFileName(name) => json!({ "name": name }),
+            Error::ThumbnailProvider { provider, .. } => json!({ "provider": provider }),
+            Error::JobCancelled(job_id) | Error::JobAlreadyRunning(job_id) => {
+                json!({ "jobId": job_id })
+            }
+            Error::UnsupportedOperation(operation) => json!({ "operation": operation }),
+            _ => serde_json::Value::Null,
+        }
+    }
+}
+
+/// Errors reach the frontend as `{ kind, message, details }`.
 impl Serialize for Error {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        serializer.serialize_str(self.to_string().as_ref())
+        let mut error = serializer.serialize_struct("Error", 3)?;
+        error.serialize_field("kind", &self.kind())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.serialize_field("details", &self.details())?;
+        error.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_serialize() {
+        let error = Error::MediaLibrarySourceNotSupported(MediaLibrarySource::PictureDir);
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "kind": "SourceNotSupported",
+                "message": "unsupported media library source: PictureDir",
+                "details": { "source": "PictureDir" },
+            })
+        );
+
+        let error = Error::Io(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert_eq!(serde_json::to_value(&error).unwrap()["kind"], "NotFound");
+        assert_eq!(
+            serde_json::to_value(Error::MissingImageContent).unwrap()["details"],
+            serde_json::Value::Null
+        );
     }
 }
@@ -0,0 +1,161 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use chrono::NaiveDateTime;
+use exif::{Tag, Value};
+
+use crate::{
+    raw::{find_field, RawFormat},
+    MetaDataField,
+};
+
+/// EXIF data of an image, RAW files may store it in several TIFF structures.
+struct ExifData(Vec<exif::Exif>);
+
+impl ExifData {
+    fn get(&self, tag: Tag) -> Option<&exif::Field> {
+        find_field(&self.0, tag)
+    }
+}
+
+fn read_exif(path: &Path) -> Option<ExifData> {
+    let mut input = BufReader::new(File::open(path).ok()?);
+
+    // CR3, RAF and ORF are not supported by the exif crate, which also reads TIFF based RAW
+    // files completely
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    if let Some(raw) = extension.and_then(RawFormat::from_extension) {
+        let exif = raw.exif(&mut input);
+        return (!exif.is_empty()).then_some(ExifData(exif));
+    }
+
+    let exif = exif::Reader::new().read_from_container(&mut input).ok()?;
+    Some(ExifData(vec![exif]))
+}
+
+fn read_date_time(exif: &ExifData, tag: Tag) -> Option<NaiveDateTime> {
+    let field = exif.get(tag)?;
+    let Value::Ascii(ref values) = field.value else {
+        return None;
+    };
+    let date_time = exif::DateTime::from_ascii(values.first()?).ok()?;
+
+    chrono::NaiveDate::from_ymd_opt(
+        date_time.year.into(),
+        date_time.month.into(),
+        date_time.day.into(),
+    )?
+    .and_hms_opt(
+        date_time.hour.into(),
+        date_time.minute.into(),
+        date_time.second.into(),
+    )
+}
+
+/// Returns the (local) date and time the image was taken according to its EXIF data.
+pub(crate) fn date_taken(path: &Path) -> Option<NaiveDateTime> {
+    read_date_taken(&read_exif(path)?)
+}
+
+fn read_date_taken(exif: &ExifData) -> Option<NaiveDateTime> {
+    [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| read_date_time(exif, tag))
+}
+
+/// Returns the orientation of the image according to its EXIF data.
+#[cfg(feature = "amt")]
+pub(crate) fn orientation(path: &Path) -> Option<image::metadata::Orientation> {
+    let exif = read_exif(path)?;
+    let value = exif.get(Tag::Orientation)?.value.get_uint(0)?;
+    image::metadata::Orientation::from_exif(value.try_into().ok()?)
+}
+
+fn ascii(field: &exif::Field) -> Option<String> {
+    let Value::Ascii(ref values) = field.value else {
+        return None;
+    };
+    let value = String::from_utf8_lossy(values.first()?);
+    let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn rational(field: &exif::Field) -> Option<f64> {
+    match field.value {
+        Value::Rational(ref values) => values.first().map(|value| value.to_f64()),
+        Value::SRational(ref values) => values.first().map(|value| value.to_f64()),
+        _ => None,
+    }
+    .filter(|value| value.is_finite())
+}
+
+/// Formats an exposure time like cameras do, e.g. `1/250` or `2.5`.
+fn exposure_time(seconds: f64) -> String {
+    if seconds > 0.0 && seconds < 1.0 {
+        format!("1/{}", (1.0 / seconds).round())
+    } else {
+        format!("{}", (seconds * 10.0).round() / 10.0)
+    }
+}
+
+/// Returns the camera settings and the date taken as metadata fields.
+pub(crate) fn to_meta_data(path: &Path) -> Vec<(MetaDataField, String)> {
+    let Some(exif) = read_exif(path) else {
+        return Vec::new();
+    };
+    let round = |value: f64| format!("{}", (value * 10.0).round() / 10.0);
+
+    [
+        (
+            MetaDataField::CameraMake,
+            exif.get(Tag::Make).and_then(ascii),
+        ),
+        (
+            MetaDataField::CameraModel,
+            exif.get(Tag::Model).and_then(ascii),
+        ),
+        (
+            MetaDataField::LensModel,
+            exif.get(Tag::LensModel).and_then(ascii),
+        ),
+        (
+            MetaDataField::ExposureTime,
+            exif.get(Tag::ExposureTime)
+                .and_then(rational)
+                .map(exposure_time),
+        ),
+        (
+            MetaDataField::FNumber,
+            exif.get(Tag::FNumber).and_then(rational).map(round),
+        ),
+        (
+            MetaDataField::FocalLength,
+            exif.get(Tag::FocalLength).and_then(rational).map(round),
+        ),
+        (
+            MetaDataField::Iso,
+            exif.get(Tag::PhotographicSensitivity)
+                .and_then(|field| field.value.get_uint(0))
+                .map(|iso| iso.to_string()),
+        ),
+        (
+            MetaDataField::DateTaken,
+            read_date_taken(&exif)
+                .map(|date_time| date_time.format("%Y-%m-%dT%H:%M:%S").to_string()),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(field, value)| Some((field, value?)))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exposure_time() {
+        assert_eq!(exposure_time(0.004), "1/250");
+        assert_eq!(exposure_time(2.5), "2.5");
+        assert_eq!(exposure_time(30.0), "30");
+    }
+}
@@ -0,0 +1,175 @@
+use image::{error::ImageFormatHint, DynamicImage, ImageError, ImageResult};
+
+/// Image formats which are not supported by the `image` crate and need an additional decoder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ExtraFormat {
+    Heif,
+    Avif,
+    Jxl,
+}
+
+const HEIF_BRANDS: [&[u8; 4]; 8] = [
+    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1",
+];
+const AVIF_BRANDS: [&[u8; 4]; 2] = [b"avif", b"avis"];
+
+const JXL_CODESTREAM: &[u8] = &[0xff, 0x0a];
+const JXL_CONTAINER: &[u8] = b"\0\0\0\x0cJXL \r\n\x87\n";
+
+impl ExtraFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            ExtraFormat::Heif => "HEIF",
+            ExtraFormat::Avif => "AVIF",
+            ExtraFormat::Jxl => "JPEG XL",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ExtraFormat::Heif => "image/heif",
+            ExtraFormat::Avif => "image/avif",
+            ExtraFormat::Jxl => "image/jxl",
+        }
+    }
+
+    /// Detects the format from the first bytes of the file.
+    pub fn detect(content: &[u8]) -> Option<Self> {
+        if content.starts_with(JXL_CODESTREAM) || content.starts_with(JXL_CONTAINER) {
+            return Some(ExtraFormat::Jxl);
+        }
+
+        // ISO base media file: size of the box, "ftyp", major brand, minor version and the
+        // compatible brands
+        if content.len() < 16 || &content[4..8] != b"ftyp" {
+            return None;
+        }
+        let box_size = u32::from_be_bytes(content[..4].try_into().ok()?) as usize;
+        let major_brand = &content[8..12];
+        let compatible_brands = content
+            .get(16..box_size.min(content.len()))
+            .unwrap_or_default()
+            .chunks_exact(4);
+        let brands = || std::iter::once(major_brand).chain(compatible_brands.clone());
+
+        // an avif file usually lists "mif1" as well, so it has to be checked first
+        if brands().any(|brand| AVIF_BRANDS.iter().any(|avif| brand == *avif)) {
+            Some(ExtraFormat::Avif)
+        } else if brands().any(|brand| HEIF_BRANDS.iter().any(|heif| brand == *heif)) {
+            Some(ExtraFormat::Heif)
+        } else {
+            None
+        }
+    }
+
+    /// Decodes the primary image, transformations stored in the file are applied by the decoder.
+    /// Fails with an unsupported error if the decoder for the format is not enabled.
+    #[cfg_attr(not(all(feature = "heif", feature = "jxl")), allow(unused_variables))]
+    pub fn decode(&self, content: &[u8]) -> ImageResult<DynamicImage> {
+        match self {
+            #[cfg(feature = "heif")]
+            ExtraFormat::Heif | ExtraFormat::Avif => decode_heif(content).map_err(|err| {
+                ImageError::Decoding(image::error::DecodingError::new(self.hint(), err))
+            }),
+            #[cfg(feature = "jxl")]
+            ExtraFormat::Jxl => decode_jxl(content),
+            #[cfg(not(all(feature = "heif", feature = "jxl")))]
+            _ => {
+                use image::error::{UnsupportedError, UnsupportedErrorKind};
+                Err(ImageError::Unsupported(
+                    UnsupportedError::from_format_and_kind(
+                        self.hint(),
+                        UnsupportedErrorKind::Format(self.hint()),
+                    ),
+                ))
+            }
+        }
+    }
+
+    fn hint(&self) -> ImageFormatHint {
+        ImageFormatHint::Name(self.name().to_string())
+    }
+}
+
+/// Decodes HEIF and AVIF (which is a HEIF container with AV1 images) with libheif.
+#[cfg(feature = "heif")]
+fn decode_heif(content: &[u8]) -> libheif_rs::Result<DynamicImage> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let context = HeifContext::read_from_bytes(content)?;
+    let handle = context.primary_image_handle()?;
+    let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)?;
+
+    let planes = image.planes();
+    let Some(plane) = planes.interleaved else {
+        return Err(libheif_rs::HeifError {
+            code: libheif_rs::HeifErrorCode::DecoderPluginError,
+            sub_code: libheif_rs::HeifErrorSubCode::Unspecified,
+            message: "the decoded image has no interleaved plane".to_string(),
+        });
+    };
+
+    // rows may be padded, so they are copied one by one
+    let row_len = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    let buffer = image::RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .expect("the buffer has the size of the image");
+    Ok(DynamicImage::ImageRgba8(buffer))
+}
+
+/// Decodes JPEG XL with jxl-oxide, which applies the orientation of the image.
+#[cfg(feature = "jxl")]
+fn decode_jxl(content: &[u8]) -> ImageResult<DynamicImage> {
+    let decoder =
+        jxl_oxide::integration::JxlDecoder::new(std::io::Cursor::new(content)).map_err(|err| {
+            ImageError::Decoding(image::error::DecodingError::new(
+                ExtraFormat::Jxl.hint(),
+                err,
+            ))
+        })?;
+    DynamicImage::from_decoder(decoder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftyp(major_brand: &[u8], compatible_brands: &[&[u8]]) -> Vec<u8> {
+        let size = 16 + 4 * compatible_brands.len() as u32;
+        let mut content = size.to_be_bytes().to_vec();
+        content.extend_from_slice(b"ftyp");
+        content.extend_from_slice(major_brand);
+        content.extend_from_slice(&[0, 0, 0, 0]);
+        for brand in compatible_brands {
+            content.extend_from_slice(brand);
+        }
+        content.extend_from_slice(b"\0\0\0\x08meta");
+        content
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            ExtraFormat::detect(&ftyp(b"heic", &[b"mif1", b"heic"])),
+            Some(ExtraFormat::Heif)
+        );
+        assert_eq!(
+            ExtraFormat::detect(&ftyp(b"mif1", &[b"avif", b"miaf"])),
+            Some(ExtraFormat::Avif)
+        );
+        assert_eq!(ExtraFormat::detect(&ftyp(b"isom", &[b"mp41"])), None);
+        assert_eq!(
+            ExtraFormat::detect(b"\xff\x0a\x00\x00"),
+            Some(ExtraFormat::Jxl)
+        );
+        assert_eq!(
+            ExtraFormat::detect(b"\0\0\0\x0cJXL \r\n\x87\n\0\0\0\x14ftypjxl "),
+            Some(ExtraFormat::Jxl)
+        );
+        assert_eq!(ExtraFormat::detect(b"\xff\xd8\xff\xe0"), None);
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use log::warn;
+
+use crate::{CollisionPolicy, Error};
+
+/// Extensions of files that belong to an image and are moved/copied together with it.
+const SIDECAR_EXTENSIONS: &[&str] = &["xmp", "aae"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TransferMode {
+    Move,
+    Copy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SidecarNaming {
+    /// `IMG_0001.xmp` for `IMG_0001.jpg`
+    Stem,
+    /// `IMG_0001.jpg.xmp` for `IMG_0001.jpg` (e.g. darktable)
+    FullName,
+}
+
+#[derive(Debug, Clone)]
+struct Sidecar {
+    path: PathBuf,
+    naming: SidecarNaming,
+    extension: OsString,
+}
+
+impl Sidecar {
+    fn target_name(&self, file_name: &OsStr) -> OsString {
+        let mut name = match self.naming {
+            SidecarNaming::Stem => Path::new(file_name)
+                .file_stem()
+                .unwrap_or(file_name)
+                .to_os_string(),
+            SidecarNaming::FullName => file_name.to_os_string(),
+        };
+        name.push(".");
+        name.push(&self.extension);
+        name
+    }
+}
+
+/// Ensures that `file_name` is a plain file name without any directory components.
+pub(crate) fn validate_file_name(file_name: &str) -> crate::Result<&OsStr> {
+    let mut components = Path::new(file_name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) if !file_name.contains(['/', '\\']) => Ok(name),
+        _ => Err(Error::InvalidFileName(file_name.to_string())),
+    }
+}
+
+/// Joins a relative album path (e.g. `Holidays/2024`) to a library root and makes sure the
+/// result can not escape the root.
+pub(crate) fn resolve_album_path(root: &Path, album: Option<&str>) -> crate::Result<PathBuf> {
+    let mut path = root.to_path_buf();
+    if let Some(album) = album {
+        for component in Path::new(album).components() {
+            match component {
+                Component::Normal(segment) => path.push(segment),
+                Component::CurDir => {}
+                _ => return Err(Error::InvalidFileName(album.to_string())),
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Returns true if `path` (which has to exist) is located below `root`.
+pub(crate) fn is_within(root: &Path, path: &Path) -> bool {
+    match (fs::canonicalize(root), fs::canonicalize(path)) {
+        (Ok(root), Ok(path)) => path.starts_with(root),
+        _ => false,
+    }
+}
+
+fn find_sidecars(path: &Path) -> Vec<Sidecar> {
+    let (Some(parent), Some(file_name), Some(stem)) =
+        (path.parent(), path.file_name(), path.file_stem())
+    else {
+        return Vec::new();
+    };
+
+    let Ok(entries) = fs::read_dir(parent) else {
+        return Vec::new();
+    };
+
+    let file_name = file_name.to_string_lossy();
+    let stem = stem.to_string_lossy();
+
+    entries
+        .filter_map(std::result::Result::ok)
+        .filter_map(|entry| {
+            let entry_path = entry.path();
+            if entry_path == path {
+                return None;
+            }
+            let extension = entry_path.extension()?.to_os_string();
+            let ext = extension.to_string_lossy().to_lowercase();
+            if !SIDECAR_EXTENSIONS.contains(&ext.as_str()) {
+                return None;
+            }
+
+            let entry_stem = entry_path.file_stem()?.to_string_lossy().into_owned();
+            let naming = if entry_stem == stem {
+                SidecarNaming::Stem
+            } else if entry_stem == file_name {
+                SidecarNaming::FullName
+            } else {
+                return None;
+            };
+
+            Some(Sidecar {
+                path: entry_path,
+                naming,
+                extension,
+            })
+        })
+        .collect()
+}
+
+fn with_counter(file_name: &OsStr, counter: usize) -> OsString {
+    let path = Path::new(file_name);
+    let stem = path.file_stem().unwrap_or(file_name).to_string_lossy();
+    match path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, counter, ext.to_string_lossy()).into(),
+        None => format!("{} ({})", stem, counter).into(),
+    }
+}
+
+/// Picks the name for the target file according to the collision policy.
+/// The image and all its sidecars are checked, so they always keep matching names.
+/// Moving a file onto itself is not a collision, copying it is.
+fn resolve_target_name(
+    source: &Path,
+    target_dir: &Path,
+    file_name: &OsStr,
+    sidecars: &[Sidecar],
+    policy: CollisionPolicy,
+    mode: TransferMode,
+) -> crate::Result<OsString> {
+    let is_occupied = |target: &Path, original: &Path| {
+        target.exists() && (mode == TransferMode::Copy || target != original)
+    };
+
+    let collides = |name: &OsStr| {
+        is_occupied(&target_dir.join(name), source)
+            || sidecars.iter().any(|sidecar| {
+                is_occupied(&target_dir.join(sidecar.target_name(name)), &sidecar.path)
+            })
+    };
+
+    if !collides(file_name) {
+        return Ok(file_name.to_os_string());
+    }
+
+    match policy {
+        CollisionPolicy::Fail => Err(Error::FileExists(
+            target_dir.join(file_name).to_string_lossy().to_string(),
+        )),
+        CollisionPolicy::Overwrite => Ok(file_name.to_os_string()),
+        CollisionPolicy::KeepBoth => (1..)
+            .map(|counter| with_counter(file_name, counter))
+            .find(|name| !collides(name))
+            .ok_or_else(|| Error::FileExists(file_name.to_string_lossy().to_string())),
+    }
+}
+
+fn copy_file(from: &Path, to: &Path, overwrite: bool) -> io::Result<()> {
+    if overwrite {
+        fs::copy(from, to)?;
+        return Ok(());
+    }
+
+    let mut source = fs::File::open(from)?;
+    let mut target = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)?;
+    if let Err(err) = io::copy(&mut source, &mut target).and_then(|_| target.flush()) {
+        let _ = fs::remove_file(to);
+        return Err(err);
+    }
+    Ok(())
+}
+
+fn transfer_single(from: &Path, to: &Path, mode: TransferMode, overwrite: bool) -> io::Result<()> {
+    match mode {
+        // never truncate a file by copying it onto itself
+        TransferMode::Copy if from == to => Err(io::Error::from(io::ErrorKind::AlreadyExists)),
+        TransferMode::Copy => copy_file(from, to, overwrite),
+        TransferMode::Move if from == to => Ok(()),
+        TransferMode::Move => {
+            if !overwrite && to.exists() {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+            }
+            match fs::rename(from, to) {
+                Ok(_) => Ok(()),
+                // rename does not work across file systems, fall back to copy and delete
+                Err(err) if crosses_devices(&err) => {
+                    copy_file(from, to, overwrite)?;
+                    fs::remove_file(from)
+                }
+                Err(err) => Err(err),
+            }
+        }
+    }
+}
+
+/// `io::ErrorKind::CrossesDevices` requires a newer Rust version than the MSRV.
+fn crosses_devices(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    const EXDEV: i32 = libc::EXDEV;
+    // ERROR_NOT_SAME_DEVICE
+    #[cfg(windows)]
+    const EXDEV: i32 = 17;
+
+    err.raw_os_error() == Some(EXDEV)
+}
+
+/// Moves or copies `source` to `target_dir/file_name` together with its sidecar files.
+/// Returns the path of the transferred image.
+pub(crate) fn transfer_image(
+    source: &Path,
+    target_dir: &Path,
+    file_name: &OsStr,
+    policy: CollisionPolicy,
+    mode: TransferMode,
+) -> crate::Result<PathBuf> {
+    if !source.is_file() {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist", source.to_string_lossy()),
+        )));
+    }
+
+    fs::create_dir_all(target_dir)?;
+
+    let sidecars = find_sidecars(source);
+    let target_name = resolve_target_name(source, target_dir, file_name, &sidecars, policy, mode)?;
+    let overwrite = policy == CollisionPolicy::Overwrite;
+
+    let target = target_dir.join(&target_name);
+    transfer_single(source, &target, mode, overwrite)?;
+
+    for sidecar in sidecars {
+        let sidecar_target = target_dir.join(sidecar.target_name(&target_name));
+        if let Err(err) = transfer_single(&sidecar.path, &sidecar_target, mode, overwrite) {
+            warn!(
+                "unable to transfer sidecar {}: {err}",
+                sidecar.path.to_string_lossy()
+            );
+        }
+    }
+
+    Ok(target)
+}
+
+/// Writes a new file to `target_dir/file_name`.
+/// The content is written to a temporary file first and moved into place afterwards,
+/// so other applications never see a partially written image.
+pub(crate) fn write_new_file<F>(
+    target_dir: &Path,
+    file_name: &OsStr,
+    policy: CollisionPolicy,
+    write: F,
+) -> crate::Result<PathBuf>
+where
+    F: FnOnce(&mut fs::File) -> io::Result<()>,
+{
+    fs::create_dir_all(target_dir)?;
+
+    let mut file = tempfile::Builder::new()
+        .prefix(".")
+        .suffix(".tmp")
+        .tempfile_in(target_dir)?;
+    write(file.as_file_mut())?;
+    file.as_file().sync_all()?;
+    // removed when dropped unless it has been published
+    let temp_path = file.into_temp_path();
+
+    loop {
+        // in copy mode the source is never compared with the target, so any path will do
+        let target_name = resolve_target_name(
+            target_dir,
+            target_dir,
+            file_name,
+            &[],
+            policy,
+            TransferMode::Copy,
+        )?;
+        let target = target_dir.join(&target_name);
+
+        match publish_file(&temp_path, &target, policy == CollisionPolicy::Overwrite) {
+            Ok(_) => return Ok(target),
+            // another writer took the name in the meantime, pick the next one
+            Err(err)
+                if err.kind() == io::ErrorKind::AlreadyExists
+                    && policy == CollisionPolicy::KeepBoth => {}
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(Error::FileExists(target.to_string_lossy().to_string()))
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+fn publish_file(temp_path: &Path, target: &Path, overwrite: bool) -> io::Result<()> {
+    if overwrite {
+        return fs::rename(temp_path, target);
+    }
+
+    // a hard link fails if the target has been created in the meantime,
+    // rename would silently replace it
+    match fs::hard_link(temp_path, target) {
+        Ok(_) => fs::remove_file(temp_path),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(err),
+        // some file systems (e.g. FAT) do not support hard links
+        Err(_) if !target.exists() => fs::rename(temp_path, target),
+        Err(_) => Err(io::Error::from(io::ErrorKind::AlreadyExists)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_file_name() {
+        assert!(validate_file_name("image.jpg").is_ok());
+        assert!(validate_file_name("../image.jpg").is_err());
+        assert!(validate_file_name("album/image.jpg").is_err());
+        assert!(validate_file_name("").is_err());
+    }
+
+    #[test]
+    fn test_resolve_album_path_rejects_parent_components() {
+        let root = Path::new("/pictures");
+        assert_eq!(
+            resolve_album_path(root, Some("Holidays/2024")).unwrap(),
+            PathBuf::from("/pictures/Holidays/2024")
+        );
+        assert!(resolve_album_path(root, Some("../etc")).is_err());
+        assert!(resolve_album_path(root, Some("/etc")).is_err());
+    }
+
+    #[test]
+    fn test_move_carries_sidecars() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("IMG_0001.jpg"), b"image").unwrap();
+        fs::write(dir.join("IMG_0001.xmp"), b"xmp").unwrap();
+        fs::write(dir.join("IMG_0001.jpg.xmp"), b"darktable").unwrap();
+        fs::write(dir.join("IMG_0002.xmp"), b"other").unwrap();
+
+        let target = transfer_image(
+            &dir.join("IMG_0001.jpg"),
+            &dir.join("album"),
+            OsStr::new("renamed.jpg"),
+            CollisionPolicy::Fail,
+            TransferMode::Move,
+        )
+        .unwrap();
+
+        assert_eq!(target, dir.join("album").join("renamed.jpg"));
+        assert!(dir.join("album").join("renamed.xmp").exists());
+        assert!(dir.join("album").join("renamed.jpg.xmp").exists());
+        assert!(dir.join("IMG_0002.xmp").exists());
+        assert!(!dir.join("IMG_0001.jpg").exists());
+    }
+
+    #[test]
+    fn test_write_new_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("new.png"), b"existing").unwrap();
+
+        let result = write_new_file(dir, OsStr::new("new.png"), CollisionPolicy::Fail, |file| {
+            file.write_all(b"content")
+        });
+        assert!(matches!(result, Err(Error::FileExists(_))));
+
+        let target = write_new_file(
+            dir,
+            OsStr::new("new.png"),
+            CollisionPolicy::KeepBoth,
+            |file| file.write_all(b"content"),
+        )
+        .unwrap();
+        assert_eq!(target, dir.join("new (1).png"));
+        assert_eq!(fs::read(&target).unwrap(), b"content");
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_collision_policies() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("a.jpg"), b"a").unwrap();
+        fs::write(dir.join("b.jpg"), b"b").unwrap();
+
+        let result = transfer_image(
+            &dir.join("a.jpg"),
+            dir,
+            OsStr::new("b.jpg"),
+            CollisionPolicy::Fail,
+            TransferMode::Copy,
+        );
+        assert!(matches!(result, Err(Error::FileExists(_))));
+
+        let target = transfer_image(
+            &dir.join("a.jpg"),
+            dir,
+            OsStr::new("b.jpg"),
+            CollisionPolicy::KeepBoth,
+            TransferMode::Copy,
+        )
+        .unwrap();
+        assert_eq!(target, dir.join("b (1).jpg"));
+
+        transfer_image(
+            &dir.join("a.jpg"),
+            dir,
+            OsStr::new("b.jpg"),
+            CollisionPolicy::Overwrite,
+            TransferMode::Copy,
+        )
+        .unwrap();
+        assert_eq!(fs::read(dir.join("b.jpg")).unwrap(), b"a");
+    }
+
+    #[test]
+    fn test_write_new_file_concurrently() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let dir = dir.to_path_buf();
+                std::thread::spawn(move || {
+                    write_new_file(
+                        &dir,
+                        OsStr::new("new.png"),
+                        CollisionPolicy::KeepBoth,
+                        |file| file.write_all(&[i]),
+                    )
+                })
+            })
+            .collect();
+        let mut targets: Vec<PathBuf> = writers
+            .into_iter()
+            .map(|writer| writer.join().unwrap().unwrap())
+            .collect();
+        targets.sort();
+        targets.dedup();
+
+        assert_eq!(targets.len(), 8);
+        // no temporary files are left behind
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 8);
+    }
+}
@@ -1,7 +1,9 @@
 use http::{header::*, response::Builder as ResponseBuilder, status::StatusCode};
 use tauri::{AppHandle, Runtime};
 
-use crate::{protocol_handler::get_uri_string_from_request, MedialibraryExt};
+use crate::{
+    image_transform::ImageTransform, protocol_handler::get_uri_string_from_request, MedialibraryExt,
+};
 
 pub(crate) async fn get_response<R: Runtime>(
     request: http::Request<Vec<u8>>,
@@ -9,6 +11,14 @@ pub(crate) async fn get_response<R: Runtime>(
 ) -> Result<http::Response<Vec<u8>>, Box<dyn std::error::Error>> {
     let uri_str = get_uri_string_from_request(&request);
 
+    let transform = match request.uri().query() {
+        Some(query) => ImageTransform::from_query(query)?,
+        None => None,
+    };
+    if let Some(transform) = transform {
+        return get_rendition(app, uri_str, transform).await;
+    }
+
     let image = app.medialibrary().get_image_data(uri_str).await?;
     let file_len = image.content.len();
 
@@ -17,3 +27,48 @@ pub(crate) async fn get_response<R: Runtime>(
         .header(CONTENT_LENGTH, file_len)
         .body(image.content)?)
 }
+
+/// Returns the transformed image, renditions are cached as long as the original is unchanged.
+async fn get_rendition<R: Runtime>(
+    app: &AppHandle<R>,
+    uri: String,
+    transform: ImageTransform,
+) -> Result<http::Response<Vec<u8>>, Box<dyn std::error::Error>> {
+    // without a version of the original a cached rendition could be outdated
+    let lookup = {
+        let uri = uri.clone();
+        let transform = transform.clone();
+        crate::blocking::run(app, move |app| {
+            let medialibrary = app.medialibrary();
+            let key = medialibrary
+                .rendition_cache()
+                .and_then(|_| medialibrary.get_image_version(&uri))
+                .map(|version| transform.cache_key(&uri, &version));
+            let cached = key
+                .as_ref()
+                .and_then(|key| medialibrary.rendition_cache()?.get(key));
+            Ok((key, cached))
+        })
+    };
+
+    let (content, format) = match lookup.await? {
+        (_, Some(rendition)) => rendition,
+        (key, None) => {
+            let image = app.medialibrary().get_image_data(uri).await?;
+            crate::blocking::run(app, move |app| {
+                let rendition = transform.apply(&image.content)?;
+                if let Some((cache, key)) = app.medialibrary().rendition_cache().zip(key) {
+                    cache.put(&key, &rendition.0);
+                }
+                Ok(rendition)
+            })
+            .await?
+        }
+    };
+
+    Ok(ResponseBuilder::new()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, format.mime_type())
+        .header(CONTENT_LENGTH, content.len())
+        .body(content)?)
+}
@@ -0,0 +1,489 @@
+use std::{
+    fs,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, GenericImageView, ImageFormat,
+};
+use log::warn;
+
+use crate::{orientation::decode_upright, Error};
+
+const DEFAULT_QUALITY: u8 = 85;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) enum Fit {
+    /// Scale the image to fit into the given size, keeping the aspect ratio
+    #[default]
+    Contain,
+    /// Scale and crop the image so it covers the given size
+    Cover,
+    /// Scale the image to exactly the given size
+    Fill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OutputFormat {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl OutputFormat {
+    fn from_image_format(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::Jpeg => OutputFormat::Jpeg,
+            ImageFormat::WebP => OutputFormat::Webp,
+            _ => OutputFormat::Png,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Png => "image/png",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// Transformation requested with the query of an `image://` uri, e.g.
+/// `?w=1600&h=1200&fit=contain&format=webp&q=80&crop=0,0,800,600`.
+/// Renditions are always upright, `?upright=1` alone only applies the EXIF orientation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ImageTransform {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    pub format: Option<OutputFormat>,
+    /// JPEG quality (1 - 100), webp is always encoded lossless
+    pub quality: Option<u8>,
+    /// x, y, width and height in pixels of the upright image, applied before resizing
+    pub crop: Option<(u32, u32, u32, u32)>,
+}
+
+fn invalid(key: &str, value: &str) -> Error {
+    Error::InvalidImageTransform(format!("{key}={value}"))
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: &str) -> crate::Result<T> {
+    value.parse().map_err(|_| invalid(key, value))
+}
+
+impl ImageTransform {
+    /// Parses the query of a request, returns `None` if it does not contain any transformation
+    /// so the original image is returned unchanged.
+    pub fn from_query(query: &str) -> crate::Result<Option<Self>> {
+        let mut transform = ImageTransform::default();
+        let mut has_transform = false;
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_encoding::percent_decode_str(value).decode_utf8_lossy();
+            let value = value.as_ref();
+
+            match key {
+                "w" => transform.width = Some(parse_number(key, value)?),
+                "h" => transform.height = Some(parse_number(key, value)?),
+                "fit" => {
+                    transform.fit = match value {
+                        "contain" => Fit::Contain,
+                        "cover" => Fit::Cover,
+                        "fill" => Fit::Fill,
+                        _ => return Err(invalid(key, value)),
+                    }
+                }
+                "format" => {
+                    transform.format = Some(match value {
+                        "jpeg" | "jpg" => OutputFormat::Jpeg,
+                        "png" => OutputFormat::Png,
+                        "webp" => OutputFormat::Webp,
+                        _ => return Err(invalid(key, value)),
+                    })
+                }
+                "q" => {
+                    let quality: u8 = parse_number(key, value)?;
+                    if !(1..=100).contains(&quality) {
+                        return Err(invalid(key, value));
+                    }
+                    transform.quality = Some(quality);
+                }
+                "crop" => {
+                    let parts = value
+                        .split(',')
+                        .map(|part| parse_number::<u32>(key, part.trim()))
+                        .collect::<crate::Result<Vec<_>>>()?;
+                    match parts[..] {
+                        [x, y, width, height] if width > 0 && height > 0 => {
+                            transform.crop = Some((x, y, width, height))
+                        }
+                        _ => return Err(invalid(key, value)),
+                    }
+                }
+                "upright" => {
+                    if !matches!(value, "1" | "true") {
+                        continue;
+                    }
+                }
+                // unknown parameters (e.g. cache busters) are ignored
+                _ => continue,
+            }
+            has_transform = true;
+        }
+
+        // the webp encoder only supports lossless compression
+        if transform.format == Some(OutputFormat::Webp) && transform.quality.is_some() {
+            return Err(Error::InvalidImageTransform(
+                "q is not supported for webp, it is always encoded lossless".to_string(),
+            ));
+        }
+        if transform.width == Some(0) || transform.height == Some(0) {
+            return Err(Error::InvalidImageTransform(
+                "width and height must not be 0".to_string(),
+            ));
+        }
+
+        Ok(has_transform.then_some(transform))
+    }
+
+    /// File name of the rendition in the cache, `version` identifies the state of the original.
+    pub fn cache_key(&self, uri: &str, version: &str) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(uri.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(version.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(format!("{self:?}").as_bytes());
+        hasher.finalize().to_hex().to_string()
+    }
+
+    fn resize(&self, image: DynamicImage) -> DynamicImage {
+        let (width, height) = image.dimensions();
+        let (target_width, target_height) = match (self.width, self.height) {
+            (None, None) => return image,
+            (Some(w), Some(h)) => (w, h),
+            // keep the aspect ratio if only one side is given
+            (Some(w), None) => (w, u32::MAX),
+            (None, Some(h)) => (u32::MAX, h),
+        };
+
+        match self.fit {
+            // renditions are meant for screens, so images are never scaled up
+            Fit::Contain | Fit::Cover if width <= target_width && height <= target_height => image,
+            Fit::Contain => image.resize(target_width, target_height, FilterType::Lanczos3),
+            // with a single side there is nothing to crop
+            Fit::Cover if target_width == u32::MAX || target_height == u32::MAX => {
+                image.resize(target_width, target_height, FilterType::Lanczos3)
+            }
+            Fit::Cover => image.resize_to_fill(
+                target_width.min(width),
+                target_height.min(height),
+                FilterType::Lanczos3,
+            ),
+            Fit::Fill => image.resize_exact(
+                target_width.min(width),
+                target_height.min(height),
+                FilterType::Lanczos3,
+            ),
+        }
+    }
+
+    /// Applies the transformation to the encoded original image.
+    pub fn apply(&self, content: &[u8]) -> crate::Result<(Vec<u8>, OutputFormat)> {
+        // the rendition is encoded without EXIF data, so the orientation has to be applied
+        let (mut image, source_format) = decode_upright(content).map_err(image_error)?;
+
+        if let Some((x, y, width, height)) = self.crop {
+            let (image_width, image_height) = image.dimensions();
+            if x >= image_width || y >= image_height {
+                return Err(Error::InvalidImageTransform(
+                    "crop is outside of the image".to_string(),
+                ));
+            }
+            image = image.crop_imm(x, y, width, height);
+        }
+        let image = self.resize(image);
+
+        // formats which webviews usually cannot display are transcoded to jpeg
+        let format = self.format.unwrap_or_else(|| {
+            source_format.map_or(OutputFormat::Jpeg, OutputFormat::from_image_format)
+        });
+        let mut output = Cursor::new(Vec::new());
+        match format {
+            OutputFormat::Jpeg => {
+                let encoder = JpegEncoder::new_with_quality(
+                    &mut output,
+                    self.quality.unwrap_or(DEFAULT_QUALITY),
+                );
+                // jpeg has no alpha channel
+                image.to_rgb8().write_with_encoder(encoder)
+            }
+            OutputFormat::Png => image.write_to(&mut output, ImageFormat::Png),
+            OutputFormat::Webp => image.to_rgba8().write_to(&mut output, ImageFormat::WebP),
+        }
+        .map_err(image_error)?;
+
+        Ok((output.into_inner(), format))
+    }
+}
+
+fn image_error(err: image::ImageError) -> Error {
+    Error::ImageTransform(err.to_string())
+}
+
+/// Disk cache for transformed images. The least recently used entries are removed once the
+/// cache exceeds its maximum size.
+pub(crate) struct RenditionCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Total size of the entries, computed when it is needed first
+    bytes: Mutex<Option<u64>>,
+}
+
+impl RenditionCache {
+    pub fn new(dir: &Path, max_bytes: u64) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            bytes: Mutex::new(None),
+        }
+    }
+
+    /// Returns a cached rendition, the format is detected from the content.
+    pub fn get(&self, key: &str) -> Option<(Vec<u8>, OutputFormat)> {
+        let content = self.read(key)?;
+        let format = OutputFormat::from_image_format(image::guess_format(&content).ok()?);
+        self.touch(key);
+        Some((content, format))
+    }
+
+    pub fn read(&self, key: &str) -> Option<Vec<u8>> {
+        fs::read(self.dir.join(key)).ok()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.dir.join(key).is_file()
+    }
+
+    /// Marks an entry as used, the modification time is the last access time of the entry.
+    pub fn touch(&self, key: &str) {
+        let _ = fs::File::options()
+            .write(true)
+            .open(self.dir.join(key))
+            .and_then(|file| file.set_modified(SystemTime::now()));
+    }
+
+    pub fn remove(&self, key: &str) -> bool {
+        let removed = fs::remove_file(self.dir.join(key)).is_ok();
+        if removed {
+            *self.bytes.lock().unwrap() = None;
+        }
+        removed
+    }
+
+    /// Removes all entries.
+    pub fn purge(&self) {
+        for (key, _, _) in self.entries() {
+            let _ = fs::remove_file(self.dir.join(key));
+        }
+        *self.bytes.lock().unwrap() = None;
+    }
+
+    /// Key, size and last access time of all entries.
+    pub fn entries(&self) -> Vec<(String, u64, SystemTime)> {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        dir.filter_map(Result::ok)
+            .filter_map(|entry| {
+                let metadata = entry
+                    .metadata()
+                    .ok()
+                    .filter(|metadata| metadata.is_file())?;
+                let key = entry.file_name().to_str()?.to_string();
+                // temporary files of entries which are being written
+                if key.starts_with('.') {
+                    return None;
+                }
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                Some((key, metadata.len(), modified))
+            })
+            .collect()
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Number and total size of the entries.
+    pub fn stats(&self) -> (usize, u64) {
+        let entries = self.entries();
+        let bytes = entries.iter().map(|(_, len, _)| len).sum();
+        *self.bytes.lock().unwrap() = Some(bytes);
+        (entries.len(), bytes)
+    }
+
+    /// Failing to cache a rendition is not an error, it is created again next time.
+    pub fn put(&self, key: &str, content: &[u8]) {
+        let path = self.dir.join(key);
+        let result = fs::create_dir_all(&self.dir)
+            .and_then(|_| tempfile::NamedTempFile::new_in(&self.dir))
+            .and_then(|mut file| {
+                file.write_all(content)?;
+                file.persist(&path).map_err(|err| err.error)
+            });
+        if let Err(err) = result {
+            warn!("unable to cache {}: {err}", path.to_string_lossy());
+            return;
+        }
+
+        let mut bytes = self.bytes.lock().unwrap();
+        let total = match *bytes {
+            Some(total) => total + content.len() as u64,
+            None => self.entries().iter().map(|(_, len, _)| len).sum(),
+        };
+        *bytes = Some(if total > self.max_bytes {
+            self.evict()
+        } else {
+            total
+        });
+    }
+
+    /// Removes the least recently used entries until the cache is below 90% of its maximum size,
+    /// so not every new entry has to evict another one. Returns the remaining size.
+    fn evict(&self) -> u64 {
+        let mut entries = self.entries();
+        entries.sort_by_key(|(_, _, last_used)| *last_used);
+
+        let target = self.max_bytes / 10 * 9;
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        for (key, len, _) in entries {
+            if total <= target {
+                break;
+            }
+            if fs::remove_file(self.dir.join(key)).is_ok() {
+                total -= len;
+            }
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn encoded_image(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([200, 0, 0])));
+        let mut content = Cursor::new(Vec::new());
+        image.write_to(&mut content, ImageFormat::Png).unwrap();
+        content.into_inner()
+    }
+
+    #[test]
+    fn test_from_query() {
+        assert_eq!(ImageTransform::from_query("").unwrap(), None);
+        assert_eq!(ImageTransform::from_query("v=2").unwrap(), None);
+        assert_eq!(ImageTransform::from_query("upright=0").unwrap(), None);
+        assert_eq!(
+            ImageTransform::from_query("upright=1").unwrap(),
+            Some(ImageTransform::default())
+        );
+
+        let transform = ImageTransform::from_query("w=1600&h=1200&fit=cover&format=jpeg&q=80")
+            .unwrap()
+            .unwrap();
+        assert_eq!(transform.width, Some(1600));
+        assert_eq!(transform.height, Some(1200));
+        assert_eq!(transform.fit, Fit::Cover);
+        assert_eq!(transform.format, Some(OutputFormat::Jpeg));
+        assert_eq!(transform.quality, Some(80));
+
+        let transform = ImageTransform::from_query("crop=10%2C20%2C30%2C40")
+            .unwrap()
+            .unwrap();
+        assert_eq!(transform.crop, Some((10, 20, 30, 40)));
+
+        assert!(ImageTransform::from_query("w=abc").is_err());
+        assert!(ImageTransform::from_query("w=0").is_err());
+        assert!(ImageTransform::from_query("q=101").is_err());
+        assert!(ImageTransform::from_query("format=webp&q=80").is_err());
+        assert!(ImageTransform::from_query("fit=stretch").is_err());
+        assert!(ImageTransform::from_query("crop=1,2,3").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let content = encoded_image(400, 200);
+
+        let transform = ImageTransform::from_query("w=100&h=100").unwrap().unwrap();
+        let (output, format) = transform.apply(&content).unwrap();
+        assert_eq!(format, OutputFormat::Png);
+        assert_eq!(
+            image::load_from_memory(&output).unwrap().dimensions(),
+            (100, 50)
+        );
+
+        let transform = ImageTransform::from_query("w=100&h=100&fit=cover&format=jpeg")
+            .unwrap()
+            .unwrap();
+        let (output, format) = transform.apply(&content).unwrap();
+        assert_eq!(format, OutputFormat::Jpeg);
+        assert_eq!(
+            image::load_from_memory(&output).unwrap().dimensions(),
+            (100, 100)
+        );
+
+        // never scaled up
+        for query in ["w=1000", "w=1000&fit=cover", "h=1000&fit=cover"] {
+            let transform = ImageTransform::from_query(query).unwrap().unwrap();
+            let (output, _) = transform.apply(&content).unwrap();
+            assert_eq!(
+                image::load_from_memory(&output).unwrap().dimensions(),
+                (400, 200)
+            );
+        }
+        let transform = ImageTransform::from_query("w=300&h=1000&fit=cover")
+            .unwrap()
+            .unwrap();
+        let (output, _) = transform.apply(&content).unwrap();
+        assert_eq!(
+            image::load_from_memory(&output).unwrap().dimensions(),
+            (300, 200)
+        );
+
+        let transform = ImageTransform::from_query("crop=350,0,100,100&format=webp")
+            .unwrap()
+            .unwrap();
+        let (output, _) = transform.apply(&content).unwrap();
+        assert_eq!(
+            image::load_from_memory(&output).unwrap().dimensions(),
+            (50, 100)
+        );
+    }
+
+    #[test]
+    fn test_rendition_cache_evicts() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = RenditionCache::new(temp.path(), 100);
+
+        cache.put("a", &[0; 60]);
+        let past = SystemTime::now() - std::time::Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open(temp.path().join("a"))
+            .and_then(|file| file.set_modified(past))
+            .unwrap();
+        cache.put("b", &[0; 60]);
+        assert_eq!(cache.stats(), (1, 60));
+        assert!(cache.contains("b"));
+
+        cache.purge();
+        assert_eq!(cache.stats(), (0, 0));
+    }
+}
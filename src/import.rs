@@ -0,0 +1,312 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Local, NaiveDateTime,
+};
+use log::warn;
+use walkdir::WalkDir;
+
+use crate::{
+    content_hash::{hash_file, HashIndex},
+    directory_reader::FileFilter,
+    exif_metadata::date_taken,
+    file_operations::{resolve_album_path, transfer_image, TransferMode},
+    jobs::Job,
+    media_type,
+    walkdir_reader::{read_image_info, walk_files},
+    CollisionPolicy, Config, ContentSniffing, Error, ImportFailure, ImportProgress, ImportReport,
+    ImportSkipped,
+};
+
+pub(crate) const DEFAULT_FOLDER_TEMPLATE: &str = "%Y/%m";
+
+pub(crate) const IMPORT_PROGRESS_EVENT: &str = "medialibrary://import-progress";
+pub(crate) const IMPORT_FINISHED_EVENT: &str = "medialibrary://import-finished";
+
+pub(crate) struct ImportOptions<'a> {
+    pub job: &'a Job,
+    pub config: &'a Config,
+    /// Caches the hashes of the library files between imports
+    pub index: &'a HashIndex,
+    /// The directory the folder template is applied to
+    pub target_dir: &'a Path,
+    pub folder_template: &'a str,
+    pub mode: TransferMode,
+    pub collision_policy: CollisionPolicy,
+}
+
+pub(crate) fn validate_folder_template(template: &str) -> crate::Result<()> {
+    if StrftimeItems::new(template).any(|item| matches!(item, Item::Error)) {
+        return Err(Error::InvalidFolderTemplate(template.to_string()));
+    }
+    Ok(())
+}
+
+/// The date the image was taken (EXIF) with the file's modification time as fallback.
+fn capture_date(path: &Path) -> Option<NaiveDateTime> {
+    date_taken(path).or_else(|| {
+        let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+        let modified: DateTime<Local> = modified.into();
+        Some(modified.naive_local())
+    })
+}
+
+/// Walks the image files below `dir` with the walk options of the config and the file filter
+/// registered with the plugin builder, they are counted by the job and the walk stops once the
+/// job is cancelled.
+pub(crate) fn image_files<'a>(
+    dir: &Path,
+    config: &'a Config,
+    filter: Option<&'a FileFilter>,
+    job: &'a Job,
+) -> impl Iterator<Item = (PathBuf, u64)> + 'a {
+    walk_files(WalkDir::new(dir).sort_by_file_name(), config, Some(job))
+        .filter(|e| media_type::detect(e.path(), ContentSniffing::default()).is_some())
+        .filter_map(move |e| {
+            let metadata = e.metadata().ok()?;
+            if filter.is_some_and(|filter| !filter(e.path(), &metadata)) {
+                return None;
+            }
+            Some((e.into_path(), metadata.len()))
+        })
+        .inspect(|(path, len)| job.file_seen(path, *len))
+}
+
+/// Hashes all library files which have the same size as one of the files to import,
+/// files with a different size can not be duplicates.
+fn hash_library(
+    library_root: &Path,
+    exclude: &Path,
+    sizes: &HashSet<u64>,
+    options: &ImportOptions,
+) -> HashMap<String, PathBuf> {
+    // files hidden by the filter exist nonetheless
+    image_files(library_root, options.config, None, options.job)
+        .filter(|(path, len)| sizes.contains(len) && !path.starts_with(exclude))
+        .filter_map(|(path, _)| {
+            let hash =
+                std::fs::metadata(&path).and_then(|metadata| options.index.hash(&path, &metadata));
+            match hash {
+                Ok(hash) => Some((hash, path)),
+                Err(err) => {
+                    warn!("unable to hash {}: {err}", path.to_string_lossy());
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn import_file(path: &Path, options: &ImportOptions) -> crate::Result<PathBuf> {
+    // a made up date would file the image in the wrong folder
+    let date = capture_date(path)
+        .ok_or_else(|| Error::CaptureDateUnavailable(path.to_string_lossy().to_string()))?;
+    let folder = date.format(options.folder_template).to_string();
+    let target_dir = resolve_album_path(options.target_dir, Some(&folder))?;
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::InvalidFileName(path.to_string_lossy().to_string()))?;
+
+    transfer_image(
+        path,
+        &target_dir,
+        file_name,
+        options.collision_policy,
+        options.mode,
+    )
+}
+
+/// Imports all images below `folder` into `options.target_dir`, skipping files whose content
+/// already exists somewhere below `library_root`.
+pub(crate) fn import_folder<F>(
+    folder: &Path,
+    library_root: &Path,
+    options: ImportOptions,
+    mut on_progress: F,
+) -> crate::Result<ImportReport>
+where
+    F: FnMut(&ImportProgress),
+{
+    validate_folder_template(options.folder_template)?;
+
+    let job = options.job;
+    let candidates: Vec<(PathBuf, u64)> = image_files(folder, options.config, None, job).collect();
+    let sizes = candidates.iter().map(|(_, len)| *len).collect();
+    let mut known_hashes = hash_library(library_root, folder, &sizes, &options);
+    options.index.save_or_log();
+
+    let mut report = ImportReport {
+        job_id: job.id(),
+        ..Default::default()
+    };
+
+    let total = candidates.len();
+    for (processed, (path, _)) in candidates.into_iter().enumerate() {
+        // files which were imported already stay in the library
+        if job.is_cancelled() {
+            report.cancelled = true;
+            return Ok(report);
+        }
+
+        on_progress(&ImportProgress {
+            job_id: job.id(),
+            total,
+            processed,
+            current_path: path.to_string_lossy().to_string(),
+        });
+
+        let hash = match hash_file(&path) {
+            Ok(hash) => hash,
+            Err(err) => {
+                report.failed.push(failure(&path, err.into()));
+                continue;
+            }
+        };
+
+        if let Some(existing) = known_hashes.get(&hash) {
+            report.skipped.push(ImportSkipped {
+                path: path.to_string_lossy().to_string(),
+                duplicate_of: existing.to_string_lossy().to_string(),
+            });
+            continue;
+        }
+
+        match import_file(&path, &options) {
+            Ok(target) => {
+                if let Some(info) = read_image_info(&target) {
+                    report.imported.push(info);
+                }
+                // the same image may be contained more than once in the imported folder
+                known_hashes.insert(hash, target);
+            }
+            Err(err) => report.failed.push(failure(&path, err)),
+        }
+    }
+
+    on_progress(&ImportProgress {
+        job_id: job.id(),
+        total,
+        processed: total,
+        current_path: String::new(),
+    });
+
+    Ok(report)
+}
+
+fn failure(path: &Path, error: Error) -> ImportFailure {
+    ImportFailure {
+        path: path.to_string_lossy().to_string(),
+        error: error.to_string(),
+    }
+}
+
+/// Convenience to use an `Option<&str>` template with the default fallback.
+pub(crate) fn folder_template(template: Option<&str>) -> &str {
+    template
+        .filter(|template| !template.is_empty())
+        .unwrap_or(DEFAULT_FOLDER_TEMPLATE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jobs::Jobs, JobKind};
+    use std::fs;
+
+    #[test]
+    fn test_validate_folder_template() {
+        assert!(validate_folder_template("%Y/%m").is_ok());
+        assert!(validate_folder_template("%Y/%m-%d Imports").is_ok());
+        assert!(validate_folder_template("%Y/%Q").is_err());
+    }
+
+    #[test]
+    fn test_import_skips_duplicates() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let card = dir.join("card");
+        let library = dir.join("library");
+        fs::create_dir_all(card.join("DCIM")).unwrap();
+        fs::create_dir_all(&library).unwrap();
+
+        fs::write(library.join("existing.jpg"), b"existing").unwrap();
+        fs::write(card.join("DCIM").join("a.jpg"), b"existing").unwrap();
+        fs::write(card.join("DCIM").join("b.jpg"), b"new").unwrap();
+        fs::write(card.join("DCIM").join("c.jpg"), b"new").unwrap();
+        fs::write(card.join("DCIM").join("notes.txt"), b"new").unwrap();
+
+        let mut progress = Vec::new();
+        let job = Jobs::default()
+            .start(None, JobKind::Import, |_| {})
+            .unwrap();
+        let report = import_folder(
+            &card,
+            &library,
+            ImportOptions {
+                job: &job,
+                config: &Config::default(),
+                index: &HashIndex::load(None),
+                target_dir: &library,
+                folder_template: "imported",
+                mode: TransferMode::Copy,
+                collision_policy: CollisionPolicy::KeepBoth,
+            },
+            |p| progress.push(p.processed),
+        )
+        .unwrap();
+
+        assert_eq!(report.imported.len(), 1);
+        assert_eq!(report.skipped.len(), 2);
+        assert!(report.failed.is_empty());
+        assert!(library.join("imported").join("b.jpg").exists());
+        assert_eq!(progress, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_image_files_uses_the_walk_options() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        fs::create_dir_all(dir.join("a").join("b")).unwrap();
+        for path in ["1.jpg", "2.png", ".hidden/3.jpg", "a/4.jpg", "a/b/5.jpg"] {
+            fs::write(dir.join(path), b"image").unwrap();
+        }
+
+        let files = |config: &Config| {
+            let job = Jobs::default()
+                .start(None, JobKind::Listing, |_| {})
+                .unwrap();
+            image_files(dir, config, None, &job)
+                .map(|(path, _)| path.strip_prefix(dir).unwrap().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+
+        let config = Config {
+            allowed_extensions: Some(vec!["jpg".to_string()]),
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            files(&config),
+            vec![PathBuf::from("1.jpg"), PathBuf::from("a/4.jpg")]
+        );
+
+        let config = Config {
+            include_hidden: true,
+            ..Default::default()
+        };
+        assert_eq!(files(&config).len(), 5);
+    }
+
+    #[test]
+    fn test_folder_template_default() {
+        assert_eq!(folder_template(None), DEFAULT_FOLDER_TEMPLATE);
+        assert_eq!(folder_template(Some("")), DEFAULT_FOLDER_TEMPLATE);
+        assert_eq!(folder_template(Some("%Y")), "%Y");
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{Error, JobKind, JobProgress};
+
+pub(crate) const JOB_PROGRESS_EVENT: &str = "medialibrary://job-progress";
+
+/// Minimum time between two progress events of a job.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+type ProgressCallback = Box<dyn Fn(&JobProgress) + Send + Sync>;
+
+/// The running jobs which can be cancelled by their id.
+#[derive(Clone, Default)]
+pub(crate) struct Jobs {
+    running: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+}
+
+impl Jobs {
+    /// Starts a job, jobs without an id report progress but can not be cancelled.
+    ///
+    /// Fails with `Error::JobAlreadyRunning` if a running job has the same id.
+    pub fn start<F>(&self, id: Option<String>, kind: JobKind, on_progress: F) -> crate::Result<Job>
+    where
+        F: Fn(&JobProgress) + Send + Sync + 'static,
+    {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        if let Some(id) = &id {
+            let mut running = self.running.lock().unwrap();
+            if running.contains_key(id) {
+                return Err(Error::JobAlreadyRunning(id.clone()));
+            }
+            running.insert(id.clone(), cancelled.clone());
+        }
+
+        Ok(Job(Arc::new(JobInner {
+            jobs: self.clone(),
+            cancelled,
+            on_progress: Box::new(on_progress),
+            state: Mutex::new(JobState {
+                progress: JobProgress {
+                    job_id: id,
+                    kind,
+                    ..Default::default()
+                },
+                last_emit: None,
+            }),
+        })))
+    }
+
+    /// Requests a running job to stop, returns `false` if there is no job with this id.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.running.lock().unwrap().get(id) {
+            Some(cancelled) => {
+                cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Handle of a long running operation, shared by the parts of the operation.
+#[derive(Clone)]
+pub(crate) struct Job(Arc<JobInner>);
+
+struct JobInner {
+    jobs: Jobs,
+    cancelled: Arc<AtomicBool>,
+    on_progress: ProgressCallback,
+    state: Mutex<JobState>,
+}
+
+struct JobState {
+    progress: JobProgress,
+    last_emit: Option<Instant>,
+}
+
+impl Job {
+    pub fn id(&self) -> Option<String> {
+        self.0.state.lock().unwrap().progress.job_id.clone()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns `Error::JobCancelled` once the job was cancelled.
+    pub fn check(&self) -> crate::Result<()> {
+        if self.is_cancelled() {
+            Err(Error::JobCancelled(self.id().unwrap_or_default()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Counts a file found by the job, progress events are emitted at most every 200ms.
+    pub fn file_seen(&self, path: &Path, bytes: u64) {
+        let mut state = self.0.state.lock().unwrap();
+        state.progress.files_seen += 1;
+        state.progress.bytes += bytes;
+        if let Some(folder) = path.parent() {
+            if folder.as_os_str() != state.progress.current_folder.as_str() {
+                state.progress.current_folder = folder.to_string_lossy().to_string();
+            }
+        }
+
+        let now = Instant::now();
+        if state.last_emit.map_or(true, |last_emit| {
+            now.duration_since(last_emit) >= PROGRESS_INTERVAL
+        }) {
+            state.last_emit = Some(now);
+            (self.0.on_progress)(&state.progress);
+        }
+    }
+}
+
+impl Drop for JobInner {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        state.progress.finished = true;
+        state.progress.cancelled = self.cancelled.load(Ordering::Relaxed);
+        (self.on_progress)(&state.progress);
+
+        if let Some(id) = &state.progress.job_id {
+            self.jobs.running.lock().unwrap().remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel() {
+        let jobs = Jobs::default();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let job = {
+            let events = events.clone();
+            jobs.start(Some("scan".into()), JobKind::Listing, move |progress| {
+                events.lock().unwrap().push(progress.clone())
+            })
+            .unwrap()
+        };
+
+        job.file_seen(Path::new("/pictures/a.jpg"), 10);
+        job.file_seen(Path::new("/pictures/b.jpg"), 20);
+        assert!(job.check().is_ok());
+        assert!(!jobs.cancel("other"));
+        assert!(jobs.cancel("scan"));
+        assert!(matches!(job.check(), Err(Error::JobCancelled(id)) if id == "scan"));
+        // a second job can not take over the id of the running one
+        assert!(matches!(
+            jobs.start(Some("scan".into()), JobKind::Hashing, |_| {}),
+            Err(Error::JobAlreadyRunning(id)) if id == "scan"
+        ));
+        assert!(jobs.cancel("scan"));
+
+        drop(job);
+        // the job is unregistered once it finished
+        assert!(!jobs.cancel("scan"));
+
+        let events = events.lock().unwrap();
+        // the second file is within the progress interval
+        assert_eq!(events.len(), 2);
+        let last = events.last().unwrap();
+        assert!(last.finished && last.cancelled);
+        assert_eq!((last.files_seen, last.bytes), (2, 30));
+        assert_eq!(last.current_folder, "/pictures");
+    }
+}
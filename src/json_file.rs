@@ -0,0 +1,33 @@
+use std::{fs, io::Write, path::Path};
+
+use log::error;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Reads a json file the plugin persisted, missing or broken files result in the default value.
+pub(crate) fn load<T: DeserializeOwned + Default>(file: &Path) -> T {
+    match fs::read(file) {
+        Ok(content) => match serde_json::from_slice(&content) {
+            Ok(data) => data,
+            Err(err) => {
+                error!("unable to parse {}: {err}", file.to_string_lossy());
+                // keep the broken file, it would be overwritten by the next change
+                let _ = fs::rename(file, file.with_extension("json.bak"));
+                T::default()
+            }
+        },
+        Err(_) => T::default(),
+    }
+}
+
+/// Writes the data to a temporary file first so a crash never leaves a truncated file behind.
+pub(crate) fn save<T: Serialize>(file: &Path, data: &T) -> crate::Result<()> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp_file = file.with_extension("json.tmp");
+    let mut out = fs::File::create(&temp_file)?;
+    out.write_all(&serde_json::to_vec(data)?)?;
+    out.sync_all()?;
+    fs::rename(temp_file, file)?;
+    Ok(())
+}
@@ -1,9 +1,11 @@
+use std::collections::HashMap;
+
 use http::{header::*, status::StatusCode};
 use log::error;
 pub use models::*;
 use tauri::{
     ipc::ScopeObject,
-    plugin::{Builder, TauriPlugin},
+    plugin::{Builder as PluginBuilder, TauriPlugin},
     utils::acl::Value,
     AppHandle, Manager, Runtime,
 };
@@ -13,16 +15,49 @@ mod desktop;
 #[cfg(mobile)]
 mod mobile;
 
+#[cfg(desktop)]
+mod access;
+mod blocking;
 mod commands;
+mod config;
+#[cfg(desktop)]
+mod content_hash;
 mod directory_reader;
+#[cfg(desktop)]
+mod duplicates;
 mod error;
+#[cfg(desktop)]
+mod exif_metadata;
+mod extra_formats;
+#[cfg(desktop)]
+mod file_operations;
+#[cfg(desktop)]
+mod import;
+// only the registry is used on mobile, the platform lists the images itself
+#[cfg_attr(mobile, allow(dead_code))]
+mod jobs;
+mod json_file;
+mod media_type;
 mod models;
+mod orientation;
+#[cfg(all(desktop, feature = "portal"))]
+mod portal;
 mod protocol_handler;
+mod raw;
 mod scope;
+#[cfg(desktop)]
+mod similarity;
 
+#[cfg(desktop)]
+mod thumbnail_cache;
+#[cfg(desktop)]
+mod thumbnail_memory_cache;
 mod thumbnail_provider;
+#[cfg(desktop)]
+mod thumbnail_queue;
 
 mod uri;
+mod user_data_store;
 #[cfg(feature = "xdg")]
 mod xdg_directory_reader;
 
@@ -32,11 +67,20 @@ mod amt_thumbnail_provider;
 #[cfg(feature = "thumb_cache")]
 mod thumbcache_thumbnail_provider;
 mod walkdir_reader;
+#[cfg(desktop)]
+mod xmp;
 
 mod image_protocol_handler;
+mod image_transform;
 mod thumbnail_protocol_handler;
 
-pub use error::{Error, Result};
+pub use config::Config;
+pub use directory_reader::{DirectoryReader, FileFilter};
+pub use error::{Error, ErrorKind, Result};
+pub use thumbnail_provider::{
+    default_thumbnail_providers, DecoderThumbnailProvider, PlaceholderThumbnailProvider,
+    ThumbnailProvider,
+};
 
 #[cfg(desktop)]
 use desktop::Medialibrary;
@@ -51,11 +95,15 @@ impl ScopeObject for scope::Entry {
         _app: &AppHandle<R>,
         raw: Value,
     ) -> std::result::Result<Self, Self::Error> {
-        match serde_json::from_value(raw.into()).map(|raw| match raw {
-            scope::EntryRaw::Object { source } => source,
-            scope::EntryRaw::Value(val) => val,
-        }) {
-            Ok(source) => Ok(Self { source }),
+        match serde_json::from_value(raw.into()) {
+            Ok(scope::EntryRaw::Object { source } | scope::EntryRaw::Value(source)) => Ok(Self {
+                source: Some(source),
+                import_folder: None,
+            }),
+            Ok(scope::EntryRaw::ImportFolder { import_folder }) => Ok(Self {
+                source: None,
+                import_folder: Some(import_folder),
+            }),
             Err(err) => Err(err.into()),
         }
     }
@@ -71,56 +119,198 @@ impl<R: Runtime, T: Manager<R>> crate::MedialibraryExt<R> for T {
     }
 }
 
+/// Directory for data the plugin persists, e.g. favorites and tags.
+fn plugin_data_dir<R: Runtime>(app: &AppHandle<R>) -> Option<std::path::PathBuf> {
+    match app.path().app_data_dir() {
+        Ok(dir) => Some(dir.join("medialibrary")),
+        Err(e) => {
+            error!("unable to resolve the app data dir: {e}");
+            None
+        }
+    }
+}
+
+/// Directory for data the plugin can recreate, e.g. transformed images. The config is passed in
+/// since the cache is set up before the config is managed by the app.
+#[cfg(desktop)]
+fn plugin_cache_dir<R: Runtime>(app: &AppHandle<R>, config: &Config) -> Option<std::path::PathBuf> {
+    if let Some(dir) = &config.cache_dir {
+        return Some(dir.clone());
+    }
+    match app.path().app_cache_dir() {
+        Ok(dir) => Some(dir.join("medialibrary")),
+        Err(e) => {
+            error!("unable to resolve the app cache dir: {e}");
+            None
+        }
+    }
+}
+
+/// Protocol requests are answered from spawned tasks, so a slow file does not delay the others.
+fn into_http_response(
+    response: std::result::Result<http::Response<Vec<u8>>, Box<dyn std::error::Error>>,
+) -> http::Response<Vec<u8>> {
+    response.unwrap_or_else(|e| {
+        error!("{e}");
+        error_response(e)
+    })
+}
+
 fn error_response(e: Box<dyn std::error::Error>) -> http::Response<Vec<u8>> {
+    let status = match e.downcast_ref::<Error>().map(Error::kind) {
+        Some(ErrorKind::NotFound) => StatusCode::NOT_FOUND,
+        Some(ErrorKind::Forbidden | ErrorKind::PermissionDenied) => StatusCode::FORBIDDEN,
+        Some(ErrorKind::InvalidUri | ErrorKind::InvalidArgument) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
     http::Response::builder()
-        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .status(status)
         .header(CONTENT_TYPE, "text/plain")
         .body(e.to_string().into_bytes())
         .unwrap()
 }
 
+/// Builds the plugin with extensions registered by the app.
+pub struct Builder {
+    thumbnail_providers: Vec<Box<dyn ThumbnailProvider>>,
+    default_thumbnail_providers: bool,
+    directory_readers: HashMap<MediaLibrarySource, Box<dyn DirectoryReader + Send + Sync>>,
+    file_filter: Option<FileFilter>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            thumbnail_providers: Vec::new(),
+            default_thumbnail_providers: true,
+            directory_readers: HashMap::new(),
+            file_filter: None,
+        }
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a thumbnail provider (desktop only). Providers are tried in the order they are added,
+    /// before the [`default_thumbnail_providers`].
+    pub fn thumbnail_provider<P: ThumbnailProvider + 'static>(mut self, provider: P) -> Self {
+        self.thumbnail_providers.push(Box::new(provider));
+        self
+    }
+
+    /// Only uses the providers added with [`Builder::thumbnail_provider`].
+    pub fn without_default_thumbnail_providers(mut self) -> Self {
+        self.default_thumbnail_providers = false;
+        self
+    }
+
+    /// Lists the source with a custom reader instead of the built-in one (desktop only).
+    /// The reader has to handle paging, sorting and the favorites and tags filters of the
+    /// request itself, favorites, tags and metadata of the returned items are added by the plugin.
+    pub fn directory_reader<D>(mut self, source: MediaLibrarySource, reader: D) -> Self
+    where
+        D: DirectoryReader + Send + Sync + 'static,
+    {
+        self.directory_readers.insert(source, Box::new(reader));
+        self
+    }
+
+    /// Hides files from the listings of the built-in readers and from the scans (duplicates,
+    /// similar images and thumbnail pregeneration), only files for which the predicate returns
+    /// `true` are included (desktop only).
+    pub fn file_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&std::path::Path, &std::fs::Metadata) -> bool + Send + Sync + 'static,
+    {
+        self.file_filter = Some(std::sync::Arc::new(filter));
+        self
+    }
+
+    pub fn build<R: Runtime>(self) -> TauriPlugin<R, Option<Config>> {
+        build_plugin(self)
+    }
+}
+
 /// Initializes the plugin.
-pub fn init<R: Runtime>() -> TauriPlugin<R> {
-    Builder::new("medialibrary")
+pub fn init<R: Runtime>() -> TauriPlugin<R, Option<Config>> {
+    Builder::new().build()
+}
+
+fn build_plugin<R: Runtime>(builder: Builder) -> TauriPlugin<R, Option<Config>> {
+    PluginBuilder::<R, Option<Config>>::new("medialibrary")
         .invoke_handler(tauri::generate_handler![
             commands::get_images,
+            commands::get_images_stream,
             commands::get_thumbnail,
             commands::get_available_sources,
             commands::request_permissions,
+            commands::check_permissions,
             commands::get_image,
-            commands::delete_image
+            commands::delete_image,
+            commands::move_image,
+            commands::copy_image,
+            commands::rename_image,
+            commands::save_image,
+            commands::import_folder,
+            commands::set_favorite,
+            commands::add_tags,
+            commands::remove_tags,
+            commands::list_tags,
+            commands::get_xmp_metadata,
+            commands::set_xmp_metadata,
+            commands::find_duplicates,
+            commands::find_similar,
+            commands::find_similar_groups,
+            commands::cancel_job,
+            commands::prioritize_thumbnails,
+            commands::get_cache_stats,
+            commands::purge_thumbnail_cache,
+            commands::invalidate_thumbnails
         ])
-        .setup(|app, api| {
+        .setup(move |app, api| {
+            let config = api.config().clone().unwrap_or_default();
+
             #[cfg(mobile)]
-            let medialibrary = mobile::init(app, api)?;
+            let medialibrary = {
+                // thumbnails are created by the platform
+                drop(builder);
+                mobile::init(app, api)?
+            };
             #[cfg(desktop)]
-            let medialibrary = desktop::init(app, api)?;
+            let medialibrary = {
+                let mut thumbnail_providers = builder.thumbnail_providers;
+                if builder.default_thumbnail_providers {
+                    thumbnail_providers.extend(default_thumbnail_providers(&config));
+                }
+                let extensions = desktop::Extensions {
+                    thumbnails: thumbnail_provider::ThumbnailProviders::new(thumbnail_providers),
+                    directory_readers: builder.directory_readers,
+                    file_filter: builder.file_filter,
+                };
+                desktop::init(app, api, &config, extensions)?
+            };
+            app.manage(blocking::BlockingPool::new(config.max_blocking_tasks));
+            app.manage(jobs::Jobs::default());
+            app.manage(config);
             app.manage(medialibrary);
             Ok(())
         })
         .register_asynchronous_uri_scheme_protocol("thumbnail", move |ctx, request, responder| {
-            match tauri::async_runtime::block_on(thumbnail_protocol_handler::get_response(
-                request,
-                ctx.app_handle(),
-            )) {
-                Ok(http_response) => responder.respond(http_response),
-                Err(e) => {
-                    error!("{e}");
-                    responder.respond(error_response(e))
-                }
-            }
+            let app = ctx.app_handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let response = thumbnail_protocol_handler::get_response(request, &app).await;
+                responder.respond(into_http_response(response));
+            });
         })
         .register_asynchronous_uri_scheme_protocol("image", move |ctx, request, responder| {
-            match tauri::async_runtime::block_on(image_protocol_handler::get_response(
-                request,
-                ctx.app_handle(),
-            )) {
-                Ok(http_response) => responder.respond(http_response),
-                Err(e) => {
-                    error!("{e}");
-                    responder.respond(error_response(e))
-                }
-            }
+            let app = ctx.app_handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let response = image_protocol_handler::get_response(request, &app).await;
+                responder.respond(into_http_response(response));
+            });
         })
         .build()
 }
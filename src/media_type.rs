@@ -0,0 +1,166 @@
+use std::{fs::File, io::Read, path::Path};
+
+use crate::{extra_formats::ExtraFormat, raw::RawFormat, ContentSniffing};
+
+/// Number of bytes read to detect the type, enough for the first IFD of RAW files.
+const SNIFF_LEN: u64 = 4096;
+
+/// Media type of a file and whether its extension belongs to a different type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct MediaType {
+    pub mime_type: &'static str,
+    pub extension_mismatch: bool,
+}
+
+/// Returns the mime type of the image file extension.
+pub(crate) fn from_extension(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    match ext.as_str() {
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "png" => Some("image/png"),
+        "gif" => Some("image/gif"),
+        "bmp" => Some("image/bmp"),
+        "webp" => Some("image/webp"),
+        "tiff" => Some("image/tiff"),
+        "heic" => Some("image/heic"),
+        "heif" => Some("image/heif"),
+        "avif" => Some("image/avif"),
+        "jxl" => Some("image/jxl"),
+        _ => RawFormat::from_extension(&ext).map(|raw| raw.mime_type()),
+    }
+}
+
+/// Detects the mime type from the signature at the start of the content.
+pub(crate) fn sniff(content: &[u8]) -> Option<&'static str> {
+    if let Some(format) = ExtraFormat::detect(content) {
+        return Some(format.mime_type());
+    }
+    if let Some(format) = RawFormat::detect(content) {
+        return Some(format.mime_type());
+    }
+
+    if content.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if content.starts_with(b"BM") && content.len() >= 14 {
+        Some("image/bmp")
+    } else if content.starts_with(b"RIFF") && content.get(8..12) == Some(b"WEBP") {
+        Some("image/webp")
+    } else if content.starts_with(b"II\x2a\0") || content.starts_with(b"MM\0\x2a") {
+        Some("image/tiff")
+    } else {
+        None
+    }
+}
+
+fn sniff_file(path: &Path) -> Option<&'static str> {
+    let mut content = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut content)
+        .ok()?;
+    sniff(&content)
+}
+
+/// Whether the extension fits the detected type. HEIC is only a brand of HEIF, and RAW files
+/// which are not recognized by their first IFD are detected as plain TIFF.
+fn is_compatible(detected: &str, extension: &str) -> bool {
+    let is_heif = |mime_type: &str| matches!(mime_type, "image/heic" | "image/heif");
+    let is_tiff = |mime_type: &str| {
+        matches!(
+            mime_type,
+            "image/tiff"
+                | "image/x-canon-cr2"
+                | "image/x-nikon-nef"
+                | "image/x-sony-arw"
+                | "image/x-adobe-dng"
+        )
+    };
+
+    detected == extension
+        || (is_heif(detected) && is_heif(extension))
+        || (detected == "image/tiff" && is_tiff(extension))
+}
+
+/// Detects the media type of a file, `None` if it is not an image.
+pub(crate) fn detect(path: &Path, sniffing: ContentSniffing) -> Option<MediaType> {
+    let extension = from_extension(path);
+    let detected = match (sniffing, extension) {
+        (ContentSniffing::Never, _) | (ContentSniffing::Fallback, Some(_)) => None,
+        _ => sniff_file(path),
+    };
+
+    match (detected, extension) {
+        (Some(detected), Some(extension)) if is_compatible(detected, extension) => {
+            Some(MediaType {
+                mime_type: extension,
+                extension_mismatch: false,
+            })
+        }
+        // files without an extension are not considered to be misnamed
+        (Some(detected), _) => Some(MediaType {
+            mime_type: detected,
+            extension_mismatch: path.extension().is_some(),
+        }),
+        (None, Some(extension)) => Some(MediaType {
+            mime_type: extension,
+            extension_mismatch: false,
+        }),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_detect() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+        fs::write(dir.join("image.png"), png).unwrap();
+        fs::write(dir.join("image.jpg"), png).unwrap();
+        fs::write(dir.join("image"), png).unwrap();
+        fs::write(dir.join("notes.txt"), b"some text").unwrap();
+        fs::write(dir.join("empty.jpg"), b"").unwrap();
+
+        let detect = |name: &str, sniffing| detect(&dir.join(name), sniffing);
+        let media_type = |mime_type, extension_mismatch| {
+            Some(MediaType {
+                mime_type,
+                extension_mismatch,
+            })
+        };
+
+        for sniffing in [ContentSniffing::Fallback, ContentSniffing::Always] {
+            assert_eq!(
+                detect("image.png", sniffing),
+                media_type("image/png", false)
+            );
+            assert_eq!(detect("image", sniffing), media_type("image/png", false));
+            assert_eq!(detect("notes.txt", sniffing), None);
+            // the extension is used if the content is not recognized
+            assert_eq!(
+                detect("empty.jpg", sniffing),
+                media_type("image/jpeg", false)
+            );
+        }
+
+        assert_eq!(
+            detect("image.jpg", ContentSniffing::Always),
+            media_type("image/png", true)
+        );
+        assert_eq!(
+            detect("image.jpg", ContentSniffing::Fallback),
+            media_type("image/jpeg", false)
+        );
+        assert_eq!(detect("image", ContentSniffing::Never), None);
+    }
+}
@@ -5,7 +5,9 @@ use tauri::{
     AppHandle, Runtime,
 };
 
+use crate::image_transform::RenditionCache;
 use crate::models::*;
+use crate::user_data_store::UserDataStore;
 use base64::Engine;
 
 #[cfg(target_os = "ios")]
@@ -13,7 +15,7 @@ tauri::ios_plugin_binding!(init_plugin_medialibrary);
 
 // initializes the Kotlin or Swift plugin classes
 pub fn init<R: Runtime, C: DeserializeOwned>(
-    _app: &AppHandle<R>,
+    app: &AppHandle<R>,
     api: PluginApi<R, C>,
 ) -> crate::Result<Medialibrary<R>> {
     #[cfg(target_os = "android")]
@@ -21,47 +23,242 @@ pub fn init<R: Runtime, C: DeserializeOwned>(
         api.register_android_plugin("de.universalappfactory.medialibrary", "MediaLibraryPlugin")?;
     #[cfg(target_os = "ios")]
     let handle = api.register_ios_plugin(init_plugin_medialibrary)?;
-    Ok(Medialibrary(handle))
+    Ok(Medialibrary {
+        handle,
+        store: UserDataStore::load(crate::plugin_data_dir(app).as_deref()),
+    })
+}
+
+/// Arguments of the `getImages` command, the optional content uris restrict the query
+/// to the images matching the favorites/tags filter.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GetImagesArgs<'a> {
+    #[serde(flatten)]
+    request: &'a GetLibraryContentRequest,
+    content_uris: Option<Vec<String>>,
 }
 
 /// Access to the medialibrary APIs.
-pub struct Medialibrary<R: Runtime>(PluginHandle<R>);
+pub struct Medialibrary<R: Runtime> {
+    handle: PluginHandle<R>,
+    store: UserDataStore,
+}
 
 impl<R: Runtime> Medialibrary<R> {
     pub fn get_images(&self, request: GetLibraryContentRequest) -> crate::Result<GetImagesResult> {
-        self.0
-            .run_mobile_plugin("getImages", request)
-            .map_err(Into::into)
+        // the media store ids are used as media identity
+        let content_uris = request.has_user_data_filter().then(|| {
+            self.store
+                .find(
+                    request.favorites_only.unwrap_or_default(),
+                    request.tags.as_deref().unwrap_or_default(),
+                )
+                .into_iter()
+                .collect::<Vec<_>>()
+        });
+        if content_uris.as_ref().is_some_and(|uris| uris.is_empty()) {
+            return Ok(GetImagesResult::default());
+        }
+
+        let result: GetImagesResult = self.handle.run_mobile_plugin(
+            "getImages",
+            GetImagesArgs {
+                request: &request,
+                content_uris,
+            },
+        )?;
+        Ok(GetImagesResult {
+            items: result
+                .items
+                .into_iter()
+                .map(|item| self.with_item_user_data(item))
+                .collect(),
+        })
+    }
+
+    /// The media store is queried at once, the page is passed to `on_batch` in batches.
+    pub fn stream_images<F>(
+        &self,
+        request: GetLibraryContentRequest,
+        batch_size: usize,
+        mut on_batch: F,
+    ) -> crate::Result<usize>
+    where
+        F: FnMut(GetImagesResult) -> crate::Result<()>,
+    {
+        let items = self.get_images(request)?.items;
+        let total = items.len();
+        for batch in items.chunks(batch_size.max(1)) {
+            on_batch(GetImagesResult {
+                items: batch.to_vec(),
+            })?;
+        }
+        Ok(total)
     }
 
     pub fn get_image(&self, request: GetImageRequest) -> crate::Result<Option<ImageInfo>> {
-        self.0
-            .run_mobile_plugin("getImage", request)
-            .map_err(Into::into)
+        let image: Option<ImageInfo> = self.handle.run_mobile_plugin("getImage", request)?;
+        Ok(image.map(|item| self.with_item_user_data(item)))
+    }
+
+    fn with_item_user_data(&self, item: ImageInfo) -> ImageInfo {
+        match self.store.get(&item.content_uri) {
+            Some(data) => ImageInfo {
+                favorite: data.favorite,
+                tags: data.tags.into_iter().collect(),
+                ..item
+            },
+            None => item,
+        }
+    }
+
+    pub fn set_favorite(&self, uri: &str, favorite: bool) -> crate::Result<()> {
+        self.store.set_favorite(uri, favorite).map(|_| ())
+    }
+
+    pub fn add_tags(&self, uri: &str, tags: &[String]) -> crate::Result<Vec<String>> {
+        let data = self.store.add_tags(uri, tags)?;
+        Ok(data.tags.into_iter().collect())
+    }
+
+    pub fn remove_tags(&self, uri: &str, tags: &[String]) -> crate::Result<Vec<String>> {
+        let data = self.store.remove_tags(uri, tags)?;
+        Ok(data.tags.into_iter().collect())
+    }
+
+    pub fn list_tags(&self) -> crate::Result<Vec<TagInfo>> {
+        Ok(self.store.list_tags())
     }
 
     pub fn delete_image(&self, request: DeleteImageRequest) -> crate::Result<()> {
-        self.0
+        self.handle
             .run_mobile_plugin("executeRecoverableDeleteRequest", request)
             .map_err(Into::into)
     }
 
+    /// The media store exposes all images through a single collection, so every content uri
+    /// belongs to the default source unless it explicitly references the primary volume.
+    #[cfg_attr(not(target_os = "android"), allow(unused_variables))]
+    pub fn get_source_of_uri(&self, uri: &str) -> crate::Result<MediaLibrarySource> {
+        #[cfg(target_os = "android")]
+        if uri.contains("/external_primary/") {
+            return Ok(MediaLibrarySource::VolumeExternalPrimary);
+        }
+        Ok(MediaLibrarySource::default())
+    }
+
+    pub fn move_image(&self, request: TransferImageRequest) -> crate::Result<ImageInfo> {
+        self.handle
+            .run_mobile_plugin("moveImage", request)
+            .map_err(Into::into)
+    }
+
+    pub fn copy_image(&self, request: TransferImageRequest) -> crate::Result<ImageInfo> {
+        self.handle
+            .run_mobile_plugin("copyImage", request)
+            .map_err(Into::into)
+    }
+
+    pub fn rename_image(&self, request: RenameImageRequest) -> crate::Result<ImageInfo> {
+        self.handle
+            .run_mobile_plugin("renameImage", request)
+            .map_err(Into::into)
+    }
+
+    pub fn save_image(&self, request: SaveImageRequest) -> crate::Result<ImageInfo> {
+        self.handle
+            .run_mobile_plugin("saveImage", request)
+            .map_err(Into::into)
+    }
+
+    /// Thumbnails are created by the platform.
+    pub fn prioritize_thumbnails(&self, _uris: Vec<String>) -> crate::Result<()> {
+        Err(crate::Error::UnsupportedOperation(
+            "prioritize_thumbnails".to_string(),
+        ))
+    }
+
+    pub fn get_cache_stats(&self) -> crate::Result<CacheStats> {
+        Err(crate::Error::UnsupportedOperation(
+            "get_cache_stats".to_string(),
+        ))
+    }
+
+    pub fn purge_thumbnail_cache(&self) -> crate::Result<()> {
+        Err(crate::Error::UnsupportedOperation(
+            "purge_thumbnail_cache".to_string(),
+        ))
+    }
+
+    pub fn invalidate_thumbnails(&self, _uris: Vec<String>) -> crate::Result<()> {
+        Err(crate::Error::UnsupportedOperation(
+            "invalidate_thumbnails".to_string(),
+        ))
+    }
+
+    pub fn import_folder(&self, _request: ImportRequest) -> crate::Result<ImportReport> {
+        Err(crate::Error::UnsupportedOperation(
+            "import_folder".to_string(),
+        ))
+    }
+
+    pub fn find_duplicates(
+        &self,
+        _request: FindDuplicatesRequest,
+    ) -> crate::Result<FindDuplicatesResult> {
+        Err(crate::Error::UnsupportedOperation(
+            "find_duplicates".to_string(),
+        ))
+    }
+
+    pub fn find_similar(
+        &self,
+        _uri: &str,
+        _threshold: Option<u32>,
+    ) -> crate::Result<Vec<SimilarImage>> {
+        Err(crate::Error::UnsupportedOperation(
+            "find_similar".to_string(),
+        ))
+    }
+
+    pub fn find_similar_groups(
+        &self,
+        _request: FindSimilarGroupsRequest,
+    ) -> crate::Result<FindSimilarGroupsResult> {
+        Err(crate::Error::UnsupportedOperation(
+            "find_similar_groups".to_string(),
+        ))
+    }
+
+    pub fn get_xmp_metadata(&self, _uri: &str) -> crate::Result<XmpMetadata> {
+        Err(crate::Error::UnsupportedOperation(
+            "get_xmp_metadata".to_string(),
+        ))
+    }
+
+    pub fn set_xmp_metadata(&self, _request: SetXmpMetadataRequest) -> crate::Result<XmpMetadata> {
+        Err(crate::Error::UnsupportedOperation(
+            "set_xmp_metadata".to_string(),
+        ))
+    }
+
     pub fn check_permissions(&self) -> crate::Result<PermissionResponse> {
-        self.0
+        self.handle
             .run_mobile_plugin::<PermissionResponse>("checkPermissions", ())
             .map_err(Into::into)
     }
 
     pub async fn get_thumbnail(&self, uri: String) -> crate::Result<GetThumbnailResponse> {
         return self
-            .0
+            .handle
             .run_mobile_plugin("getThumbnailAsBase64", uri)
             .map_err(Into::into);
     }
 
     pub async fn get_thumbnail_data(&self, uri: String) -> crate::Result<Thumbnail> {
         let base64: Base64Response = self
-            .0
+            .handle
             .run_mobile_plugin(
                 "getThumbnailAsBase64",
                 uri.replace("thumbnail://localhost/", "content://"),
@@ -71,11 +268,21 @@ impl<R: Runtime> Medialibrary<R> {
         Ok(data.into())
     }
 
+    /// The media store does not expose a modification time through the plugin, so renditions
+    /// are not cached.
+    pub(crate) fn rendition_cache(&self) -> Option<&RenditionCache> {
+        None
+    }
+
+    pub fn get_image_version(&self, _uri: &str) -> Option<String> {
+        None
+    }
+
     pub async fn get_image_data(&self, uri: String) -> crate::Result<Image> {
         // ToDo: Maybe there is a better way to get the image data from android
         // Converting to base64 and decoding again may be inefficient
         let base64: Base64Response = self
-            .0
+            .handle
             .run_mobile_plugin(
                 "getImageAsBase64",
                 uri.replace("image://localhost/", "content://"),
@@ -86,11 +293,11 @@ impl<R: Runtime> Medialibrary<R> {
         Ok(data.into())
     }
 
-    pub fn request_permissions(
+    pub async fn request_permissions(
         &self,
         args: RequestPermissionsArgs,
     ) -> crate::Result<PermissionResponse> {
-        self.0
+        self.handle
             .run_mobile_plugin("requestPermissions", args)
             .map_err(Into::into)
     }
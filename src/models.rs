@@ -9,7 +9,7 @@ use std::{
 };
 use tauri::plugin::PermissionState;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum MediaLibrarySource {
     #[cfg(not(target_os = "android"))]
     PictureDir,
@@ -19,6 +19,19 @@ pub enum MediaLibrarySource {
     VolumeExternalPrimary,
 }
 
+/// How the type of a file is determined when listing images, reading the content costs
+/// opening every file.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ContentSniffing {
+    /// Only the file extension is used
+    Never,
+    /// The content is only read for files without a known image extension
+    #[default]
+    Fallback,
+    /// The content of every file is read, the extension is used if it is not recognized
+    Always,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 pub enum SortColumn {
     #[default]
@@ -47,6 +60,30 @@ pub enum MetaDataField {
     FileName,
     FileExtension,
     FileReadOnly,
+    /// XMP rating (xmp:Rating), -1 means rejected
+    Rating,
+    /// XMP color label (xmp:Label)
+    ColorLabel,
+    /// XMP keywords (dc:subject), comma separated
+    Keywords,
+    /// XMP caption (dc:description)
+    Description,
+    /// XMP title (dc:title)
+    Title,
+    /// EXIF camera manufacturer
+    CameraMake,
+    /// EXIF camera model
+    CameraModel,
+    /// EXIF lens model
+    LensModel,
+    /// Exposure time in seconds, e.g. "1/250"
+    ExposureTime,
+    /// Aperture, e.g. "2.8"
+    FNumber,
+    /// Focal length in millimeters
+    FocalLength,
+    /// ISO sensitivity
+    Iso,
 }
 
 impl Display for MediaLibrarySource {
@@ -81,6 +118,25 @@ pub struct GetLibraryContentRequest {
     pub sort_column: Option<SortColumn>,
     pub sort_direction: Option<SortDirection>,
     pub include_file_metadata: Option<bool>,
+    /// Read rating, label, keywords, description and title from XMP (desktop only)
+    pub include_xmp_metadata: Option<bool>,
+    /// Read the date taken and camera settings from EXIF, also of RAW files (desktop only)
+    pub include_exif_metadata: Option<bool>,
+    /// Detect the type of files by their content, defaults to `Fallback`
+    pub content_sniffing: Option<ContentSniffing>,
+    /// Only return images which are marked as favorite
+    pub favorites_only: Option<bool>,
+    /// Only return images which carry all of the given tags
+    pub tags: Option<Vec<String>>,
+    /// Optional id to receive progress events and to cancel the listing (desktop only)
+    pub job_id: Option<String>,
+}
+
+impl GetLibraryContentRequest {
+    pub(crate) fn has_user_data_filter(&self) -> bool {
+        self.favorites_only.unwrap_or_default()
+            || self.tags.as_ref().is_some_and(|tags| !tags.is_empty())
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -107,6 +163,217 @@ impl From<String> for DeleteImageRequest {
     }
 }
 
+/// Defines what happens when the target of a move/copy/rename already exists.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum CollisionPolicy {
+    /// Abort the operation with an error
+    #[default]
+    Fail,
+    /// Replace the existing file
+    Overwrite,
+    /// Keep both files by appending a counter to the new file name, e.g. `image (1).jpg`
+    KeepBoth,
+}
+
+/// Moves or copies an image into an album of a media library source.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferImageRequest {
+    pub uri: String,
+    /// The target source
+    pub source: MediaLibrarySource,
+    /// Path relative to the root of the target source, e.g. `Holidays/2024`.
+    /// The image is placed in the root of the source if omitted.
+    pub album: Option<String>,
+    /// New file name, the current file name is kept if omitted
+    pub file_name: Option<String>,
+    pub collision_policy: Option<CollisionPolicy>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameImageRequest {
+    pub uri: String,
+    pub file_name: String,
+    pub collision_policy: Option<CollisionPolicy>,
+}
+
+/// Saves a new image into an album of a media library source.
+/// Either `content` or `path` has to be set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveImageRequest {
+    pub source: MediaLibrarySource,
+    /// Path relative to the root of the source, e.g. `Edited`
+    pub album: Option<String>,
+    pub file_name: String,
+    /// The base64 encoded image data
+    pub content: Option<String>,
+    /// Path of a file in the temporary or the app cache directory containing the image data,
+    /// the file is copied and left untouched
+    pub path: Option<String>,
+    pub collision_policy: Option<CollisionPolicy>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ImportMode {
+    #[default]
+    Copy,
+    Move,
+}
+
+/// Imports all images of a folder (e.g. a sd card) into a media library source.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRequest {
+    /// The folder to import from
+    pub folder: String,
+    pub source: MediaLibrarySource,
+    /// Base album within the source, the images are placed in the root of the source if omitted
+    pub album: Option<String>,
+    /// strftime like template for the folder structure below the album, defaults to `%Y/%m`
+    pub folder_template: Option<String>,
+    pub mode: Option<ImportMode>,
+    /// Defaults to `KeepBoth`, identical files are skipped anyway
+    pub collision_policy: Option<CollisionPolicy>,
+    /// Optional id which is passed along with the progress events
+    pub job_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportProgress {
+    pub job_id: Option<String>,
+    pub total: usize,
+    pub processed: usize,
+    pub current_path: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSkipped {
+    pub path: String,
+    /// The file in the library with the same content
+    pub duplicate_of: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub job_id: Option<String>,
+    pub imported: Vec<ImageInfo>,
+    pub skipped: Vec<ImportSkipped>,
+    pub failed: Vec<ImportFailure>,
+    /// The import was cancelled, the report contains the files handled until then
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FindDuplicatesRequest {
+    pub source: MediaLibrarySource,
+    /// Optional id which is passed along with the progress events
+    pub job_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum JobKind {
+    /// Listing the images of a source
+    #[default]
+    Listing,
+    /// Computing content hashes to find duplicates
+    Hashing,
+    /// Computing perceptual hashes to find similar images
+    Indexing,
+    Import,
+}
+
+/// Progress of a long running job, emitted as `medialibrary://job-progress` event.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobProgress {
+    pub job_id: Option<String>,
+    pub kind: JobKind,
+    /// Image files found so far
+    pub files_seen: usize,
+    /// Total size of the files found so far
+    pub bytes: u64,
+    pub current_folder: String,
+    /// Set on the last event of the job
+    pub finished: bool,
+    pub cancelled: bool,
+}
+
+/// Progress of a scan over all images of a source.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanProgress {
+    pub job_id: Option<String>,
+    pub total: usize,
+    pub processed: usize,
+    pub current_path: String,
+}
+
+/// Images with identical content.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateGroup {
+    pub hash: String,
+    /// Size of a single file in bytes
+    pub size: u64,
+    /// Content uri of the image we suggest to keep, it is also the first of the items
+    pub suggested_keeper: String,
+    pub items: Vec<ImageInfo>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FindDuplicatesResult {
+    pub job_id: Option<String>,
+    /// Sorted by the space which could be freed, largest first
+    pub groups: Vec<DuplicateGroup>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimilarImage {
+    /// Number of differing bits of the perceptual hashes (0 - 64)
+    pub distance: u32,
+    pub image: ImageInfo,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FindSimilarGroupsRequest {
+    pub source: MediaLibrarySource,
+    /// Maximum distance of similar images, defaults to 10
+    pub threshold: Option<u32>,
+    /// Optional id which is passed along with the progress events
+    pub job_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimilarGroup {
+    pub items: Vec<ImageInfo>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FindSimilarGroupsResult {
+    pub job_id: Option<String>,
+    /// Sorted by the number of images, largest first
+    pub groups: Vec<SimilarGroup>,
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RequestPermissionsArgs {
@@ -122,6 +389,44 @@ pub struct ImageInfo {
     pub meta_data: Option<HashMap<MetaDataField, String>>,
     pub image_uri: String,
     pub thumbnail_uri: String,
+    /// The file extension does not match the type detected from the content
+    #[serde(default)]
+    pub extension_mismatch: bool,
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagInfo {
+    pub name: String,
+    /// Number of images the tag is assigned to
+    pub count: usize,
+}
+
+/// Metadata read from a XMP sidecar or the packet embedded in the image.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct XmpMetadata {
+    pub rating: Option<i32>,
+    pub label: Option<String>,
+    pub keywords: Vec<String>,
+    pub description: Option<String>,
+    pub title: Option<String>,
+}
+
+/// Fields which are not set are left untouched, empty values remove the property.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetXmpMetadataRequest {
+    pub uri: String,
+    pub rating: Option<i32>,
+    pub label: Option<String>,
+    pub keywords: Option<Vec<String>>,
+    pub description: Option<String>,
+    pub title: Option<String>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -130,10 +435,22 @@ pub struct GetImagesResult {
     pub items: Vec<ImageInfo>,
 }
 
+/// Access of the app to the images of a source.
+#[derive(Deserialize, Default, Debug, Serialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct SourcePermission {
+    pub read: PermissionState,
+    pub write: PermissionState,
+}
+
 #[derive(Deserialize, Default, Debug, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PermissionResponse {
-    pub post_notification: PermissionState,
+    #[serde(default)]
+    pub sources: HashMap<MediaLibrarySource, SourcePermission>,
+    /// The app runs in a sandbox like Flatpak or Snap
+    #[serde(default)]
+    pub sandboxed: bool,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -156,7 +473,7 @@ impl From<Thumbnail> for GetThumbnailResponse {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Thumbnail {
     pub content: Vec<u8>,
 }
@@ -178,12 +495,38 @@ impl From<Vec<u8>> for Image {
     }
 }
 
-impl PermissionResponse {
-    pub fn granted() -> Self {
-        Self {
-            post_notification: PermissionState::Granted,
-        }
-    }
+/// Location and size of the thumbnail caches.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    /// Directory of the disk cache, not set if it is disabled
+    pub directory: Option<String>,
+    pub thumbnail_count: usize,
+    /// Total size of the thumbnails on disk in bytes
+    pub thumbnail_bytes: u64,
+    /// Configured maximum size of the disk cache in bytes
+    pub max_bytes: u64,
+    pub memory_count: usize,
+    pub memory_bytes: usize,
+    /// Number of cached renditions of the `image://` protocol
+    pub rendition_count: usize,
+    pub rendition_bytes: u64,
+    /// Configured maximum size of the rendition cache in bytes
+    pub rendition_max_bytes: u64,
+}
+
+/// Message sent through the channel of `get_images_stream`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "camelCase")]
+pub enum ImageStreamEvent {
+    Batch {
+        items: Vec<ImageInfo>,
+    },
+    /// The last message, with the number of images and batches sent
+    Finished {
+        total: usize,
+        batches: usize,
+    },
 }
 
 impl GetImagesResult {
@@ -201,7 +544,67 @@ impl GetImagesResult {
     }
 }
 
+impl GetImagesResult {
+    #[cfg(desktop)]
+    pub(crate) fn with_xmp_metadata(self, include_xmp_metadata: bool) -> Self {
+        if include_xmp_metadata {
+            let items = self
+                .items
+                .into_iter()
+                .map(|item| item.with_xmp_metadata())
+                .collect();
+
+            return Self { items };
+        }
+        self
+    }
+}
+
+impl GetImagesResult {
+    #[cfg(desktop)]
+    pub(crate) fn with_exif_metadata(self, include_exif_metadata: bool) -> Self {
+        if include_exif_metadata {
+            let items = self
+                .items
+                .into_iter()
+                .map(|item| item.with_exif_metadata())
+                .collect();
+
+            return Self { items };
+        }
+        self
+    }
+}
+
 impl ImageInfo {
+    #[cfg(desktop)]
+    pub(crate) fn with_exif_metadata(self) -> Self {
+        let exif = crate::exif_metadata::to_meta_data(Path::new(&self.path));
+        if exif.is_empty() {
+            return self;
+        }
+        let mut meta_data = self.meta_data.unwrap_or_default();
+        meta_data.extend(exif);
+        Self {
+            meta_data: Some(meta_data),
+            ..self
+        }
+    }
+
+    #[cfg(desktop)]
+    pub(crate) fn with_xmp_metadata(self) -> Self {
+        let xmp = crate::xmp::read(Path::new(&self.path)).to_meta_data();
+        if xmp.is_empty() {
+            return self;
+        }
+        let mut meta_data = self.meta_data.unwrap_or_default();
+        meta_data.extend(xmp);
+        Self {
+            meta_data: Some(meta_data),
+            ..self
+        }
+    }
+
     pub fn with_file_metadata(self) -> Self {
         if let Ok(metadata) = fs::metadata(&self.path) {
             let mut meta_data = self.meta_data.unwrap_or_default();
@@ -0,0 +1,130 @@
+use std::io::Cursor;
+
+use image::{
+    metadata::Orientation, DynamicImage, ImageDecoder, ImageFormat, ImageReader, ImageResult,
+};
+
+use crate::{extra_formats::ExtraFormat, raw::RawFormat};
+
+/// Decodes an image and applies its EXIF orientation, so the result is upright.
+/// The format is `None` for formats which are not supported by the `image` crate.
+pub(crate) fn decode_upright(content: &[u8]) -> ImageResult<(DynamicImage, Option<ImageFormat>)> {
+    if let Some(format) = ExtraFormat::detect(content) {
+        return Ok((format.decode(content)?, None));
+    }
+    if let Some(format) = RawFormat::detect(content) {
+        return Ok((format.decode_preview(content)?, None));
+    }
+
+    let (mut image, format, orientation) = decode(content)?;
+    image.apply_orientation(orientation);
+    Ok((image, Some(format)))
+}
+
+/// Decodes an image supported by the `image` crate without applying its EXIF orientation.
+pub(crate) fn decode(content: &[u8]) -> ImageResult<(DynamicImage, ImageFormat, Orientation)> {
+    let reader = ImageReader::new(Cursor::new(content)).with_guessed_format()?;
+    let format = reader.format().ok_or_else(|| {
+        image::ImageError::Unsupported(image::error::ImageFormatHint::Unknown.into())
+    })?;
+
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let image = DynamicImage::from_decoder(decoder)?;
+    Ok((image, format, orientation))
+}
+
+#[cfg(feature = "amt")]
+fn swaps_axes(orientation: Orientation) -> bool {
+    matches!(
+        orientation,
+        Orientation::Rotate90
+            | Orientation::Rotate270
+            | Orientation::Rotate90FlipH
+            | Orientation::Rotate270FlipH
+    )
+}
+
+/// Rotates a thumbnail which was created from the raw pixel data of `source`.
+///
+/// Some thumbnailers already apply the orientation. For rotations by 90 degrees this is detected
+/// by comparing the aspect ratio with the stored image, those thumbnails are returned unchanged.
+#[cfg(feature = "amt")]
+pub(crate) fn upright_thumbnail(
+    source: &std::path::Path,
+    thumbnail: crate::Thumbnail,
+) -> crate::Thumbnail {
+    let Some(orientation) = crate::exif_metadata::orientation(source)
+        .filter(|orientation| *orientation != Orientation::NoTransforms)
+    else {
+        return thumbnail;
+    };
+    if thumbnail.content.is_empty() {
+        return thumbnail;
+    }
+
+    let rotate = || -> ImageResult<Vec<u8>> {
+        let format = image::guess_format(&thumbnail.content)?;
+        let mut image = image::load_from_memory_with_format(&thumbnail.content, format)?;
+
+        if swaps_axes(orientation) {
+            let (width, height) = image::image_dimensions(source)?;
+            let stored_landscape = width >= height;
+            let thumbnail_landscape = image.width() >= image.height();
+            if stored_landscape != thumbnail_landscape {
+                return Ok(thumbnail.content.clone());
+            }
+        }
+
+        image.apply_orientation(orientation);
+        let mut output = Cursor::new(Vec::new());
+        image.write_to(&mut output, format)?;
+        Ok(output.into_inner())
+    };
+
+    match rotate() {
+        Ok(content) => content.into(),
+        Err(err) => {
+            log::warn!(
+                "unable to apply the orientation to the thumbnail of {}: {err}",
+                source.to_string_lossy()
+            );
+            thumbnail
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, Rgb, RgbImage};
+
+    /// A 2x1 JPEG with the given EXIF orientation.
+    fn jpeg_with_orientation(orientation: u8) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(20, 10, Rgb([255, 0, 0])));
+        let mut jpeg = Cursor::new(Vec::new());
+        image.write_to(&mut jpeg, ImageFormat::Jpeg).unwrap();
+        let jpeg = jpeg.into_inner();
+
+        // big endian TIFF header with a single IFD entry for the orientation (0x0112)
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        exif.extend_from_slice(&[0, orientation, 0, 0, 0, 0, 0, 0]);
+
+        let mut content = jpeg[..2].to_vec();
+        content.extend_from_slice(&[0xff, 0xe1]);
+        content.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        content.extend_from_slice(&exif);
+        content.extend_from_slice(&jpeg[2..]);
+        content
+    }
+
+    #[test]
+    fn test_decode_upright() {
+        let (image, format) = decode_upright(&jpeg_with_orientation(1)).unwrap();
+        assert_eq!(format, Some(ImageFormat::Jpeg));
+        assert_eq!(image.dimensions(), (20, 10));
+
+        let (image, _) = decode_upright(&jpeg_with_orientation(6)).unwrap();
+        assert_eq!(image.dimensions(), (10, 20));
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use zbus::{
+    export::futures_core::Stream,
+    proxy,
+    zvariant::{OwnedObjectPath, OwnedValue, Value},
+    Connection,
+};
+
+use crate::{json_file, MediaLibrarySource};
+
+/// Response code of a request the user confirmed.
+const RESPONSE_SUCCESS: u32 = 0;
+
+/// Number of requests sent by this process, makes the request tokens unique.
+static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+#[proxy(
+    interface = "org.freedesktop.portal.FileChooser",
+    default_service = "org.freedesktop.portal.Desktop",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+trait FileChooser {
+    fn open_file(
+        &self,
+        parent_window: &str,
+        title: &str,
+        options: HashMap<&str, Value<'_>>,
+    ) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.portal.Request",
+    default_service = "org.freedesktop.portal.Desktop"
+)]
+trait Request {
+    #[zbus(signal)]
+    fn response(&self, response: u32, results: HashMap<String, OwnedValue>) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.portal.Documents",
+    default_service = "org.freedesktop.portal.Documents",
+    default_path = "/org/freedesktop/portal/documents"
+)]
+trait Documents {
+    fn get_mount_point(&self) -> zbus::Result<Vec<u8>>;
+}
+
+/// Folder the user granted access to through the file chooser portal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GrantedRoot {
+    pub path: PathBuf,
+    /// Set if the folder is exported by the document portal, which keeps the access across
+    /// restarts of the app
+    pub document: Option<Document>,
+}
+
+impl GrantedRoot {
+    /// Whether the folder can still be read. The document portal keeps exporting a folder across
+    /// restarts until the access is revoked, its `Info` method is not available inside the
+    /// sandbox, so the exported path is checked directly.
+    pub fn is_accessible(&self) -> bool {
+        std::fs::read_dir(&self.path).is_ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Document {
+    pub id: String,
+    /// Path of the folder below the directory of the document
+    pub relative_path: PathBuf,
+}
+
+/// Client of the xdg-desktop-portal, used to obtain the library roots of a sandboxed app.
+pub(crate) struct Portal {
+    connection: Connection,
+}
+
+impl Portal {
+    pub async fn session() -> zbus::Result<Self> {
+        Ok(Self::new(Connection::session().await?))
+    }
+
+    pub fn new(connection: Connection) -> Self {
+        Self { connection }
+    }
+
+    /// Lets the user choose a folder, returns `None` if the dialog was cancelled.
+    pub async fn choose_folder(&self, title: &str) -> zbus::Result<Option<PathBuf>> {
+        let token = request_token();
+        // subscribe before the call, the response may be sent before the call returns
+        let request = RequestProxy::builder(&self.connection)
+            .path(self.request_path(&token)?)?
+            .build()
+            .await?;
+        let mut responses = request.receive_response().await?;
+
+        let options = HashMap::from([
+            ("handle_token", Value::from(token.as_str())),
+            ("directory", Value::from(true)),
+            ("modal", Value::from(true)),
+        ]);
+        FileChooserProxy::new(&self.connection)
+            .await?
+            .open_file("", title, options)
+            .await?;
+
+        let Some(response) = next(&mut responses).await else {
+            return Ok(None);
+        };
+        let args = response.args()?;
+        if args.response != RESPONSE_SUCCESS {
+            return Ok(None);
+        }
+        let uris: Vec<String> = match args.results.get("uris") {
+            Some(uris) => uris.try_clone()?.try_into()?,
+            None => Vec::new(),
+        };
+        Ok(uris.first().and_then(|uri| file_uri_to_path(uri)))
+    }
+
+    /// Object path of the request with this token, see the `org.freedesktop.portal.Request` docs.
+    fn request_path(&self, token: &str) -> zbus::Result<String> {
+        let sender = self
+            .connection
+            .unique_name()
+            .ok_or_else(|| zbus::Error::Failure("not connected to a bus".to_string()))?
+            .trim_start_matches(':')
+            .replace('.', "_");
+        Ok(format!(
+            "/org/freedesktop/portal/desktop/request/{sender}/{token}"
+        ))
+    }
+
+    async fn mount_point(&self) -> zbus::Result<PathBuf> {
+        let mut bytes = DocumentsProxy::new(&self.connection)
+            .await?
+            .get_mount_point()
+            .await?;
+        // the path is sent as a null terminated byte string
+        if bytes.last() == Some(&0) {
+            bytes.pop();
+        }
+        Ok(PathBuf::from(String::from_utf8_lossy(&bytes).to_string()))
+    }
+
+    /// The mount point of the document portal may differ between sessions, so a folder granted
+    /// before is looked up below the current one. Returns `None` if it is not exported anymore.
+    pub async fn restore_root(&self, root: &GrantedRoot) -> zbus::Result<Option<GrantedRoot>> {
+        let Some(document) = &root.document else {
+            return Ok(None);
+        };
+        let restored = GrantedRoot {
+            path: self
+                .mount_point()
+                .await?
+                .join(&document.id)
+                .join(&document.relative_path),
+            document: Some(document.clone()),
+        };
+        Ok(restored.is_accessible().then_some(restored))
+    }
+
+    /// Remembers the document of a chosen folder, so it can be found again after a restart.
+    pub async fn granted_root(&self, path: PathBuf) -> zbus::Result<GrantedRoot> {
+        let mount_point = self.mount_point().await?;
+        Ok(GrantedRoot {
+            document: document_of(&mount_point, &path),
+            path,
+        })
+    }
+}
+
+/// Handle token of a request, it has to be unique for the connection.
+fn request_token() -> String {
+    let request = REQUESTS.fetch_add(1, Ordering::Relaxed);
+    format!("medialibrary{}_{request}", std::process::id())
+}
+
+async fn next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+    std::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+}
+
+fn file_uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    urlencoding::decode(path)
+        .ok()
+        .map(|path| PathBuf::from(path.into_owned()))
+}
+
+/// Document portal paths look like `<mount point>/<document id>/<name>`.
+fn document_of(mount_point: &Path, path: &Path) -> Option<Document> {
+    let mut components = path.strip_prefix(mount_point).ok()?.components();
+    let id = components.next()?.as_os_str().to_string_lossy().to_string();
+    Some(Document {
+        id,
+        relative_path: components.as_path().to_path_buf(),
+    })
+}
+
+/// Roots granted through the portal, persisted in the plugin data directory.
+pub(crate) struct GrantedRoots {
+    file: Option<PathBuf>,
+    roots: Mutex<HashMap<MediaLibrarySource, GrantedRoot>>,
+}
+
+impl GrantedRoots {
+    pub fn load(dir: Option<&Path>) -> Self {
+        let file = dir.map(|dir| dir.join("granted_roots.json"));
+        let roots = file.as_deref().map(json_file::load).unwrap_or_default();
+        Self {
+            file,
+            roots: Mutex::new(roots),
+        }
+    }
+
+    pub fn get(&self, source: &MediaLibrarySource) -> Option<GrantedRoot> {
+        self.roots.lock().unwrap().get(source).cloned()
+    }
+
+    pub fn path(&self, source: &MediaLibrarySource) -> Option<PathBuf> {
+        self.get(source).map(|root| root.path)
+    }
+
+    pub fn set(&self, source: MediaLibrarySource, root: GrantedRoot) -> crate::Result<()> {
+        let mut roots = self.roots.lock().unwrap();
+        roots.insert(source, root);
+        match &self.file {
+            Some(file) => json_file::save(file, &*roots),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+    };
+    use zbus::{connection, interface, message::Header, names::BusName};
+
+    /// Private session bus, stopped when dropped.
+    struct TestBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl TestBus {
+        /// Returns `None` if `dbus-daemon` is not installed.
+        fn start(dir: &Path) -> Option<Self> {
+            let config = dir.join("bus.conf");
+            fs::write(
+                &config,
+                format!(
+                    "<busconfig><type>session</type><listen>unix:dir={}</listen>\
+                     <auth>EXTERNAL</auth><policy context=\"default\">\
+                     <allow send_destination=\"*\" eavesdrop=\"true\"/>\
+                     <allow eavesdrop=\"true\"/><allow own=\"*\"/></policy></busconfig>",
+                    dir.display()
+                ),
+            )
+            .ok()?;
+            let mut daemon = Command::new("dbus-daemon")
+                .arg(format!("--config-file={}", config.display()))
+                .args(["--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+            Some(Self {
+                daemon,
+                address: address.trim().to_string(),
+            })
+        }
+
+        fn connection(&self) -> zbus::Result<connection::Builder<'_>> {
+            connection::Builder::address(self.address.as_str())
+        }
+    }
+
+    impl Drop for TestBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// Answers every request with the configured uri, or cancels it.
+    struct MockFileChooser {
+        chosen: Option<String>,
+    }
+
+    #[interface(name = "org.freedesktop.portal.FileChooser")]
+    impl MockFileChooser {
+        async fn open_file(
+            &self,
+            #[zbus(header)] header: Header<'_>,
+            #[zbus(connection)] connection: &Connection,
+            _parent_window: &str,
+            _title: &str,
+            options: HashMap<String, OwnedValue>,
+        ) -> zbus::fdo::Result<OwnedObjectPath> {
+            let token = options
+                .get("handle_token")
+                .and_then(|token| token.downcast_ref::<&str>().ok())
+                .unwrap_or_default()
+                .to_string();
+            let sender = header
+                .sender()
+                .map(|sender| sender.trim_start_matches(':').replace('.', "_"))
+                .unwrap_or_default();
+            let path = format!("/org/freedesktop/portal/desktop/request/{sender}/{token}");
+
+            let (response, uris) = match &self.chosen {
+                Some(uri) => (RESPONSE_SUCCESS, vec![uri.clone()]),
+                None => (1, Vec::new()),
+            };
+            let results = HashMap::from([("uris", Value::from(uris))]);
+            connection
+                .emit_signal(
+                    None::<BusName>,
+                    path.as_str(),
+                    "org.freedesktop.portal.Request",
+                    "Response",
+                    &(response, results),
+                )
+                .await?;
+            OwnedObjectPath::try_from(path).map_err(|err| zbus::fdo::Error::Failed(err.to_string()))
+        }
+    }
+
+    struct MockDocuments {
+        mount_point: PathBuf,
+    }
+
+    #[interface(name = "org.freedesktop.portal.Documents")]
+    impl MockDocuments {
+        fn get_mount_point(&self) -> Vec<u8> {
+            let mut bytes = self.mount_point.as_os_str().as_encoded_bytes().to_vec();
+            bytes.push(0);
+            bytes
+        }
+    }
+
+    #[test]
+    fn test_request_token() {
+        let token = request_token();
+        assert_ne!(token, request_token());
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon, run with --ignored"]
+    fn test_portal() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mount_point = dir.join("doc");
+        let pictures = mount_point.join("abc123").join("My Pictures");
+        fs::create_dir_all(&pictures).unwrap();
+
+        let bus = TestBus::start(dir).expect("dbus-daemon is not available");
+
+        let root = tauri::async_runtime::block_on(async {
+            let service = bus
+                .connection()
+                .unwrap()
+                .name("org.freedesktop.portal.Desktop")
+                .unwrap()
+                .name("org.freedesktop.portal.Documents")
+                .unwrap()
+                .serve_at(
+                    "/org/freedesktop/portal/desktop",
+                    MockFileChooser {
+                        chosen: Some(format!("file://{}", pictures.display()).replace(' ', "%20")),
+                    },
+                )
+                .unwrap()
+                .serve_at(
+                    "/org/freedesktop/portal/documents",
+                    MockDocuments {
+                        mount_point: mount_point.clone(),
+                    },
+                )
+                .unwrap()
+                .build()
+                .await
+                .unwrap();
+            let portal = Portal::new(bus.connection().unwrap().build().await.unwrap());
+
+            let path = portal.choose_folder("Pictures").await.unwrap().unwrap();
+            assert_eq!(path, pictures);
+            let root = portal.granted_root(path).await.unwrap();
+            assert_eq!(
+                root.document,
+                Some(Document {
+                    id: "abc123".to_string(),
+                    relative_path: "My Pictures".into(),
+                })
+            );
+            assert!(root.is_accessible());
+
+            // the mount point changed since the folder was granted
+            let moved = dir.join("doc2").join("abc123").join("My Pictures");
+            fs::create_dir_all(&moved).unwrap();
+            service
+                .object_server()
+                .interface::<_, MockDocuments>("/org/freedesktop/portal/documents")
+                .await
+                .unwrap()
+                .get_mut()
+                .await
+                .mount_point = dir.join("doc2");
+            let restored = portal.restore_root(&root).await.unwrap().unwrap();
+            assert_eq!(restored.path, moved);
+            assert_eq!(restored.document, root.document);
+
+            // the portal does not export the folder anymore
+            let removed = GrantedRoot {
+                path: mount_point.join("gone"),
+                document: Some(Document {
+                    id: "gone".to_string(),
+                    relative_path: PathBuf::new(),
+                }),
+            };
+            assert!(!removed.is_accessible());
+            assert_eq!(portal.restore_root(&removed).await.unwrap(), None);
+
+            service
+                .object_server()
+                .interface::<_, MockFileChooser>("/org/freedesktop/portal/desktop")
+                .await
+                .unwrap()
+                .get_mut()
+                .await
+                .chosen = None;
+            assert_eq!(portal.choose_folder("Pictures").await.unwrap(), None);
+            root
+        });
+
+        let roots = GrantedRoots::load(Some(dir));
+        roots
+            .set(MediaLibrarySource::PictureDir, root.clone())
+            .unwrap();
+        let loaded = GrantedRoots::load(Some(dir));
+        assert_eq!(loaded.get(&MediaLibrarySource::PictureDir), Some(root));
+        assert_eq!(loaded.path(&MediaLibrarySource::PictureDir), Some(pictures));
+
+        drop(bus);
+    }
+}
@@ -1,9 +1,33 @@
 use std::path::Path;
 
+#[cfg(not(target_os = "windows"))]
+use percent_encoding::{AsciiSet, CONTROLS};
+
+/// Characters which can not be used literally in the path of an uri. `?` and `#` would start
+/// the query and fragment, `%` an escape sequence.
+#[cfg(not(target_os = "windows"))]
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Returns the decoded uri of the request without the query, the query is used for options
+/// like image transformations.
 pub(crate) fn get_uri_string_from_request(request: &http::Request<Vec<u8>>) -> String {
+    let uri = request.uri().to_string();
+    // an encoded `?` within the path is `%3F`, so the first `?` always starts the query
+    let uri = uri.split_once('?').map_or(uri.as_str(), |(uri, _)| uri);
+
     #[cfg(target_os = "windows")]
     {
-        percent_encoding::percent_decode(request.uri().to_string().as_bytes())
+        percent_encoding::percent_decode(uri.as_bytes())
             .decode_utf8_lossy()
             .to_string()
             .replace("localhost/", "")
@@ -11,7 +35,7 @@ pub(crate) fn get_uri_string_from_request(request: &http::Request<Vec<u8>>) -> S
 
     #[cfg(not(target_os = "windows"))]
     {
-        percent_encoding::percent_decode(request.uri().to_string().as_bytes())
+        percent_encoding::percent_decode(uri.as_bytes())
             .decode_utf8_lossy()
             .to_string()
     }
@@ -30,11 +54,8 @@ pub(crate) fn build_uri_from_path(scheme: &str, path: &Path) -> String {
 
     #[cfg(not(target_os = "windows"))]
     {
-        return format!(
-            "{}://localhost{}",
-            scheme,
-            path.to_str().unwrap_or_default()
-        );
+        let path = percent_encoding::utf8_percent_encode(path.to_str().unwrap_or_default(), PATH);
+        format!("{}://localhost{}", scheme, path)
     }
 }
 
@@ -84,6 +105,35 @@ mod tests {
         );
     }
 
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_get_uri_string_from_request_strips_query() {
+        let request = http::Request::builder()
+            .uri("image://localhost/path/to/file%3F.jpg?w=100&h=100")
+            .body(vec![])
+            .unwrap();
+        assert_eq!(
+            get_uri_string_from_request(&request),
+            "image://localhost/path/to/file?.jpg"
+        );
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_build_uri_from_path_encodes_reserved_characters() {
+        let path = Path::new("/path/to/what?#100%.jpg");
+        let uri = build_uri_from_path("image", path);
+        assert_eq!(uri, "image://localhost/path/to/what%3F%23100%25.jpg");
+
+        let request = http::Request::builder()
+            .uri(format!("{uri}?w=100"))
+            .body(vec![])
+            .unwrap();
+        let uri = get_uri_string_from_request(&request);
+        assert_eq!(uri, "image://localhost/path/to/what?#100%.jpg");
+        assert_eq!(crate::uri::uri_to_path(&uri).unwrap(), path);
+    }
+
     #[cfg(target_os = "windows")]
     #[test]
     fn test_build_uri_from_path() {
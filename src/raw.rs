@@ -0,0 +1,489 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use image::{
+    error::{ImageFormatHint, UnsupportedError, UnsupportedErrorKind},
+    metadata::Orientation,
+    DynamicImage, ImageError, ImageResult,
+};
+
+use crate::orientation::decode;
+
+/// Camera RAW formats, only their embedded JPEG previews are decoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RawFormat {
+    Cr2,
+    Cr3,
+    Nef,
+    Arw,
+    Dng,
+    Raf,
+    Orf,
+}
+
+const TAG_MAKE: u16 = 0x010f;
+const TAG_DNG_VERSION: u16 = 0xc612;
+
+/// Number of bytes read from TIFF based RAW files for the EXIF data, the IFDs precede the
+/// image data. Fields whose values are stored beyond are skipped.
+const EXIF_PREFIX_LEN: u64 = 1024 * 1024;
+/// Number of bytes read from the JPEG preview of a RAF file, the EXIF data is stored in the
+/// APP1 segment at its start which is at most 64 KiB.
+const JPEG_HEADER_LEN: u64 = 128 * 1024;
+/// Upper bound of the CR3 `moov` box, it contains the metadata and a small thumbnail.
+const MAX_BOX_LEN: u64 = 8 * 1024 * 1024;
+
+impl RawFormat {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "cr2" => Some(RawFormat::Cr2),
+            "cr3" => Some(RawFormat::Cr3),
+            "nef" => Some(RawFormat::Nef),
+            "arw" => Some(RawFormat::Arw),
+            "dng" => Some(RawFormat::Dng),
+            "raf" => Some(RawFormat::Raf),
+            "orf" => Some(RawFormat::Orf),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            RawFormat::Cr2 => "image/x-canon-cr2",
+            RawFormat::Cr3 => "image/x-canon-cr3",
+            RawFormat::Nef => "image/x-nikon-nef",
+            RawFormat::Arw => "image/x-sony-arw",
+            RawFormat::Dng => "image/x-adobe-dng",
+            RawFormat::Raf => "image/x-fuji-raf",
+            RawFormat::Orf => "image/x-olympus-orf",
+        }
+    }
+
+    /// Detects the format from the file header. NEF, ARW and DNG are plain TIFF files,
+    /// they are told apart from other TIFF images by the tags of the first IFD.
+    pub fn detect(content: &[u8]) -> Option<Self> {
+        if content.starts_with(b"FUJIFILMCCD-RAW") {
+            return Some(RawFormat::Raf);
+        }
+        if content.get(4..12) == Some(b"ftypcrx ") {
+            return Some(RawFormat::Cr3);
+        }
+        if [b"IIRO", b"IIRS", b"MMOR"]
+            .iter()
+            .any(|magic| content.starts_with(*magic))
+        {
+            return Some(RawFormat::Orf);
+        }
+
+        let tiff = Tiff::new(content)?;
+        if content.get(8..10) == Some(b"CR") {
+            return Some(RawFormat::Cr2);
+        }
+        if tiff.has_tag(TAG_DNG_VERSION) {
+            return Some(RawFormat::Dng);
+        }
+        let make = tiff.ascii(TAG_MAKE)?.to_uppercase();
+        if make.starts_with("NIKON") {
+            Some(RawFormat::Nef)
+        } else if make.starts_with("SONY") {
+            Some(RawFormat::Arw)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the TIFF structures containing the EXIF data of the RAW file. Only the parts
+    /// of the file holding them are read.
+    pub fn exif<R: Read + Seek>(&self, input: &mut R) -> Vec<exif::Exif> {
+        let mut reader = exif::Reader::new();
+        reader.continue_on_error(true);
+        let read_raw = |data: Vec<u8>| {
+            reader
+                .read_raw(data)
+                .or_else(|err| err.distill_partial_result(|_| {}))
+                .ok()
+        };
+        if input.rewind().is_err() {
+            return Vec::new();
+        }
+
+        match self {
+            // a TIFF file with its own magic number
+            RawFormat::Orf => read_prefix(input, EXIF_PREFIX_LEN)
+                .and_then(|mut data| {
+                    let magic: &[u8] = match data.starts_with(b"MM") {
+                        true => &[0x00, 0x2a],
+                        false => &[0x2a, 0x00],
+                    };
+                    data.get_mut(2..4)?.copy_from_slice(magic);
+                    read_raw(data)
+                })
+                .into_iter()
+                .collect(),
+            // CMT1 contains the first IFD, CMT2 the EXIF IFD, both are stored in the moov box
+            RawFormat::Cr3 => {
+                let moov = read_box(input, b"moov").unwrap_or_default();
+                [b"CMT1", b"CMT2"]
+                    .into_iter()
+                    .filter_map(|name| find_box(&moov, name))
+                    .filter_map(|data| read_raw(data.to_vec()))
+                    .collect()
+            }
+            // the EXIF data is only stored in the preview
+            RawFormat::Raf => read_raf_preview_header(input)
+                .and_then(|preview| reader.read_from_container(&mut Cursor::new(preview)).ok())
+                .into_iter()
+                .collect(),
+            _ => read_prefix(input, EXIF_PREFIX_LEN)
+                .and_then(read_raw)
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Decodes the largest embedded JPEG preview and rotates it according to the RAW's
+    /// orientation if the preview does not have one of its own.
+    pub fn decode_preview(&self, content: &[u8]) -> ImageResult<DynamicImage> {
+        let (mut image, orientation) = embedded_jpegs(content)
+            .into_iter()
+            .find_map(|preview| decode(preview).ok())
+            .map(|(image, _, orientation)| (image, orientation))
+            .ok_or_else(|| {
+                let hint = ImageFormatHint::Name(format!("{self:?}"));
+                ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+                    hint.clone(),
+                    UnsupportedErrorKind::Format(hint),
+                ))
+            })?;
+
+        let orientation = match orientation {
+            Orientation::NoTransforms => self.orientation(content),
+            orientation => orientation,
+        };
+        image.apply_orientation(orientation);
+        Ok(image)
+    }
+
+    fn orientation(&self, content: &[u8]) -> Orientation {
+        find_field(
+            &self.exif(&mut Cursor::new(content)),
+            exif::Tag::Orientation,
+        )
+        .and_then(|field| field.value.get_uint(0))
+        .and_then(|value| Orientation::from_exif(value.try_into().ok()?))
+        .unwrap_or(Orientation::NoTransforms)
+    }
+}
+
+/// Finds a field of the first IFD in one of the EXIF structures. The tag is compared by number
+/// only, since the IFDs of a CR3 file are all read as first IFD.
+pub(crate) fn find_field(exif: &[exif::Exif], tag: exif::Tag) -> Option<&exif::Field> {
+    let is_gps = |tag: exif::Tag| tag.context() == exif::Context::Gps;
+    exif.iter().flat_map(|exif| exif.fields()).find(|field| {
+        field.ifd_num == exif::In::PRIMARY
+            && field.tag.number() == tag.number()
+            && is_gps(field.tag) == is_gps(tag)
+    })
+}
+
+/// Minimal reader for the first IFD of a TIFF file.
+struct Tiff<'a> {
+    content: &'a [u8],
+    little_endian: bool,
+    ifd: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(content: &'a [u8]) -> Option<Self> {
+        let little_endian = match content.get(..4)? {
+            b"II\x2a\0" => true,
+            b"MM\0\x2a" => false,
+            _ => return None,
+        };
+        let mut tiff = Self {
+            content,
+            little_endian,
+            ifd: 0,
+        };
+        tiff.ifd = tiff.u32(4)? as usize;
+        Some(tiff)
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.content.get(offset..offset + 2)?.try_into().ok()?;
+        Some(match self.little_endian {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.content.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match self.little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    /// Offset of the 12 byte entry of the tag in the first IFD.
+    fn entry(&self, tag: u16) -> Option<usize> {
+        let count = self.u16(self.ifd)? as usize;
+        (0..count)
+            .map(|index| self.ifd + 2 + index * 12)
+            .find(|entry| self.u16(*entry) == Some(tag))
+    }
+
+    fn has_tag(&self, tag: u16) -> bool {
+        self.entry(tag).is_some()
+    }
+
+    fn ascii(&self, tag: u16) -> Option<String> {
+        let entry = self.entry(tag)?;
+        let count = self.u32(entry + 4)? as usize;
+        // values of up to 4 bytes are stored in the entry itself
+        let offset = match count {
+            0..=4 => entry + 8,
+            _ => self.u32(entry + 8)? as usize,
+        };
+        let value = self.content.get(offset..offset + count)?;
+        let value = value.split(|byte| *byte == 0).next().unwrap_or_default();
+        Some(String::from_utf8_lossy(value).trim().to_string())
+    }
+}
+
+fn read_prefix<R: Read>(input: &mut R, len: u64) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    input.take(len).read_to_end(&mut data).ok()?;
+    Some(data)
+}
+
+/// Reads the content of the top level ISO base media box with the given type.
+fn read_box<R: Read + Seek>(input: &mut R, name: &[u8; 4]) -> Option<Vec<u8>> {
+    loop {
+        let mut header = [0u8; 8];
+        input.read_exact(&mut header).ok()?;
+        let len = match u32::from_be_bytes(header[..4].try_into().ok()?) {
+            // the box extends to the end of the file
+            0 => return None,
+            1 => {
+                let mut size = [0u8; 8];
+                input.read_exact(&mut size).ok()?;
+                u64::from_be_bytes(size).checked_sub(16)?
+            }
+            size => u64::from(size).checked_sub(8)?,
+        };
+        if &header[4..] == name {
+            return (len <= MAX_BOX_LEN).then(|| read_prefix(input, len))?;
+        }
+        input.seek(SeekFrom::Current(len.try_into().ok()?)).ok()?;
+    }
+}
+
+/// Reads the start of the JPEG preview of a RAF file, its offset and length are stored in the
+/// header.
+fn read_raf_preview_header<R: Read + Seek>(input: &mut R) -> Option<Vec<u8>> {
+    let mut header = [0u8; 92];
+    input.read_exact(&mut header).ok()?;
+    let offset = u32::from_be_bytes(header[84..88].try_into().ok()?);
+    let len = u32::from_be_bytes(header[88..92].try_into().ok()?);
+    input.seek(SeekFrom::Start(offset.into())).ok()?;
+    read_prefix(input, u64::from(len).min(JPEG_HEADER_LEN))
+}
+
+/// Returns the content of the first ISO base media box with the given type.
+fn find_box<'a>(content: &'a [u8], name: &[u8; 4]) -> Option<&'a [u8]> {
+    let position = content.windows(4).position(|window| window == name)?;
+    let size = u32::from_be_bytes(
+        content
+            .get(position.checked_sub(4)?..position)?
+            .try_into()
+            .ok()?,
+    );
+    content.get(position + 4..(position - 4).checked_add(size as usize)?)
+}
+
+/// Returns the length of the JPEG stream at the start of `content` or `None` if it is invalid.
+fn jpeg_len(content: &[u8]) -> Option<usize> {
+    if !content.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+
+    let mut position = 2;
+    loop {
+        if *content.get(position)? != 0xff {
+            return None;
+        }
+        let marker = *content.get(position + 1)?;
+        match marker {
+            // fill byte
+            0xff => {
+                position += 1;
+                continue;
+            }
+            0xd9 => return Some(position + 2),
+            0x01 | 0xd0..=0xd7 => {
+                position += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([*content.get(position + 2)?, *content.get(position + 3)?]);
+        if length < 2 {
+            return None;
+        }
+        position += 2 + length as usize;
+
+        if marker == 0xda {
+            // entropy coded data ends at the first marker which is neither a stuffed zero
+            // nor a restart marker
+            loop {
+                position += content.get(position..)?.iter().position(|b| *b == 0xff)?;
+                match *content.get(position + 1)? {
+                    0xff => position += 1,
+                    0x00 | 0xd0..=0xd7 => position += 2,
+                    _ => break,
+                }
+            }
+        }
+    }
+}
+
+/// Returns all JPEG streams embedded in a RAW file, the largest first. Streams embedded
+/// in another stream (e.g. the EXIF thumbnail of a preview) are skipped.
+pub(crate) fn embedded_jpegs(content: &[u8]) -> Vec<&[u8]> {
+    let mut previews = Vec::new();
+    let mut position = 0;
+    while let Some(offset) = content.get(position..).and_then(|rest| {
+        rest.windows(3)
+            .position(|window| window == [0xff, 0xd8, 0xff])
+    }) {
+        let start = position + offset;
+        match jpeg_len(&content[start..]) {
+            Some(length) => {
+                previews.push(&content[start..start + length]);
+                position = start + length;
+            }
+            None => position = start + 1,
+        }
+    }
+    previews.sort_by_key(|preview| std::cmp::Reverse(preview.len()));
+    previews
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, ImageFormat, Rgb, RgbImage};
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([0, 0, 200])));
+        let mut content = Cursor::new(Vec::new());
+        image.write_to(&mut content, ImageFormat::Jpeg).unwrap();
+        content.into_inner()
+    }
+
+    /// A little endian TIFF with the make and the orientation in the first IFD, followed by
+    /// a small and a large JPEG preview.
+    fn nef(orientation: u16) -> Vec<u8> {
+        let mut content = b"II\x2a\0\x08\0\0\0".to_vec();
+        content.extend_from_slice(&2u16.to_le_bytes());
+        // make, ascii, 6 bytes at offset 38
+        content.extend_from_slice(&[0x0f, 0x01, 2, 0, 6, 0, 0, 0, 38, 0, 0, 0]);
+        // orientation, short
+        content.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0]);
+        content.extend_from_slice(&orientation.to_le_bytes());
+        content.extend_from_slice(&[0, 0]);
+        content.extend_from_slice(&[0, 0, 0, 0]);
+        content.extend_from_slice(b"NIKON\0");
+        content.extend_from_slice(&jpeg(16, 8));
+        content.extend_from_slice(&[0x12, 0x34, 0xff, 0xd8, 0xff, 0x00]);
+        content.extend_from_slice(&jpeg(64, 32));
+        content
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(RawFormat::detect(&nef(1)), Some(RawFormat::Nef));
+        assert_eq!(
+            RawFormat::detect(b"\0\0\0\x18ftypcrx \0\0\0\x01crx isom"),
+            Some(RawFormat::Cr3)
+        );
+        assert_eq!(
+            RawFormat::detect(b"II\x2a\0\x10\0\0\0CR\x02\0\0\0\0\0\0\0"),
+            Some(RawFormat::Cr2)
+        );
+        assert_eq!(RawFormat::detect(&jpeg(8, 8)), None);
+    }
+
+    fn iso_box(name: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut data = (content.len() as u32 + 8).to_be_bytes().to_vec();
+        data.extend_from_slice(name);
+        data.extend_from_slice(content);
+        data
+    }
+
+    fn exif_orientation<R: Read + Seek>(format: RawFormat, input: &mut R) -> Option<u32> {
+        find_field(&format.exif(input), exif::Tag::Orientation)?
+            .value
+            .get_uint(0)
+    }
+
+    #[test]
+    fn test_exif() {
+        // the first IFD of the NEF without the previews
+        let tiff = &nef(6)[..44];
+
+        let mut orf = tiff.to_vec();
+        orf[2..4].copy_from_slice(b"RO");
+        assert_eq!(
+            exif_orientation(RawFormat::Orf, &mut Cursor::new(orf)),
+            Some(6)
+        );
+
+        let moov = iso_box(
+            b"moov",
+            &iso_box(b"uuid", &[&[0; 16], &iso_box(b"CMT1", tiff)[..]].concat()),
+        );
+        let mut cr3 = iso_box(b"ftyp", b"crx \0\0\0\x01");
+        cr3.extend_from_slice(&iso_box(b"mdat", &[0; 64]));
+        cr3.extend_from_slice(&moov);
+        assert_eq!(
+            exif_orientation(RawFormat::Cr3, &mut Cursor::new(cr3)),
+            Some(6)
+        );
+
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend_from_slice(tiff);
+        let mut preview = vec![0xff, 0xd8, 0xff, 0xe1];
+        preview.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+        preview.extend_from_slice(&app1);
+        preview.extend_from_slice(&jpeg(16, 8)[2..]);
+        let mut raf = b"FUJIFILMCCD-RAW ".to_vec();
+        raf.resize(84, 0);
+        raf.extend_from_slice(&92u32.to_be_bytes());
+        raf.extend_from_slice(&(preview.len() as u32).to_be_bytes());
+        raf.extend_from_slice(&preview);
+        assert_eq!(
+            exif_orientation(RawFormat::Raf, &mut Cursor::new(raf)),
+            Some(6)
+        );
+
+        assert_eq!(
+            exif_orientation(RawFormat::Nef, &mut Cursor::new(nef(3))),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_decode_preview() {
+        let content = nef(1);
+        assert_eq!(embedded_jpegs(&content).len(), 2);
+
+        let image = RawFormat::Nef.decode_preview(&content).unwrap();
+        assert_eq!(image.dimensions(), (64, 32));
+
+        // rotated by 90 degrees
+        let image = RawFormat::Nef.decode_preview(&nef(6)).unwrap();
+        assert_eq!(image.dimensions(), (32, 64));
+    }
+}
@@ -1,14 +1,24 @@
+use std::path::PathBuf;
+
 use crate::MediaLibrarySource;
 use serde::Deserialize;
 
 #[derive(Debug)]
 pub struct Entry {
-    pub source: MediaLibrarySource,
+    pub source: Option<MediaLibrarySource>,
+    /// Folder `import_folder` may import from, including its subfolders
+    pub import_folder: Option<PathBuf>,
 }
 
 #[derive(Deserialize)]
 #[serde(untagged)]
 pub(crate) enum EntryRaw {
     Value(MediaLibrarySource),
-    Object { source: MediaLibrarySource },
+    Object {
+        source: MediaLibrarySource,
+    },
+    #[serde(rename_all = "camelCase")]
+    ImportFolder {
+        import_folder: PathBuf,
+    },
 }
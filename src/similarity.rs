@@ -0,0 +1,382 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use image::{imageops::FilterType, DynamicImage, GrayImage};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    content_hash::HashIndex, directory_reader::FileFilter, import::image_files, jobs::Job,
+    orientation::decode_upright, Config, ScanProgress, Thumbnail,
+};
+
+pub(crate) const SIMILARITY_PROGRESS_EVENT: &str = "medialibrary://similarity-progress";
+pub(crate) const SIMILARITY_FINISHED_EVENT: &str = "medialibrary://similarity-finished";
+
+/// Maximum distance (of 64 bits) for images to be considered similar if the caller does not
+/// provide a threshold.
+pub(crate) const DEFAULT_THRESHOLD: u32 = 10;
+
+const DCT_SIZE: usize = 32;
+const HASH_SIZE: usize = 8;
+
+/// Difference hash and DCT based perceptual hash of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PerceptualHash {
+    pub dhash: u64,
+    pub phash: u64,
+}
+
+impl PerceptualHash {
+    pub fn from_image(image: &DynamicImage) -> Self {
+        Self {
+            dhash: dhash(image),
+            phash: phash(image),
+        }
+    }
+
+    /// Both hashes have to agree, so the larger of the two hamming distances is used.
+    pub fn distance(&self, other: &PerceptualHash) -> u32 {
+        let dhash = (self.dhash ^ other.dhash).count_ones();
+        let phash = (self.phash ^ other.phash).count_ones();
+        dhash.max(phash)
+    }
+}
+
+fn grayscale(image: &DynamicImage, width: u32, height: u32) -> GrayImage {
+    image
+        .resize_exact(width, height, FilterType::Triangle)
+        .to_luma8()
+}
+
+/// Compares the brightness of neighboured pixels of a 9x8 version of the image.
+fn dhash(image: &DynamicImage) -> u64 {
+    let size = HASH_SIZE as u32;
+    let pixels = grayscale(image, size + 1, size);
+
+    let mut hash = 0u64;
+    for y in 0..size {
+        for x in 0..size {
+            let bit = pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | bit as u64;
+        }
+    }
+    hash
+}
+
+/// Compares the low frequencies of the DCT of a 32x32 version of the image with their median.
+fn phash(image: &DynamicImage) -> u64 {
+    let pixels = grayscale(image, DCT_SIZE as u32, DCT_SIZE as u32);
+
+    let cosines: Vec<f64> = (0..DCT_SIZE * DCT_SIZE)
+        .map(|i| {
+            let (frequency, position) = ((i / DCT_SIZE) as f64, (i % DCT_SIZE) as f64);
+            ((2.0 * position + 1.0) * frequency * std::f64::consts::PI / (2.0 * DCT_SIZE as f64))
+                .cos()
+        })
+        .collect();
+    let dct_1d = |values: &[f64], frequency: usize| -> f64 {
+        values
+            .iter()
+            .enumerate()
+            .map(|(position, value)| value * cosines[frequency * DCT_SIZE + position])
+            .sum()
+    };
+
+    // rows first, only the low frequencies are needed
+    let rows: Vec<Vec<f64>> = pixels
+        .rows()
+        .map(|row| {
+            let values: Vec<f64> = row.map(|pixel| pixel[0] as f64).collect();
+            (0..HASH_SIZE).map(|u| dct_1d(&values, u)).collect()
+        })
+        .collect();
+
+    let mut coefficients = Vec::with_capacity(HASH_SIZE * HASH_SIZE);
+    for v in 0..HASH_SIZE {
+        for u in 0..HASH_SIZE {
+            let column: Vec<f64> = rows.iter().map(|row| row[u]).collect();
+            coefficients.push(dct_1d(&column, v));
+        }
+    }
+
+    // the DC coefficient would dominate the median
+    let mut sorted: Vec<f64> = coefficients[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+
+    coefficients
+        .iter()
+        .fold(0u64, |hash, value| (hash << 1) | (*value > median) as u64)
+}
+
+/// Decodes the thumbnail of the provider, falls back to the original image if there is none.
+fn load_image<T>(path: &Path, thumbnail: &T) -> io::Result<DynamicImage>
+where
+    T: Fn(&Path) -> crate::Result<Thumbnail>,
+{
+    if let Ok(thumbnail) = thumbnail(path) {
+        if !thumbnail.content.is_empty() {
+            if let Ok(image) = image::load_from_memory(&thumbnail.content) {
+                return Ok(image);
+            }
+        }
+    }
+
+    let content = std::fs::read(path)?;
+    decode_upright(&content)
+        .map(|(image, _)| image)
+        .map_err(io::Error::other)
+}
+
+/// Returns the perceptual hash of a single image using the cache of `index`.
+pub(crate) fn hash_image<T>(
+    path: &Path,
+    index: &HashIndex,
+    thumbnail: &T,
+) -> io::Result<PerceptualHash>
+where
+    T: Fn(&Path) -> crate::Result<Thumbnail>,
+{
+    let metadata = std::fs::metadata(path)?;
+    index.perceptual_hash(path, &metadata, || {
+        load_image(path, thumbnail).map(|image| PerceptualHash::from_image(&image))
+    })
+}
+
+/// Computes (or reads from the index) the perceptual hashes of all images below `root`.
+pub(crate) fn hash_images<T, F>(
+    root: &Path,
+    config: &Config,
+    filter: Option<&FileFilter>,
+    index: &HashIndex,
+    thumbnail: &T,
+    job: &Job,
+    mut on_progress: F,
+) -> crate::Result<Vec<(PathBuf, PerceptualHash)>>
+where
+    T: Fn(&Path) -> crate::Result<Thumbnail>,
+    F: FnMut(&ScanProgress),
+{
+    let job_id = job.id();
+    let files: Vec<PathBuf> = image_files(root, config, filter, job)
+        .map(|(path, _)| path)
+        .collect();
+    job.check()?;
+    let total = files.len();
+
+    let mut hashes = Vec::with_capacity(total);
+    for (processed, path) in files.iter().enumerate() {
+        if job.is_cancelled() {
+            // keep the hashes computed so far
+            index.save_or_log();
+            job.check()?;
+        }
+
+        on_progress(&ScanProgress {
+            job_id: job_id.clone(),
+            total,
+            processed,
+            current_path: path.to_string_lossy().to_string(),
+        });
+
+        match hash_image(path, index, thumbnail) {
+            Ok(hash) => hashes.push((path.clone(), hash)),
+            Err(err) => warn!("unable to hash {}: {err}", path.to_string_lossy()),
+        }
+    }
+
+    on_progress(&ScanProgress {
+        job_id,
+        total,
+        processed: total,
+        current_path: String::new(),
+    });
+
+    index.prune(root, &files);
+    index.save_or_log();
+
+    Ok(hashes)
+}
+
+/// Returns all images within `threshold` of `target` ordered by distance, the most similar first.
+pub(crate) fn find_similar(
+    target: &PerceptualHash,
+    hashes: &[(PathBuf, PerceptualHash)],
+    threshold: u32,
+) -> Vec<(PathBuf, u32)> {
+    let mut similar: Vec<(PathBuf, u32)> = hashes
+        .iter()
+        .map(|(path, hash)| (path.clone(), target.distance(hash)))
+        .filter(|(_, distance)| *distance <= threshold)
+        .collect();
+    similar.sort_by_key(|(_, distance)| *distance);
+    similar
+}
+
+/// BK-tree over the hashes, so the neighbours of an image are found without comparing it
+/// to every other image.
+struct BkTree {
+    /// (index of the hash, children keyed by distance)
+    nodes: Vec<(usize, HashMap<u32, usize>)>,
+}
+
+impl BkTree {
+    fn new(hashes: &[(PathBuf, PerceptualHash)]) -> Self {
+        let mut tree = Self { nodes: Vec::new() };
+        for index in 0..hashes.len() {
+            tree.insert(index, hashes);
+        }
+        tree
+    }
+
+    fn insert(&mut self, index: usize, hashes: &[(PathBuf, PerceptualHash)]) {
+        if self.nodes.is_empty() {
+            self.nodes.push((index, HashMap::new()));
+            return;
+        }
+
+        let mut node = 0;
+        loop {
+            let distance = hashes[self.nodes[node].0].1.distance(&hashes[index].1);
+            match self.nodes[node].1.get(&distance) {
+                Some(child) => node = *child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes[node].1.insert(distance, child);
+                    self.nodes.push((index, HashMap::new()));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn find(
+        &self,
+        hash: &PerceptualHash,
+        threshold: u32,
+        hashes: &[(PathBuf, PerceptualHash)],
+    ) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut pending = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(node) = pending.pop() {
+            let (index, children) = &self.nodes[node];
+            let distance = hashes[*index].1.distance(hash);
+            if distance <= threshold {
+                found.push(*index);
+            }
+            pending.extend(
+                children
+                    .iter()
+                    .filter(|(d, _)| d.abs_diff(distance) <= threshold)
+                    .map(|(_, child)| *child),
+            );
+        }
+        found
+    }
+}
+
+fn find_root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+/// Groups images whose distance is within `threshold`, transitively: if a is similar to b and b
+/// to c, all three end up in the same group. Only groups with more than one image are returned.
+pub(crate) fn cluster(hashes: &[(PathBuf, PerceptualHash)], threshold: u32) -> Vec<Vec<PathBuf>> {
+    let tree = BkTree::new(hashes);
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+
+    for (index, (_, hash)) in hashes.iter().enumerate() {
+        for neighbour in tree.find(hash, threshold, hashes) {
+            let (a, b) = (
+                find_root(&mut parents, index),
+                find_root(&mut parents, neighbour),
+            );
+            if a != b {
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<PathBuf>> = HashMap::new();
+    for (index, (path, _)) in hashes.iter().enumerate() {
+        let root = find_root(&mut parents, index);
+        groups.entry(root).or_default().push(path.clone());
+    }
+
+    let mut groups: Vec<Vec<PathBuf>> = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect();
+    groups.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a[0].cmp(&b[0])));
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// A grid of 8x8 blocks with pseudo random brightness.
+    fn blocks(size: u32, seed: u32, brightness: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(size, size, |x, y| {
+            let block = (y * 8 / size) * 8 + x * 8 / size;
+            let value = ((block + seed).wrapping_mul(2654435761) >> 24) as u8;
+            let value = value.saturating_add(brightness);
+            Rgb([value, value, value])
+        }))
+    }
+
+    #[test]
+    fn test_similar_images_have_a_small_distance() {
+        let original = PerceptualHash::from_image(&blocks(512, 0, 0));
+        // resized and slightly brighter, e.g. a re-export
+        let edited = PerceptualHash::from_image(&blocks(256, 0, 10));
+        let other = PerceptualHash::from_image(&blocks(512, 1000, 0));
+
+        assert!(original.distance(&edited) <= DEFAULT_THRESHOLD);
+        assert!(original.distance(&other) > DEFAULT_THRESHOLD);
+    }
+
+    #[test]
+    fn test_cluster() {
+        let hash = |dhash: u64| PerceptualHash {
+            dhash,
+            phash: dhash,
+        };
+        let hashes = vec![
+            (PathBuf::from("a"), hash(0)),
+            (PathBuf::from("b"), hash(0b111)),
+            (PathBuf::from("c"), hash(0b111111)),
+            (PathBuf::from("d"), hash(u64::MAX)),
+            (PathBuf::from("e"), hash(u64::MAX - 1)),
+            (PathBuf::from("f"), hash(0xffff_0000)),
+        ];
+
+        let groups = cluster(&hashes, 3);
+        assert_eq!(
+            groups,
+            vec![
+                vec![PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("c")],
+                vec![PathBuf::from("d"), PathBuf::from("e")],
+            ]
+        );
+
+        let similar = find_similar(&hash(0b1), &hashes, 2);
+        assert_eq!(
+            similar,
+            vec![(PathBuf::from("a"), 1), (PathBuf::from("b"), 2)]
+        );
+    }
+}
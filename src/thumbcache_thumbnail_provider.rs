@@ -1,20 +1,52 @@
 use log::error;
 use std::path::Path;
 
-use crate::{thumbnail_provider::ThumbnailProvider, Error, Thumbnail};
+use thumbcache::ThumbSize;
 
-pub struct ThumbCacheThumbnailProvider;
+use crate::{thumbnail_provider::ThumbnailProvider, Config, Error, Thumbnail};
+
+pub struct ThumbCacheThumbnailProvider {
+    size: u32,
+}
+
+impl ThumbCacheThumbnailProvider {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            size: config.thumbnail_size,
+        }
+    }
+
+    /// The smallest size of the windows thumbnail cache which is at least the configured size.
+    fn thumb_size(&self) -> ThumbSize {
+        match self.size {
+            0..=16 => ThumbSize::S16,
+            17..=32 => ThumbSize::S32,
+            33..=48 => ThumbSize::S48,
+            49..=96 => ThumbSize::S96,
+            97..=256 => ThumbSize::S256,
+            257..=768 => ThumbSize::S768,
+            769..=1280 => ThumbSize::S1280,
+            1281..=1920 => ThumbSize::S1920,
+            _ => ThumbSize::S2560,
+        }
+    }
+}
 
 impl ThumbnailProvider for ThumbCacheThumbnailProvider {
-    fn get_thumbnail(path: &Path) -> crate::Result<Thumbnail> {
-        match thumbcache::get_bmp(
-            path.to_str().unwrap_or_default(),
-            thumbcache::ThumbSize::S96,
-        ) {
+    fn name(&self) -> &str {
+        "thumbcache"
+    }
+
+    // the windows shell already applies the orientation
+    fn get_thumbnail(&self, path: &Path) -> crate::Result<Thumbnail> {
+        match thumbcache::get_bmp(path.to_str().unwrap_or_default(), self.thumb_size()) {
             Ok(bmp) => Ok(bmp.into()),
             Err(error) => {
-                error!("err: {}", error);
-                Err(Error::AllMyToes(format!("a error: {error:?}")))
+                error!("thumbcache error: {}", error);
+                Err(Error::ThumbnailProvider {
+                    provider: self.name().to_string(),
+                    message: format!("{error:?}"),
+                })
             }
         }
     }
@@ -0,0 +1,171 @@
+use std::{fs::Metadata, path::Path, time::UNIX_EPOCH};
+
+use crate::{image_transform::RenditionCache, Thumbnail};
+
+/// Disk cache for thumbnails created by the providers, entries are keyed by the path, size and
+/// modification time of the original, so changed images get a new thumbnail. The least recently
+/// used thumbnails are removed once the cache exceeds its maximum size.
+pub(crate) struct ThumbnailCache {
+    cache: Option<RenditionCache>,
+    thumbnail_size: u32,
+    max_bytes: u64,
+}
+
+impl ThumbnailCache {
+    /// Without a directory or with a maximum size of 0 nothing is cached.
+    pub fn new(dir: Option<&Path>, thumbnail_size: u32, max_bytes: u64) -> Self {
+        Self {
+            cache: dir
+                .filter(|_| max_bytes > 0)
+                .map(|dir| RenditionCache::new(dir, max_bytes)),
+            thumbnail_size,
+            max_bytes,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.cache.is_some()
+    }
+
+    fn key(&self, path: &Path, metadata: &Metadata) -> String {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(&metadata.len().to_le_bytes());
+        hasher.update(&modified.as_nanos().to_le_bytes());
+        hasher.update(&self.thumbnail_size.to_le_bytes());
+        hasher.finalize().to_hex().to_string()
+    }
+
+    fn current_key(&self, path: &Path) -> Option<(&RenditionCache, String)> {
+        let cache = self.cache.as_ref()?;
+        let metadata = std::fs::metadata(path).ok()?;
+        Some((cache, self.key(path, &metadata)))
+    }
+
+    pub fn get(&self, path: &Path) -> Option<Thumbnail> {
+        let (cache, key) = self.current_key(path)?;
+        let content = cache.read(&key)?;
+        cache.touch(&key);
+        Some(content.into())
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.current_key(path)
+            .is_some_and(|(cache, key)| cache.contains(&key))
+    }
+
+    pub fn put(&self, path: &Path, thumbnail: &Thumbnail) {
+        let Some((cache, key)) = self.current_key(path) else {
+            return;
+        };
+        cache.put(&key, &thumbnail.content);
+    }
+
+    /// Removes the thumbnail of the current state of the file, returns whether it was cached.
+    pub fn invalidate(&self, path: &Path) -> bool {
+        self.current_key(path)
+            .is_some_and(|(cache, key)| cache.remove(&key))
+    }
+
+    pub fn purge(&self) {
+        if let Some(cache) = &self.cache {
+            cache.purge();
+        }
+    }
+
+    /// Directory, number and total size of the cached thumbnails.
+    pub fn stats(&self) -> (Option<&Path>, usize, u64) {
+        let Some(cache) = &self.cache else {
+            return (None, 0, 0);
+        };
+        let (count, bytes) = cache.stats();
+        (Some(cache.dir()), count, bytes)
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    /// Last access times are set explicitly, file systems may store them coarsely.
+    fn set_last_used(cache: &RenditionCache, key: &str, time: SystemTime) {
+        fs::File::options()
+            .write(true)
+            .open(cache.dir().join(key))
+            .and_then(|file| file.set_modified(time))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_invalidated_on_change() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let image = dir.join("image.jpg");
+        fs::write(&image, b"original").unwrap();
+
+        let cache = ThumbnailCache::new(Some(&dir.join("cache")), 256, 1024);
+        assert!(cache.get(&image).is_none());
+        cache.put(&image, &b"thumbnail".to_vec().into());
+        assert!(cache.contains(&image));
+        assert_eq!(cache.get(&image).unwrap().content, b"thumbnail");
+
+        fs::write(&image, b"changed content").unwrap();
+        assert!(!cache.contains(&image));
+        assert!(!ThumbnailCache::new(None, 256, 1024).contains(&image));
+        assert!(!ThumbnailCache::new(Some(dir), 256, 0).is_enabled());
+
+        cache.put(&image, &b"thumbnail".to_vec().into());
+        assert!(cache.invalidate(&image));
+        assert!(!cache.contains(&image));
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let cache = ThumbnailCache::new(Some(&dir.join("cache")), 256, 100);
+        let images: Vec<_> = (0..3)
+            .map(|i| {
+                let image = dir.join(format!("{i}.jpg"));
+                fs::write(&image, [i]).unwrap();
+                image
+            })
+            .collect();
+
+        let rendition_cache = cache.cache.as_ref().unwrap();
+        let past = SystemTime::now() - Duration::from_secs(3600);
+        for (i, image) in images[..2].iter().enumerate() {
+            cache.put(image, &vec![0; 40].into());
+            let (_, key) = cache.current_key(image).unwrap();
+            set_last_used(rendition_cache, &key, past + Duration::from_secs(i as u64));
+        }
+        // the first thumbnail is used again, so the second one is evicted
+        let (_, key) = cache.current_key(&images[0]).unwrap();
+        set_last_used(rendition_cache, &key, past + Duration::from_secs(10));
+
+        cache.put(&images[2], &vec![0; 40].into());
+        assert!(cache.contains(&images[0]));
+        assert!(!cache.contains(&images[1]));
+        assert!(cache.contains(&images[2]));
+        assert_eq!(cache.stats().1, 2);
+
+        cache.purge();
+        assert_eq!(cache.stats().2, 0);
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::UNIX_EPOCH,
+};
+
+use tokio::sync::OnceCell;
+
+use crate::Thumbnail;
+
+/// Identifies a thumbnail: the original file in a certain state and the thumbnail size.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ThumbnailKey {
+    path: PathBuf,
+    len: u64,
+    modified: u128,
+    size: u32,
+}
+
+impl ThumbnailKey {
+    pub fn new(path: &Path, size: u32) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Ok(Self {
+            path: path.to_path_buf(),
+            len: metadata.len(),
+            modified: modified.as_nanos(),
+            size,
+        })
+    }
+}
+
+/// Least recently used thumbnails up to a total size in bytes.
+struct Lru {
+    max_bytes: usize,
+    bytes: usize,
+    tick: u64,
+    entries: HashMap<ThumbnailKey, (Thumbnail, u64)>,
+    order: BTreeMap<u64, ThumbnailKey>,
+}
+
+impl Lru {
+    fn get(&mut self, key: &ThumbnailKey) -> Option<Thumbnail> {
+        self.tick += 1;
+        let (thumbnail, last_used) = self.entries.get_mut(key)?;
+        self.order.remove(last_used);
+        *last_used = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(thumbnail.clone())
+    }
+
+    fn put(&mut self, key: ThumbnailKey, thumbnail: Thumbnail) {
+        let len = thumbnail.content.len();
+        if len > self.max_bytes {
+            return;
+        }
+        self.remove(&key);
+        while self.bytes + len > self.max_bytes {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.content.len();
+            }
+        }
+
+        self.tick += 1;
+        self.bytes += len;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (thumbnail, self.tick));
+    }
+
+    fn remove(&mut self, key: &ThumbnailKey) {
+        if let Some((thumbnail, last_used)) = self.entries.remove(key) {
+            self.order.remove(&last_used);
+            self.bytes -= thumbnail.content.len();
+        }
+    }
+}
+
+type InFlight = Arc<OnceCell<Thumbnail>>;
+
+/// Keeps recently served thumbnails in memory and lets concurrent requests of the same
+/// thumbnail wait for a single generation.
+pub(crate) struct ThumbnailMemoryCache {
+    lru: Mutex<Lru>,
+    in_flight: Mutex<HashMap<ThumbnailKey, InFlight>>,
+}
+
+impl ThumbnailMemoryCache {
+    /// A size of 0 disables the cache, concurrent requests are coalesced anyway.
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            lru: Mutex::new(Lru {
+                max_bytes,
+                bytes: 0,
+                tick: 0,
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+            }),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the cached thumbnail or runs `generate`, unless the same thumbnail is being
+    /// generated already. If a generation fails, the next waiting request tries again.
+    pub async fn get_or_generate<F, Fut>(
+        &self,
+        key: ThumbnailKey,
+        generate: F,
+    ) -> crate::Result<Thumbnail>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = crate::Result<Thumbnail>>,
+    {
+        if let Some(thumbnail) = self.lru.lock().unwrap().get(&key) {
+            return Ok(thumbnail);
+        }
+
+        let cell = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let result = cell.get_or_try_init(generate).await.cloned();
+
+        // cached before the cell is removed, so a request in between does not generate it again
+        if let Ok(thumbnail) = &result {
+            self.lru.lock().unwrap().put(key.clone(), thumbnail.clone());
+        }
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, &cell))
+        {
+            in_flight.remove(&key);
+        }
+        result
+    }
+
+    /// Removes the thumbnails of all states of the file.
+    pub fn remove_path(&self, path: &Path) {
+        let mut lru = self.lru.lock().unwrap();
+        let keys: Vec<ThumbnailKey> = lru
+            .entries
+            .keys()
+            .filter(|key| key.path == path)
+            .cloned()
+            .collect();
+        for key in keys {
+            lru.remove(&key);
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.entries.clear();
+        lru.order.clear();
+        lru.bytes = 0;
+    }
+
+    /// Number and total size of the thumbnails in memory.
+    pub fn stats(&self) -> (usize, usize) {
+        let lru = self.lru.lock().unwrap();
+        (lru.entries.len(), lru.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn key(name: &str) -> ThumbnailKey {
+        ThumbnailKey {
+            path: name.into(),
+            len: 0,
+            modified: 0,
+            size: 256,
+        }
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = ThumbnailMemoryCache::new(10);
+        let thumbnail = |len: usize| Thumbnail::from(vec![0; len]);
+        let mut lru = cache.lru.lock().unwrap();
+
+        lru.put(key("a"), thumbnail(4));
+        lru.put(key("b"), thumbnail(4));
+        assert!(lru.get(&key("a")).is_some());
+        // b is the least recently used one
+        lru.put(key("c"), thumbnail(4));
+        assert!(lru.get(&key("b")).is_none());
+        assert!(lru.get(&key("a")).is_some());
+        assert_eq!(lru.bytes, 8);
+
+        lru.put(key("large"), thumbnail(11));
+        assert!(lru.get(&key("large")).is_none());
+        assert_eq!(lru.bytes, 8);
+    }
+
+    #[test]
+    fn test_single_flight() {
+        let cache = Arc::new(ThumbnailMemoryCache::new(0));
+        let generated = Arc::new(AtomicUsize::new(0));
+
+        let requests: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                let generated = generated.clone();
+                tauri::async_runtime::spawn(async move {
+                    cache
+                        .get_or_generate(key("a"), || async move {
+                            tauri::async_runtime::spawn_blocking(move || {
+                                std::thread::sleep(std::time::Duration::from_millis(50));
+                                generated.fetch_add(1, Ordering::SeqCst);
+                            })
+                            .await?;
+                            Ok(Thumbnail::from(b"thumbnail".to_vec()))
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        tauri::async_runtime::block_on(async {
+            for request in requests {
+                assert_eq!(request.await.unwrap().unwrap().content, b"thumbnail");
+            }
+        });
+        assert_eq!(generated.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_cached_after_generation() {
+        let cache = ThumbnailMemoryCache::new(100);
+        tauri::async_runtime::block_on(async {
+            let thumbnail = cache
+                .get_or_generate(key("a"), || async { Ok(Thumbnail::from(b"a".to_vec())) })
+                .await
+                .unwrap();
+            assert_eq!(thumbnail.content, b"a");
+            assert!(cache.in_flight.lock().unwrap().is_empty());
+            assert_eq!(cache.stats(), (1, 1));
+
+            let cached = cache
+                .get_or_generate(key("a"), || async { unreachable!("served from memory") })
+                .await
+                .unwrap();
+            assert_eq!(cached.content, b"a");
+        });
+    }
+}
@@ -1,21 +1,234 @@
 use std::path::Path;
 
-#[cfg(all(not(feature = "thumb_cache"), not(feature = "amt")))]
-use log::warn;
+use crate::{Config, Thumbnail};
 
-use crate::Thumbnail;
+/// Creates thumbnails of image files. Providers are registered with the plugin [`Builder`](crate::Builder)
+/// and tried in order until one of them returns a thumbnail.
+pub trait ThumbnailProvider: Send + Sync {
+    /// Name of the provider used in log messages.
+    fn name(&self) -> &str;
 
-pub trait ThumbnailProvider {
-    fn get_thumbnail(path: &Path) -> crate::Result<Thumbnail>;
+    /// Whether the provider should be asked for files of this mime type, all types by default.
+    fn supports_mime_type(&self, _mime_type: &str) -> bool {
+        true
+    }
+
+    /// Placeholders are skipped if the thumbnail is used to analyze the image.
+    fn is_placeholder(&self) -> bool {
+        false
+    }
+
+    /// Returns the (upright) encoded thumbnail of the file.
+    fn get_thumbnail(&self, path: &Path) -> crate::Result<Thumbnail>;
+}
+
+/// Creates JPEG thumbnails by decoding the image.
+pub struct DecoderThumbnailProvider {
+    /// Size of the longer side in pixels
+    pub size: u32,
+    /// JPEG quality (1 - 100)
+    pub quality: u8,
+}
+
+impl DecoderThumbnailProvider {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            size: config.thumbnail_size,
+            quality: config.thumbnail_quality.clamp(1, 100),
+        }
+    }
+}
+
+impl ThumbnailProvider for DecoderThumbnailProvider {
+    fn name(&self) -> &str {
+        "decoder"
+    }
+
+    fn supports_mime_type(&self, mime_type: &str) -> bool {
+        let needs_heif = matches!(mime_type, "image/heic" | "image/heif" | "image/avif");
+        let needs_jxl = mime_type == "image/jxl";
+        (cfg!(feature = "heif") || !needs_heif) && (cfg!(feature = "jxl") || !needs_jxl)
+    }
+
+    fn get_thumbnail(&self, path: &Path) -> crate::Result<Thumbnail> {
+        let content = std::fs::read(path)?;
+        let (image, _) = crate::orientation::decode_upright(&content)
+            .map_err(|e| crate::Error::ImageTransform(e.to_string()))?;
+
+        let mut output = std::io::Cursor::new(Vec::new());
+        let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output, self.quality);
+        image
+            .thumbnail(self.size, self.size)
+            .to_rgb8()
+            .write_with_encoder(encoder)
+            .map_err(|e| crate::Error::ImageTransform(e.to_string()))?;
+        Ok(output.into_inner().into())
+    }
+}
+
+/// Returns a plain gray image for every file, meant to be the last provider of the chain.
+pub struct PlaceholderThumbnailProvider {
+    pub size: u32,
+}
+
+impl ThumbnailProvider for PlaceholderThumbnailProvider {
+    fn name(&self) -> &str {
+        "placeholder"
+    }
+
+    fn is_placeholder(&self) -> bool {
+        true
+    }
+
+    fn get_thumbnail(&self, _path: &Path) -> crate::Result<Thumbnail> {
+        let image = image::RgbImage::from_pixel(self.size, self.size, image::Rgb([128; 3]));
+        let mut output = std::io::Cursor::new(Vec::new());
+        image
+            .write_to(&mut output, image::ImageFormat::Png)
+            .map_err(|e| crate::Error::ImageTransform(e.to_string()))?;
+        Ok(output.into_inner().into())
+    }
 }
 
-#[cfg(all(not(feature = "thumb_cache"), not(feature = "amt")))]
-pub struct EmptyThumbnailProvider;
+/// The providers used if none are registered: the platform thumbnail caches enabled by features,
+/// the built-in decoder and the placeholder.
+pub fn default_thumbnail_providers(config: &Config) -> Vec<Box<dyn ThumbnailProvider>> {
+    vec![
+        #[cfg(feature = "thumb_cache")]
+        Box::new(crate::thumbcache_thumbnail_provider::ThumbCacheThumbnailProvider::new(config)),
+        #[cfg(feature = "amt")]
+        Box::new(crate::amt_thumbnail_provider::AmtThumbnailProvider::new(
+            config,
+        )),
+        Box::new(DecoderThumbnailProvider::new(config)),
+        Box::new(PlaceholderThumbnailProvider {
+            size: config.thumbnail_size,
+        }),
+    ]
+}
+
+/// Asks the registered providers in order for a thumbnail, on mobile the platform creates them.
+#[cfg(desktop)]
+pub(crate) struct ThumbnailProviders {
+    providers: Vec<Box<dyn ThumbnailProvider>>,
+}
+
+#[cfg(desktop)]
+impl ThumbnailProviders {
+    pub fn new(providers: Vec<Box<dyn ThumbnailProvider>>) -> Self {
+        Self { providers }
+    }
+
+    /// Returns the thumbnail of the first provider which succeeds, placeholders are only used
+    /// if `placeholder` is set.
+    pub fn get_thumbnail(&self, path: &Path, placeholder: bool) -> crate::Result<Thumbnail> {
+        use log::debug;
+
+        let mime_type = crate::media_type::detect(path, crate::ContentSniffing::default())
+            .map(|media_type| media_type.mime_type)
+            .unwrap_or("application/octet-stream");
+
+        let mut last_error = None;
+        for provider in self
+            .providers
+            .iter()
+            .filter(|provider| placeholder || !provider.is_placeholder())
+            .filter(|provider| provider.supports_mime_type(mime_type))
+        {
+            match provider.get_thumbnail(path) {
+                Ok(thumbnail) if !thumbnail.content.is_empty() => {
+                    debug!(
+                        "thumbnail of {} served by {}",
+                        path.to_string_lossy(),
+                        provider.name()
+                    );
+                    return Ok(thumbnail);
+                }
+                Ok(_) => debug!(
+                    "{} returned an empty thumbnail for {}",
+                    provider.name(),
+                    path.to_string_lossy()
+                ),
+                Err(err) => {
+                    debug!(
+                        "{} failed to create a thumbnail for {}: {err}",
+                        provider.name(),
+                        path.to_string_lossy()
+                    );
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            crate::Error::NoThumbnailProvider(path.to_string_lossy().to_string())
+        }))
+    }
+
+    /// Returns the thumbnail of the first placeholder provider.
+    pub fn get_placeholder(&self, path: &Path) -> Option<Thumbnail> {
+        self.providers
+            .iter()
+            .filter(|provider| provider.is_placeholder())
+            .find_map(|provider| provider.get_thumbnail(path).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    struct Failing;
+
+    impl ThumbnailProvider for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn get_thumbnail(&self, path: &Path) -> crate::Result<Thumbnail> {
+            Err(Error::NoThumbnailProvider(
+                path.to_string_lossy().to_string(),
+            ))
+        }
+    }
+
+    struct Fixed(&'static str);
+
+    impl ThumbnailProvider for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn supports_mime_type(&self, mime_type: &str) -> bool {
+            mime_type == "image/png"
+        }
+
+        fn get_thumbnail(&self, _path: &Path) -> crate::Result<Thumbnail> {
+            Ok(self.0.as_bytes().to_vec().into())
+        }
+    }
+
+    #[test]
+    fn test_fallback_chain() {
+        let providers = ThumbnailProviders::new(vec![
+            Box::new(Failing),
+            Box::new(Fixed("png only")),
+            Box::new(PlaceholderThumbnailProvider { size: 16 }),
+        ]);
+
+        let thumbnail = providers
+            .get_thumbnail(Path::new("image.png"), true)
+            .unwrap();
+        assert_eq!(thumbnail.content, b"png only");
 
-#[cfg(all(not(feature = "thumb_cache"), not(feature = "amt")))]
-impl ThumbnailProvider for EmptyThumbnailProvider {
-    fn get_thumbnail(_path: &Path) -> crate::Result<Thumbnail> {
-        warn!("using EmptyThumbnailProvider");
-        Ok(Thumbnail::default())
+        // only the placeholder supports jpeg
+        let thumbnail = providers
+            .get_thumbnail(Path::new("image.jpg"), true)
+            .unwrap();
+        assert!(image::load_from_memory(&thumbnail.content).is_ok());
+        assert!(providers
+            .get_thumbnail(Path::new("image.jpg"), false)
+            .is_err());
     }
 }
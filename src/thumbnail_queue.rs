@@ -0,0 +1,178 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{directory_reader::FileFilter, import::image_files, jobs::Jobs, Config, JobKind};
+
+/// Upper bound of the pause between two thumbnails generated in the background.
+const MAX_IDLE_PAUSE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq)]
+enum Task {
+    /// Queue the thumbnails of all images below the root
+    Scan(PathBuf),
+    /// Thumbnail requested with `prioritize_thumbnails`
+    Prioritized(PathBuf),
+    Background(PathBuf),
+}
+
+#[derive(Default)]
+struct QueueState {
+    prioritized: VecDeque<PathBuf>,
+    scans: VecDeque<PathBuf>,
+    background: VecDeque<PathBuf>,
+    queued: HashSet<PathBuf>,
+}
+
+impl QueueState {
+    /// Returns `false` if a scan of the root is pending already.
+    fn enqueue_root(&mut self, root: PathBuf) -> bool {
+        if self.scans.contains(&root) {
+            return false;
+        }
+        self.scans.push_back(root);
+        true
+    }
+
+    fn next(&mut self) -> Option<Task> {
+        if let Some(path) = self.prioritized.pop_front() {
+            return Some(Task::Prioritized(path));
+        }
+        if let Some(root) = self.scans.pop_front() {
+            return Some(Task::Scan(root));
+        }
+        let path = self.background.pop_front()?;
+        self.queued.remove(&path);
+        Some(Task::Background(path))
+    }
+}
+
+/// Creates thumbnails on a background thread, so scrolling a fresh library does not have to wait
+/// for them. Prioritized thumbnails (e.g. of the visible images) are created first, the others
+/// only use part of the time of the thread.
+#[derive(Clone)]
+pub(crate) struct ThumbnailQueue {
+    state: Arc<(Mutex<QueueState>, Condvar)>,
+}
+
+impl ThumbnailQueue {
+    /// Starts the worker thread, `generate` creates and caches the thumbnail of a file unless
+    /// it is cached already. Roots are walked with the walk options of the config, files hidden
+    /// by the filter are skipped.
+    pub fn start<F>(config: Config, filter: Option<FileFilter>, generate: F) -> Self
+    where
+        F: Fn(&Path) + Send + 'static,
+    {
+        let queue = Self {
+            state: Arc::new((Mutex::new(QueueState::default()), Condvar::new())),
+        };
+
+        let worker = queue.clone();
+        let spawned = std::thread::Builder::new()
+            .name("medialibrary-thumbnails".into())
+            .spawn(move || loop {
+                match worker.wait_for_task() {
+                    Task::Scan(root) => worker.scan(&root, &config, filter.as_ref()),
+                    Task::Prioritized(path) => generate(&path),
+                    Task::Background(path) => {
+                        let started = Instant::now();
+                        generate(&path);
+                        // idle as long as the generation took, at most half of a core is used
+                        std::thread::sleep(started.elapsed().min(MAX_IDLE_PAUSE));
+                    }
+                }
+            });
+        if let Err(err) = spawned {
+            log::error!("unable to start the thumbnail worker: {err}");
+        }
+
+        queue
+    }
+
+    fn wait_for_task(&self) -> Task {
+        let (state, available) = &*self.state;
+        let mut state = state.lock().unwrap();
+        loop {
+            if let Some(task) = state.next() {
+                return task;
+            }
+            state = available.wait(state).unwrap();
+        }
+    }
+
+    fn scan(&self, root: &Path, config: &Config, filter: Option<&FileFilter>) {
+        // scans are not reported to the frontend
+        let job = Jobs::default()
+            .start(None, JobKind::Listing, |_| {})
+            .expect("jobs without an id always start");
+        let files: Vec<PathBuf> = image_files(root, config, filter, &job)
+            .map(|(path, _)| path)
+            .collect();
+
+        let mut state = self.state.0.lock().unwrap();
+        for path in files {
+            if state.queued.insert(path.clone()) {
+                state.background.push_back(path);
+            }
+        }
+        self.state.1.notify_one();
+    }
+
+    /// Queues the thumbnails of all images below the root. The root is walked again on every
+    /// call, so files added since the last scan are picked up; queued files are skipped.
+    pub fn enqueue_root(&self, root: PathBuf) {
+        let mut state = self.state.0.lock().unwrap();
+        if state.enqueue_root(root) {
+            self.state.1.notify_one();
+        }
+    }
+
+    /// Creates these thumbnails next, in the given order. Replaces the previously prioritized
+    /// thumbnails which were not created yet, they are usually not visible anymore.
+    pub fn prioritize(&self, paths: Vec<PathBuf>) {
+        let mut state = self.state.0.lock().unwrap();
+        state.prioritized = paths.into_iter().collect();
+        self.state.1.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_order() {
+        let mut state = QueueState::default();
+        for path in ["a.jpg", "b.jpg"] {
+            state.queued.insert(path.into());
+            state.background.push_back(path.into());
+        }
+        state.scans.push_back("/pictures".into());
+        state.prioritized = VecDeque::from([PathBuf::from("x.jpg"), PathBuf::from("y.jpg")]);
+
+        assert_eq!(state.next(), Some(Task::Prioritized("x.jpg".into())));
+        assert_eq!(state.next(), Some(Task::Prioritized("y.jpg".into())));
+        assert_eq!(state.next(), Some(Task::Scan("/pictures".into())));
+        assert_eq!(state.next(), Some(Task::Background("a.jpg".into())));
+        assert!(!state.queued.contains(Path::new("a.jpg")));
+        assert_eq!(state.next(), Some(Task::Background("b.jpg".into())));
+        assert_eq!(state.next(), None);
+    }
+
+    #[test]
+    fn test_enqueue_root() {
+        let mut state = QueueState::default();
+        assert!(state.enqueue_root("/pictures".into()));
+        // a pending scan is not queued twice
+        assert!(!state.enqueue_root("/pictures".into()));
+        assert_eq!(state.next(), Some(Task::Scan("/pictures".into())));
+        assert_eq!(state.next(), None);
+
+        // the root is scanned again on the next listing
+        assert!(state.enqueue_root("/pictures".into()));
+        assert_eq!(state.next(), Some(Task::Scan("/pictures".into())));
+    }
+}
@@ -0,0 +1,370 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{json_file, TagInfo};
+
+const STORE_FILE_NAME: &str = "user-data.json";
+
+/// Data the user attached to a single media item.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MediaUserData {
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// Hint to find the file again after it was renamed outside of the app, see `file_id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+}
+
+impl MediaUserData {
+    fn is_empty(&self) -> bool {
+        !self.favorite && self.tags.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct StoreData {
+    #[serde(default)]
+    items: HashMap<String, MediaUserData>,
+}
+
+/// Identifies an item in the store, on desktop the canonical path of the file (see
+/// `media_key`), on mobile the content uri.
+pub(crate) struct MediaKey {
+    pub identity: String,
+    pub file_id: Option<String>,
+}
+
+impl From<&str> for MediaKey {
+    fn from(identity: &str) -> Self {
+        Self {
+            identity: identity.to_string(),
+            file_id: None,
+        }
+    }
+}
+
+/// Persists favorites and tags keyed by a stable media identity (see `media_identity`).
+pub(crate) struct UserDataStore {
+    file: Option<PathBuf>,
+    data: Mutex<StoreData>,
+}
+
+fn normalize_tags(tags: &[String]) -> impl Iterator<Item = String> + '_ {
+    tags.iter()
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+}
+
+impl UserDataStore {
+    /// Loads the store from `dir`, a store without a directory is kept in memory only.
+    pub fn load(dir: Option<&Path>) -> Self {
+        let file = dir.map(|dir| dir.join(STORE_FILE_NAME));
+        let data = file.as_deref().map(json_file::load).unwrap_or_default();
+
+        Self {
+            file,
+            data: Mutex::new(data),
+        }
+    }
+
+    fn save(&self, data: &StoreData) -> crate::Result<()> {
+        match &self.file {
+            Some(file) => json_file::save(file, data),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies `change` to a copy of the data, which replaces the data once it has been saved,
+    /// so a failed save does not leave changes in memory that are missing on disk.
+    /// `change` returns whether it changed anything.
+    fn modify<T, F>(&self, change: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut StoreData) -> (T, bool),
+    {
+        let mut data = self.data.lock().unwrap();
+        let mut changed_data = data.clone();
+        let (result, changed) = change(&mut changed_data);
+        if changed {
+            self.save(&changed_data)?;
+            *data = changed_data;
+        }
+        Ok(result)
+    }
+
+    fn update<F>(&self, key: impl Into<MediaKey>, change: F) -> crate::Result<MediaUserData>
+    where
+        F: FnOnce(&mut MediaUserData),
+    {
+        let key = key.into();
+        self.modify(|data| {
+            let mut item = data.items.remove(&key.identity).unwrap_or_default();
+            change(&mut item);
+            if key.file_id.is_some() {
+                item.file_id = key.file_id;
+            }
+            if !item.is_empty() {
+                data.items.insert(key.identity, item.clone());
+            }
+            (item, true)
+        })
+    }
+
+    pub fn get(&self, identity: &str) -> Option<MediaUserData> {
+        self.data.lock().unwrap().items.get(identity).cloned()
+    }
+
+    pub fn set_favorite(
+        &self,
+        key: impl Into<MediaKey>,
+        favorite: bool,
+    ) -> crate::Result<MediaUserData> {
+        self.update(key, |item| item.favorite = favorite)
+    }
+
+    pub fn add_tags(
+        &self,
+        key: impl Into<MediaKey>,
+        tags: &[String],
+    ) -> crate::Result<MediaUserData> {
+        self.update(key, |item| item.tags.extend(normalize_tags(tags)))
+    }
+
+    pub fn remove_tags(
+        &self,
+        key: impl Into<MediaKey>,
+        tags: &[String],
+    ) -> crate::Result<MediaUserData> {
+        self.update(key, |item| {
+            for tag in normalize_tags(tags) {
+                item.tags.remove(&tag);
+            }
+        })
+    }
+
+    /// Moves the data of an item to a new identity after the file has been moved.
+    pub fn rekey(&self, from: &str, to: MediaKey) -> crate::Result<()> {
+        self.modify(|data| match data.items.remove(from) {
+            Some(mut item) => {
+                item.file_id = to.file_id.or(item.file_id);
+                data.items.insert(to.identity, item);
+                ((), true)
+            }
+            None => ((), false),
+        })
+    }
+
+    /// Moves the data of files renamed outside of the app to their new path and removes the
+    /// data of deleted files. The folder of a missing file is searched for a file with the same
+    /// `file_id`. Items in folders which do not exist (e.g. on an unmounted volume) are kept.
+    #[cfg(desktop)]
+    pub fn reconcile(&self) -> crate::Result<()> {
+        self.modify(|data| {
+            let missing: Vec<String> = data
+                .items
+                .keys()
+                .filter(|identity| !Path::new(identity).exists())
+                .cloned()
+                .collect();
+
+            let mut changed = false;
+            for identity in missing {
+                let Some(dir) = Path::new(&identity).parent().filter(|dir| dir.is_dir()) else {
+                    continue;
+                };
+                let Some(item) = data.items.remove(&identity) else {
+                    continue;
+                };
+                changed = true;
+
+                let renamed = item
+                    .file_id
+                    .as_deref()
+                    .and_then(|file_id| find_file_id(dir, file_id))
+                    .map(|path| media_identity(&path));
+                if let Some(renamed) = renamed {
+                    data.items.entry(renamed).or_insert(item);
+                }
+            }
+            ((), changed)
+        })
+    }
+
+    pub fn remove(&self, identity: &str) -> crate::Result<()> {
+        self.modify(|data| ((), data.items.remove(identity).is_some()))
+    }
+
+    /// Returns all tags together with the number of items they are assigned to.
+    pub fn list_tags(&self) -> Vec<TagInfo> {
+        let data = self.data.lock().unwrap();
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for tag in data.items.values().flat_map(|item| item.tags.iter()) {
+            *counts.entry(tag).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .map(|(name, count)| TagInfo {
+                name: name.to_string(),
+                count,
+            })
+            .collect()
+    }
+
+    /// Returns the identities of all items which match the filter.
+    /// Items have to be favorites (if requested) and carry all of the given tags.
+    pub fn find(&self, favorites_only: bool, tags: &[String]) -> HashSet<String> {
+        let tags: Vec<String> = normalize_tags(tags).collect();
+        self.data
+            .lock()
+            .unwrap()
+            .items
+            .iter()
+            .filter(|(_, item)| !favorites_only || item.favorite)
+            .filter(|(_, item)| tags.iter().all(|tag| item.tags.contains(tag)))
+            .map(|(identity, _)| identity.clone())
+            .collect()
+    }
+}
+
+/// Items are keyed by the canonical path of the file. Inodes are reused after deletes and device
+/// ids change between mounts, so they only serve as a hint (see `file_id`). The plugin updates
+/// the store whenever it moves a file.
+#[cfg(desktop)]
+pub(crate) fn media_identity(path: &Path) -> String {
+    std::fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(desktop)]
+pub(crate) fn media_key(path: &Path, metadata: &std::fs::Metadata) -> MediaKey {
+    MediaKey {
+        identity: media_identity(path),
+        file_id: file_id(metadata),
+    }
+}
+
+/// Device and inode of a file, they survive renames within the same file system.
+#[cfg(all(desktop, unix))]
+fn file_id(metadata: &std::fs::Metadata) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    Some(format!("{}:{}", metadata.dev(), metadata.ino()))
+}
+
+/// The stable file index is not available on stable rust for windows.
+#[cfg(all(desktop, not(unix)))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<String> {
+    None
+}
+
+#[cfg(desktop)]
+fn find_file_id(dir: &Path, id: &str) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .find(|entry| {
+            entry
+                .metadata()
+                .ok()
+                .and_then(|metadata| file_id(&metadata))
+                .is_some_and(|file_id| file_id == id)
+        })
+        .map(|entry| entry.path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_and_favorites() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        {
+            let store = UserDataStore::load(Some(dir));
+            store.set_favorite("a", true).unwrap();
+            store
+                .add_tags("a", &["holiday".to_string(), " beach ".to_string()])
+                .unwrap();
+            store.add_tags("b", &["holiday".to_string()]).unwrap();
+            store.add_tags("c", &["".to_string()]).unwrap();
+        }
+
+        let store = UserDataStore::load(Some(dir));
+        assert_eq!(store.find(true, &[]), HashSet::from(["a".to_string()]));
+        assert_eq!(
+            store.find(false, &["holiday".to_string()]),
+            HashSet::from(["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(
+            store.find(false, &["holiday".to_string(), "beach".to_string()]),
+            HashSet::from(["a".to_string()])
+        );
+        assert!(store.get("c").is_none());
+
+        let tags = store.list_tags();
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[1].name, "holiday");
+        assert_eq!(tags[1].count, 2);
+
+        store.remove_tags("b", &["holiday".to_string()]).unwrap();
+        assert!(store.get("b").is_none());
+
+        store.rekey("a", "d".into()).unwrap();
+        assert!(store.get("d").unwrap().favorite);
+    }
+
+    #[test]
+    fn test_failed_save_keeps_the_data() {
+        let temp = tempfile::tempdir().unwrap();
+        // the store directory can not be created below a file
+        let file = temp.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+
+        let store = UserDataStore::load(Some(&file));
+        assert!(store.set_favorite("a", true).is_err());
+        assert!(store.get("a").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reconcile() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let key = |name: &str| {
+            let path = dir.join(name);
+            media_key(&path, &std::fs::metadata(&path).unwrap())
+        };
+        for name in ["renamed.jpg", "deleted.jpg", "kept.jpg"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+
+        let store = UserDataStore::load(None);
+        store.set_favorite(key("renamed.jpg"), true).unwrap();
+        store.set_favorite(key("deleted.jpg"), true).unwrap();
+        store.set_favorite(key("kept.jpg"), true).unwrap();
+        store
+            .set_favorite("/unmounted/volume/image.jpg", true)
+            .unwrap();
+
+        std::fs::rename(dir.join("renamed.jpg"), dir.join("new name.jpg")).unwrap();
+        std::fs::remove_file(dir.join("deleted.jpg")).unwrap();
+        store.reconcile().unwrap();
+
+        let favorites = store.find(true, &[]);
+        assert_eq!(favorites.len(), 3);
+        assert!(favorites.contains(&key("new name.jpg").identity));
+        assert!(favorites.contains(&key("kept.jpg").identity));
+        assert!(favorites.contains("/unmounted/volume/image.jpg"));
+    }
+}
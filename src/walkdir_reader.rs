@@ -1,51 +1,174 @@
 use chrono::{DateTime, Utc};
 
 use crate::{
-    directory_reader::DirectoryReader, Error, GetImagesResult, GetLibraryContentRequest, ImageInfo,
+    directory_reader::{DirectoryReader, FileFilter},
+    jobs::Job,
+    media_type::{self, MediaType},
+    Config, ContentSniffing, Error, GetImagesResult, GetLibraryContentRequest, ImageInfo,
     MediaLibrarySource,
 };
 
-use std::{path::Path, time::SystemTime};
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 use walkdir::WalkDir;
 
+/// A listed file with its media type, modification and creation time.
+type Entry = (PathBuf, MediaType, Option<SystemTime>, Option<SystemTime>);
+
 pub struct WalkdirReader<'a> {
     path: &'a Path,
+    filter: Option<FileFilter>,
+    config: Option<&'a Config>,
+    job: Option<Job>,
 }
 
 impl<'a> WalkdirReader<'a> {
     pub fn new(path: &'a Path) -> Self {
-        WalkdirReader { path }
+        WalkdirReader {
+            path,
+            filter: None,
+            config: None,
+            job: None,
+        }
     }
-}
 
-fn is_image_file(path: &Path) -> bool {
-    match path.extension().and_then(|ext| ext.to_str()) {
-        Some(ext) => {
-            let ext = ext.to_lowercase();
-            matches!(
-                ext.as_str(),
-                "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "tiff"
-            )
+    pub fn with_filter(self, filter: Option<FileFilter>) -> Self {
+        WalkdirReader { filter, ..self }
+    }
+
+    pub fn with_config(self, config: &'a Config) -> Self {
+        WalkdirReader {
+            config: Some(config),
+            ..self
         }
-        None => false,
     }
+
+    /// Reports the found files to the job and stops walking once it is cancelled.
+    pub(crate) fn with_job(self, job: Job) -> Self {
+        WalkdirReader {
+            job: Some(job),
+            ..self
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+fn is_hidden(entry: &walkdir::DirEntry) -> bool {
+    // the root itself may be hidden, e.g. a folder in ~/.local
+    entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.')
+}
+
+/// Applies the walk options of the config to `walk` and returns the files which are not
+/// excluded by it. The walk stops once the job is cancelled.
+#[cfg(not(target_os = "android"))]
+pub(crate) fn walk_files<'a>(
+    walk: WalkDir,
+    config: &'a Config,
+    job: Option<&'a Job>,
+) -> impl Iterator<Item = walkdir::DirEntry> + 'a {
+    let mut walk = walk.follow_links(config.follow_symlinks);
+    if let Some(max_depth) = config.max_depth {
+        // files in the root are at depth 1
+        walk = walk.max_depth(max_depth.saturating_add(1));
+    }
+
+    walk.into_iter()
+        .filter_entry(|e| config.include_hidden || !is_hidden(e))
+        .take_while(move |_| !job.is_some_and(Job::is_cancelled))
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| config.is_extension_allowed(e.path()))
 }
 
-fn get_mime_type(path: &Path) -> String {
-    match path.extension().and_then(|ext| ext.to_str()) {
-        Some(ext) => {
-            let ext = ext.to_lowercase();
-            match ext.as_str() {
-                "jpg" | "jpeg" => "image/jpeg".to_owned(),
-                "png" => "image/png".to_owned(),
-                "gif" => "image/gif".to_owned(),
-                "bmp" => "image/bmp".to_owned(),
-                "webp" => "image/webp".to_owned(),
-                "tiff" => "image/tiff".to_owned(),
-                _ => "application/octet-stream".to_owned(),
+pub(crate) fn is_image_file(path: &Path) -> bool {
+    media_type::from_extension(path).is_some()
+}
+
+fn build_image_info(
+    path: &Path,
+    media_type: MediaType,
+    modified: &Option<SystemTime>,
+    created: &Option<SystemTime>,
+) -> ImageInfo {
+    use crate::protocol_handler::build_uri_from_path;
+
+    ImageInfo {
+        path: path.to_string_lossy().to_string(),
+        content_uri: format!("file://{}", path.to_string_lossy()),
+        mime_type: media_type.mime_type.to_owned(),
+        extension_mismatch: media_type.extension_mismatch,
+        meta_data: get_meta_data(modified, created),
+        image_uri: build_uri_from_path("image", path),
+        thumbnail_uri: build_uri_from_path("thumbnail", path),
+        ..Default::default()
+    }
+}
+
+/// Builds the `ImageInfo` for a single file, returns `None` if it is not a supported image.
+pub(crate) fn read_image_info(path: &Path) -> Option<ImageInfo> {
+    let meta = std::fs::metadata(path).ok().filter(|meta| meta.is_file())?;
+    let media_type = media_type::detect(path, ContentSniffing::default())?;
+    Some(build_image_info(
+        path,
+        media_type,
+        &meta.modified().ok(),
+        &meta.created().ok(),
+    ))
+}
+
+impl WalkdirReader<'_> {
+    /// Walks the source in directory order, found files are reported to the job.
+    #[cfg(not(target_os = "android"))]
+    fn entries<'b>(
+        &'b self,
+        request: &GetLibraryContentRequest,
+        config: &'b Config,
+    ) -> impl Iterator<Item = Entry> + 'b {
+        let sniffing = request.content_sniffing.unwrap_or_default();
+
+        walk_files(WalkDir::new(self.path), config, self.job.as_ref()).filter_map(move |entry| {
+            let path = entry.path().to_path_buf();
+            let media_type = media_type::detect(&path, sniffing)?;
+
+            match std::fs::metadata(&path) {
+                Ok(meta)
+                    if self
+                        .filter
+                        .as_ref()
+                        .is_some_and(|filter| !filter(&path, &meta)) =>
+                {
+                    None
+                }
+                Ok(meta) => {
+                    if let Some(job) = &self.job {
+                        job.file_seen(&path, meta.len());
+                    }
+                    let modified = meta.modified().ok();
+                    let created = meta.created().ok();
+                    Some((path, media_type, modified, created))
+                }
+                Err(_) => None, // Ignoriere Dateien ohne Metadaten
             }
-        }
-        None => "application/octet-stream".to_owned(),
+        })
+    }
+
+    /// Returns all entries of the source in the order of the request.
+    #[cfg(not(target_os = "android"))]
+    fn sorted_entries(
+        &self,
+        request: &GetLibraryContentRequest,
+        config: &Config,
+    ) -> Result<Vec<Entry>, Error> {
+        let mut all_entries: Vec<Entry> = self.entries(request, config).collect();
+        self.check_cancelled()?;
+        all_entries.sort_by(|a, b| sort_entries(a, b, request));
+        Ok(all_entries)
+    }
+
+    fn check_cancelled(&self) -> Result<(), Error> {
+        self.job.as_ref().map_or(Ok(()), Job::check)
     }
 }
 
@@ -54,30 +177,9 @@ impl<'a> DirectoryReader for WalkdirReader<'a> {
         match &request.source {
             #[cfg(not(target_os = "android"))]
             MediaLibrarySource::PictureDir => {
-                use std::path::PathBuf;
-                let mut items = Vec::new();
-
-                let mut all_entries: Vec<(PathBuf, Option<SystemTime>, Option<SystemTime>)> =
-                    WalkDir::new(self.path)
-                        .into_iter()
-                        .filter_map(Result::ok)
-                        .filter(|e| e.file_type().is_file())
-                        .filter(|e| is_image_file(e.path()))
-                        .filter_map(|entry| {
-                            let path = entry.path().to_path_buf();
-
-                            match std::fs::metadata(&path) {
-                                Ok(meta) => {
-                                    let modified = meta.modified().ok();
-                                    let created = meta.created().ok();
-                                    Some((path, modified, created))
-                                }
-                                Err(_) => None, // Ignoriere Dateien ohne Metadaten
-                            }
-                        })
-                        .collect();
-
-                all_entries.sort_by(|a, b| sort_entries(a, b, request));
+                let default_config = Config::default();
+                let config = self.config.unwrap_or(&default_config);
+                let all_entries = self.sorted_entries(request, config)?;
 
                 let skip = request.offset;
                 let limit = request.limit;
@@ -87,18 +189,12 @@ impl<'a> DirectoryReader for WalkdirReader<'a> {
                 } else {
                     &all_entries[skip..(skip + limit).min(all_entries.len())]
                 };
-                for (path, modified, created) in page {
-                    use crate::protocol_handler::build_uri_from_path;
-
-                    items.push(ImageInfo {
-                        path: path.to_string_lossy().to_string(),
-                        content_uri: format!("file://{}", path.to_string_lossy()),
-                        mime_type: get_mime_type(path),
-                        meta_data: get_meta_data(modified, created),
-                        image_uri: build_uri_from_path("image", path),
-                        thumbnail_uri: build_uri_from_path("thumbnail", path),
-                    });
-                }
+                let items = page
+                    .iter()
+                    .map(|(path, media_type, modified, created)| {
+                        build_image_info(path, *media_type, modified, created)
+                    })
+                    .collect();
 
                 Ok(GetImagesResult { items })
             }
@@ -106,17 +202,60 @@ impl<'a> DirectoryReader for WalkdirReader<'a> {
             _ => Ok(GetImagesResult { items: Vec::new() }),
         }
     }
+
+    fn stream_directory(
+        &self,
+        request: &GetLibraryContentRequest,
+        batch_size: usize,
+        on_batch: &mut dyn FnMut(Vec<ImageInfo>) -> Result<(), Error>,
+    ) -> Result<usize, Error> {
+        match &request.source {
+            #[cfg(not(target_os = "android"))]
+            MediaLibrarySource::PictureDir => {
+                let default_config = Config::default();
+                let config = self.config.unwrap_or(&default_config);
+
+                // without a sort column the images are sent in the order they are found
+                let entries: Box<dyn Iterator<Item = Entry>> = if request.sort_column.is_some() {
+                    Box::new(self.sorted_entries(request, config)?.into_iter())
+                } else {
+                    Box::new(self.entries(request, config))
+                };
+
+                let batch_size = batch_size.max(1);
+                let mut total = 0;
+                let mut batch = Vec::with_capacity(batch_size);
+                for (path, media_type, modified, created) in
+                    entries.skip(request.offset).take(request.limit)
+                {
+                    batch.push(build_image_info(&path, media_type, &modified, &created));
+                    if batch.len() == batch_size {
+                        total += batch.len();
+                        on_batch(std::mem::replace(
+                            &mut batch,
+                            Vec::with_capacity(batch_size),
+                        ))?;
+                    }
+                }
+                self.check_cancelled()?;
+
+                if !batch.is_empty() {
+                    total += batch.len();
+                    on_batch(batch)?;
+                }
+                Ok(total)
+            }
+            #[cfg(target_os = "android")]
+            _ => Ok(0),
+        }
+    }
 }
 
-fn sort_entries(
-    a: &(std::path::PathBuf, Option<SystemTime>, Option<SystemTime>),
-    b: &(std::path::PathBuf, Option<SystemTime>, Option<SystemTime>),
-    request: &GetLibraryContentRequest,
-) -> std::cmp::Ordering {
+fn sort_entries(a: &Entry, b: &Entry, request: &GetLibraryContentRequest) -> std::cmp::Ordering {
     use std::cmp::Ordering;
 
-    let (_, a_modified, a_created) = a;
-    let (_, b_modified, b_created) = b;
+    let (_, _, a_modified, a_created) = a;
+    let (_, _, b_modified, b_created) = b;
 
     let ordering = match &request.sort_column {
         Some(sort_column) => match sort_column {
@@ -164,3 +303,39 @@ fn get_meta_data(
 
     Some(meta_data)
 }
+
+#[cfg(all(test, not(target_os = "android")))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_stream_directory() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("album")).unwrap();
+        for name in ["a.jpg", "b.jpg", "album/c.jpg", "album/d.jpg", "notes.txt"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let reader = WalkdirReader::new(dir);
+        let mut request = GetLibraryContentRequest {
+            limit: 3,
+            offset: 0,
+            source: MediaLibrarySource::PictureDir,
+            ..Default::default()
+        };
+        for sort_column in [None, Some(crate::SortColumn::DateModified)] {
+            request.sort_column = sort_column;
+            let mut batches = Vec::new();
+            let total = reader
+                .stream_directory(&request, 2, &mut |items| {
+                    batches.push(items.len());
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(total, 3);
+            assert_eq!(batches, vec![2, 1]);
+        }
+    }
+}
@@ -1,32 +1,96 @@
 use crate::{
-    directory_reader::DirectoryReader, walkdir_reader::WalkdirReader, Error, GetImagesResult,
-    GetLibraryContentRequest, MediaLibrarySource,
+    directory_reader::{DirectoryReader, FileFilter},
+    jobs::Job,
+    walkdir_reader::WalkdirReader,
+    Config, Error, GetImagesResult, GetLibraryContentRequest, ImageInfo, MediaLibrarySource,
 };
 
-pub struct XdgDirectoryReader;
+#[derive(Default)]
+pub struct XdgDirectoryReader {
+    filter: Option<FileFilter>,
+    config: Config,
+    job: Option<Job>,
+}
+
+impl XdgDirectoryReader {
+    pub fn with_filter(self, filter: Option<FileFilter>) -> Self {
+        XdgDirectoryReader { filter, ..self }
+    }
+
+    pub fn with_config(self, config: Config) -> Self {
+        XdgDirectoryReader { config, ..self }
+    }
+
+    pub(crate) fn with_job(self, job: Job) -> Self {
+        XdgDirectoryReader {
+            job: Some(job),
+            ..self
+        }
+    }
+}
+
+/// Returns the XDG pictures directory, `XDG_PICTURES_DIR` takes precedence over `user-dirs.dirs`.
+pub(crate) fn pictures_dir() -> Option<std::path::PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_PICTURES_DIR").map(std::path::PathBuf::from) {
+        Some(dir)
+    } else {
+        match xdg_user::pictures() {
+            Ok(Some(dir)) => Some(dir),
+            Ok(None) | Err(_) => None,
+        }
+    }
+}
+
+impl XdgDirectoryReader {
+    /// Lists the pictures directory (or the configured root) with a `WalkdirReader`.
+    fn with_reader<T>(
+        &self,
+        request: &GetLibraryContentRequest,
+        read: impl FnOnce(&WalkdirReader) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let pictures_dir = self
+            .config
+            .roots
+            .get(&request.source)
+            .cloned()
+            .or_else(pictures_dir)
+            .ok_or_else(|| Error::MediaLibrarySourceForbidden(request.source.clone()))?;
+
+        let mut reader = WalkdirReader::new(&pictures_dir)
+            .with_filter(self.filter.clone())
+            .with_config(&self.config);
+        if let Some(job) = &self.job {
+            reader = reader.with_job(job.clone());
+        }
+        read(&reader)
+    }
+}
 
 impl DirectoryReader for XdgDirectoryReader {
     fn read_directory(&self, request: &GetLibraryContentRequest) -> Result<GetImagesResult, Error> {
         match &request.source {
             #[cfg(not(target_os = "android"))]
             MediaLibrarySource::PictureDir => {
-                let pictures_dir = if let Some(dir) =
-                    std::env::var_os("XDG_PICTURES_DIR").map(std::path::PathBuf::from)
-                {
-                    Some(dir)
-                } else {
-                    match xdg_user::pictures() {
-                        Ok(Some(dir)) => Some(dir),
-                        Ok(None) | Err(_) => None,
-                    }
-                }
-                .ok_or_else(|| Error::MediaLibrarySourceForbidden(request.source.clone()))?;
-
-                let reader = WalkdirReader::new(&pictures_dir);
-                reader.read_directory(request)
+                self.with_reader(request, |reader| reader.read_directory(request))
             }
             #[cfg(target_os = "android")]
             _ => Err(Error::MediaLibrarySourceForbidden(request.source.clone())),
         }
     }
+
+    fn stream_directory(
+        &self,
+        request: &GetLibraryContentRequest,
+        batch_size: usize,
+        on_batch: &mut dyn FnMut(Vec<ImageInfo>) -> Result<(), Error>,
+    ) -> Result<usize, Error> {
+        match &request.source {
+            #[cfg(not(target_os = "android"))]
+            MediaLibrarySource::PictureDir => self.with_reader(request, |reader| {
+                reader.stream_directory(request, batch_size, on_batch)
+            }),
+            #[cfg(target_os = "android")]
+            _ => Err(Error::MediaLibrarySourceForbidden(request.source.clone())),
+        }
+    }
 }
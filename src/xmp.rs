@@ -0,0 +1,748 @@
+use std::{
+    fs,
+    io::{BufReader, Read, Seek, SeekFrom, Write as _},
+    path::{Path, PathBuf},
+};
+
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    name::{LocalName, Namespace, QName, ResolveResult},
+    NsReader, Writer,
+};
+
+use crate::{Error, MetaDataField, SetXmpMetadataRequest, XmpMetadata};
+
+const NS_XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/";
+const NS_DC: &[u8] = b"http://purl.org/dc/elements/1.1/";
+const NS_RDF: &[u8] = b"http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+const PACKET_START: &[u8] = b"<x:xmpmeta";
+const PACKET_END: &[u8] = b"</x:xmpmeta>";
+
+/// Upper bound of the segment holding the packet and of the prefix which is scanned for
+/// formats without a known segment.
+const MAX_PACKET_LEN: u64 = 4 * 1024 * 1024;
+
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+const TIFF_TAG_XMP: u16 = 700;
+
+/// Used when neither a sidecar nor an embedded packet exists.
+const EMPTY_DOCUMENT: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/">
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Property {
+    Rating,
+    Label,
+    Subject,
+    Description,
+    Title,
+}
+
+fn is_namespace(ns: &ResolveResult, expected: &[u8]) -> bool {
+    matches!(ns, ResolveResult::Bound(Namespace(ns)) if *ns == expected)
+}
+
+fn property(ns: &ResolveResult, local: LocalName) -> Option<Property> {
+    if is_namespace(ns, NS_XMP) {
+        match local.as_ref() {
+            b"Rating" => Some(Property::Rating),
+            b"Label" => Some(Property::Label),
+            _ => None,
+        }
+    } else if is_namespace(ns, NS_DC) {
+        match local.as_ref() {
+            b"subject" => Some(Property::Subject),
+            b"description" => Some(Property::Description),
+            b"title" => Some(Property::Title),
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+fn is_changed(ns: &ResolveResult, local: LocalName, changed: &[Property]) -> bool {
+    property(ns, local).is_some_and(|property| changed.contains(&property))
+}
+
+fn is_rdf(ns: &ResolveResult, local: LocalName, name: &[u8]) -> bool {
+    is_namespace(ns, NS_RDF) && local.as_ref() == name
+}
+
+fn parse_rating(value: &str) -> Option<i32> {
+    value.trim().parse::<f64>().ok().map(|rating| rating as i32)
+}
+
+fn non_empty(value: String) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+impl XmpMetadata {
+    fn set_simple(&mut self, property: Property, value: String) {
+        match property {
+            Property::Rating => self.rating = parse_rating(&value),
+            Property::Label => self.label = non_empty(value),
+            Property::Subject => self.keywords = non_empty(value).into_iter().collect(),
+            Property::Description => self.description = non_empty(value),
+            Property::Title => self.title = non_empty(value),
+        }
+    }
+
+    pub(crate) fn to_meta_data(&self) -> Vec<(MetaDataField, String)> {
+        let mut fields = Vec::new();
+        if let Some(rating) = self.rating {
+            fields.push((MetaDataField::Rating, rating.to_string()));
+        }
+        if let Some(label) = &self.label {
+            fields.push((MetaDataField::ColorLabel, label.clone()));
+        }
+        if !self.keywords.is_empty() {
+            fields.push((MetaDataField::Keywords, self.keywords.join(", ")));
+        }
+        if let Some(description) = &self.description {
+            fields.push((MetaDataField::Description, description.clone()));
+        }
+        if let Some(title) = &self.title {
+            fields.push((MetaDataField::Title, title.clone()));
+        }
+        fields
+    }
+}
+
+/// Parses the properties we are interested in from a XMP document.
+/// Invalid documents are read as far as possible.
+pub(crate) fn parse(xml: &str) -> XmpMetadata {
+    let mut reader = NsReader::from_str(xml);
+    let mut metadata = XmpMetadata::default();
+
+    let mut current: Option<Property> = None;
+    let mut text = String::new();
+    // collected rdf:li values of the current property, the flag marks the x-default language
+    let mut items: Vec<(bool, String)> = Vec::new();
+    let mut default_language = false;
+
+    loop {
+        match reader.read_resolved_event() {
+            Ok((ns, Event::Start(e))) => match current {
+                None => {
+                    if let Some(property) = property(&ns, e.local_name()) {
+                        current = Some(property);
+                        text.clear();
+                        items.clear();
+                    } else if is_rdf(&ns, e.local_name(), b"Description") {
+                        read_attributes(&reader, &e, &mut metadata);
+                    }
+                }
+                Some(_) => {
+                    if is_rdf(&ns, e.local_name(), b"li") {
+                        default_language = e.attributes().filter_map(Result::ok).any(|attr| {
+                            attr.key.as_ref() == b"xml:lang" && attr.value.as_ref() == b"x-default"
+                        });
+                        text.clear();
+                    }
+                }
+            },
+            Ok((ns, Event::Empty(e)))
+                if current.is_none() && is_rdf(&ns, e.local_name(), b"Description") =>
+            {
+                read_attributes(&reader, &e, &mut metadata);
+            }
+            Ok((_, Event::Text(e))) if current.is_some() => {
+                if let Ok(value) = e.unescape() {
+                    text.push_str(&value);
+                }
+            }
+            Ok((_, Event::CData(e))) if current.is_some() => {
+                text.push_str(&String::from_utf8_lossy(&e));
+            }
+            Ok((ns, Event::End(e))) => {
+                let Some(property) = current else {
+                    continue;
+                };
+                if is_rdf(&ns, e.local_name(), b"li") {
+                    items.push((default_language, std::mem::take(&mut text)));
+                } else if self::property(&ns, e.local_name()) == Some(property) {
+                    match property {
+                        Property::Subject => {
+                            metadata.keywords = items
+                                .drain(..)
+                                .filter_map(|(_, value)| non_empty(value))
+                                .collect();
+                        }
+                        Property::Description | Property::Title if !items.is_empty() => {
+                            let index = items.iter().position(|(default, _)| *default);
+                            let value = items.swap_remove(index.unwrap_or(0)).1;
+                            metadata.set_simple(property, value);
+                        }
+                        _ => metadata.set_simple(property, std::mem::take(&mut text)),
+                    }
+                    current = None;
+                }
+            }
+            Ok((_, Event::Eof)) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    metadata
+}
+
+fn read_attributes(reader: &NsReader<&[u8]>, e: &BytesStart, metadata: &mut XmpMetadata) {
+    for attr in e.attributes().filter_map(Result::ok) {
+        let (ns, local) = reader.resolve_attribute(attr.key);
+        if let (Some(property), Ok(value)) = (property(&ns, local), attr.unescape_value()) {
+            metadata.set_simple(property, value.into_owned());
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+type Input = BufReader<fs::File>;
+
+fn read_segment(input: &mut Input, len: u64) -> Option<Vec<u8>> {
+    if len > MAX_PACKET_LEN {
+        return None;
+    }
+    let mut segment = vec![0; len as usize];
+    input.read_exact(&mut segment).ok()?;
+    Some(segment)
+}
+
+/// The APP1 segment with the XMP header, it comes before the image data.
+fn jpeg_segment(input: &mut Input) -> Option<Vec<u8>> {
+    input.seek(SeekFrom::Start(2)).ok()?;
+    loop {
+        let mut header = [0u8; 4];
+        input.read_exact(&mut header).ok()?;
+        let [0xff, marker, len @ ..] = header else {
+            return None;
+        };
+        // start of scan or end of image
+        if marker == 0xda || marker == 0xd9 {
+            return None;
+        }
+        let len = u64::from(u16::from_be_bytes(len).checked_sub(2)?);
+        if marker == 0xe1 {
+            let segment = read_segment(input, len)?;
+            if segment.starts_with(JPEG_XMP_HEADER) {
+                return Some(segment);
+            }
+        } else {
+            input.seek_relative(len as i64).ok()?;
+        }
+    }
+}
+
+/// The iTXt chunk with the XMP keyword.
+fn png_segment(input: &mut Input) -> Option<Vec<u8>> {
+    input.seek(SeekFrom::Start(8)).ok()?;
+    loop {
+        let mut header = [0u8; 8];
+        input.read_exact(&mut header).ok()?;
+        let len = u64::from(u32::from_be_bytes(header[..4].try_into().ok()?));
+        match &header[4..] {
+            b"IEND" => return None,
+            b"iTXt" => {
+                let chunk = read_segment(input, len)?;
+                if chunk.starts_with(PNG_XMP_KEYWORD) {
+                    return Some(chunk);
+                }
+                // crc
+                input.seek_relative(4).ok()?;
+            }
+            _ => input.seek_relative(len as i64 + 4).ok()?,
+        }
+    }
+}
+
+/// The XMP tag of the first IFD, also used by the TIFF based raw formats.
+fn tiff_segment(input: &mut Input) -> Option<Vec<u8>> {
+    let mut header = [0u8; 8];
+    input.read_exact(&mut header).ok()?;
+    let big_endian = header[0] == b'M';
+    let u16_at = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1]];
+        match big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        }
+    };
+    let u32_at = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    };
+
+    input
+        .seek(SeekFrom::Start(u32_at(&header[4..]).into()))
+        .ok()?;
+    let mut count = [0u8; 2];
+    input.read_exact(&mut count).ok()?;
+    for _ in 0..u16_at(&count) {
+        let mut entry = [0u8; 12];
+        input.read_exact(&mut entry).ok()?;
+        if u16_at(&entry) == TIFF_TAG_XMP {
+            let len = u32_at(&entry[4..]);
+            if len <= 4 {
+                return Some(entry[8..8 + len as usize].to_vec());
+            }
+            input
+                .seek(SeekFrom::Start(u32_at(&entry[8..]).into()))
+                .ok()?;
+            return read_segment(input, len.into());
+        }
+    }
+    None
+}
+
+/// The `XMP ` chunk, it follows the image data.
+fn webp_segment(input: &mut Input) -> Option<Vec<u8>> {
+    input.seek(SeekFrom::Start(12)).ok()?;
+    loop {
+        let mut header = [0u8; 8];
+        input.read_exact(&mut header).ok()?;
+        let len = u64::from(u32::from_le_bytes(header[4..].try_into().ok()?));
+        if &header[..4] == b"XMP " {
+            return read_segment(input, len);
+        }
+        input.seek_relative((len + len % 2) as i64).ok()?;
+    }
+}
+
+/// Extracts the XMP packet embedded in an image file. For JPEG, PNG, TIFF and WebP only the
+/// segment holding the packet is read, for other formats a bounded prefix is scanned.
+pub(crate) fn read_embedded_packet(path: &Path) -> Option<String> {
+    let mut input = BufReader::new(fs::File::open(path).ok()?);
+    let mut magic = Vec::new();
+    input.by_ref().take(12).read_to_end(&mut magic).ok()?;
+    input.rewind().ok()?;
+
+    let content = match magic.as_slice() {
+        [0xff, 0xd8, ..] => jpeg_segment(&mut input),
+        [0x89, b'P', b'N', b'G', ..] => png_segment(&mut input),
+        [b'I', b'I', 42, 0, ..] | [b'M', b'M', 0, 42, ..] => tiff_segment(&mut input),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P'] => webp_segment(&mut input),
+        _ => {
+            let mut prefix = Vec::new();
+            input.take(MAX_PACKET_LEN).read_to_end(&mut prefix).ok()?;
+            Some(prefix)
+        }
+    }?;
+
+    let start = find(&content, PACKET_START, 0)?;
+    let end = find(&content, PACKET_END, start)? + PACKET_END.len();
+    String::from_utf8(content[start..end].to_vec()).ok()
+}
+
+/// Returns the path of an existing sidecar (`image.xmp` or `image.jpg.xmp`).
+pub(crate) fn find_sidecar(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_string_lossy().into_owned();
+    let stem = path.file_stem()?.to_string_lossy().into_owned();
+
+    [stem, file_name]
+        .iter()
+        .flat_map(|name| [format!("{name}.xmp"), format!("{name}.XMP")])
+        .map(|name| path.with_file_name(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Reads the XMP metadata of an image. An existing sidecar replaces the embedded packet, it is
+/// created from the packet on the first write, so removed values must not be read from the
+/// image again.
+pub(crate) fn read(path: &Path) -> XmpMetadata {
+    let document = match find_sidecar(path) {
+        Some(sidecar) => fs::read_to_string(sidecar).ok(),
+        None => read_embedded_packet(path),
+    };
+    document.map(|xml| parse(&xml)).unwrap_or_default()
+}
+
+impl SetXmpMetadataRequest {
+    fn changed_properties(&self) -> Vec<Property> {
+        [
+            (self.rating.is_some(), Property::Rating),
+            (self.label.is_some(), Property::Label),
+            (self.keywords.is_some(), Property::Subject),
+            (self.description.is_some(), Property::Description),
+            (self.title.is_some(), Property::Title),
+        ]
+        .into_iter()
+        .filter_map(|(changed, property)| changed.then_some(property))
+        .collect()
+    }
+}
+
+type XmlWriter = Writer<Vec<u8>>;
+
+fn write_simple(writer: &mut XmlWriter, name: &str, value: &str) -> std::io::Result<()> {
+    writer.write_event(Event::Start(BytesStart::new(name)))?;
+    writer.write_event(Event::Text(BytesText::new(value)))?;
+    writer.write_event(Event::End(BytesEnd::new(name)))
+}
+
+fn write_list(
+    writer: &mut XmlWriter,
+    rdf: &str,
+    name: &str,
+    container: &str,
+    values: &[String],
+    language: Option<&str>,
+) -> std::io::Result<()> {
+    let container = format!("{rdf}:{container}");
+    let li = format!("{rdf}:li");
+
+    writer.write_event(Event::Start(BytesStart::new(name)))?;
+    writer.write_event(Event::Start(BytesStart::new(container.as_str())))?;
+    for value in values {
+        let mut start = BytesStart::new(li.as_str());
+        if let Some(language) = language {
+            start.push_attribute(("xml:lang", language));
+        }
+        writer.write_event(Event::Start(start))?;
+        writer.write_event(Event::Text(BytesText::new(value)))?;
+        writer.write_event(Event::End(BytesEnd::new(li.as_str())))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new(container.as_str())))?;
+    writer.write_event(Event::End(BytesEnd::new(name)))
+}
+
+fn write_properties(
+    writer: &mut XmlWriter,
+    rdf: &str,
+    update: &SetXmpMetadataRequest,
+) -> std::io::Result<()> {
+    if let Some(rating) = update.rating {
+        write_simple(writer, "xmp:Rating", &rating.to_string())?;
+    }
+    if let Some(label) = update.label.as_deref().filter(|label| !label.is_empty()) {
+        write_simple(writer, "xmp:Label", label)?;
+    }
+    if let Some(keywords) = update.keywords.as_ref().filter(|k| !k.is_empty()) {
+        write_list(writer, rdf, "dc:subject", "Bag", keywords, None)?;
+    }
+    for (name, value) in [
+        ("dc:description", &update.description),
+        ("dc:title", &update.title),
+    ] {
+        if let Some(value) = value.as_ref().filter(|value| !value.is_empty()) {
+            let values = [value.clone()];
+            write_list(writer, rdf, name, "Alt", &values, Some("x-default"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies the rdf:Description start tag without the changed properties in attribute form and
+/// makes sure the namespaces of the written properties are declared.
+fn rewrite_description<'a>(
+    reader: &NsReader<&[u8]>,
+    e: &'a BytesStart,
+    changed: &[Property],
+) -> BytesStart<'a> {
+    let mut start = e.to_owned();
+    start.clear_attributes();
+    for attr in e.attributes().filter_map(Result::ok) {
+        let (ns, local) = reader.resolve_attribute(attr.key);
+        if !property(&ns, local).is_some_and(|property| changed.contains(&property)) {
+            start.push_attribute(attr);
+        }
+    }
+
+    for (prefix, namespace) in [("xmp", NS_XMP), ("dc", NS_DC)] {
+        let name = format!("{prefix}:_");
+        let (ns, _) = reader.resolve_element(QName(name.as_bytes()));
+        if !is_namespace(&ns, namespace) {
+            start.push_attribute((format!("xmlns:{prefix}").as_bytes(), namespace));
+        }
+    }
+    start
+}
+
+fn prefix_of(name: QName) -> String {
+    let prefix = name.prefix().map(|prefix| prefix.into_inner());
+    String::from_utf8_lossy(prefix.unwrap_or(b"rdf")).into_owned()
+}
+
+/// Replaces the changed properties in a XMP document, everything else is kept as is.
+pub(crate) fn update_document(xml: &str, update: &SetXmpMetadataRequest) -> crate::Result<String> {
+    let changed = update.changed_properties();
+    let mut reader = NsReader::from_str(xml);
+    let mut writer = Writer::new(Vec::new());
+
+    let mut depth = 0usize;
+    // > 0 while skipping the content of a changed property
+    let mut skip_depth = 0usize;
+    // depth and rdf prefix of the rdf:Description the new values are written to
+    let mut target: Option<(usize, String)> = None;
+    let mut written = false;
+
+    let xml_error = |e: quick_xml::Error| Error::Xmp(e.to_string());
+
+    loop {
+        let (ns, event) = reader.read_resolved_event().map_err(xml_error)?;
+
+        if skip_depth > 0 {
+            match event {
+                Event::Start(_) => skip_depth += 1,
+                Event::End(_) => skip_depth -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+            continue;
+        }
+
+        match event {
+            Event::Start(e) if is_changed(&ns, e.local_name(), &changed) => skip_depth = 1,
+            Event::Empty(e) if is_changed(&ns, e.local_name(), &changed) => {}
+            Event::Start(e) if is_rdf(&ns, e.local_name(), b"Description") => {
+                let start = rewrite_description(&reader, &e, &changed);
+                writer.write_event(Event::Start(start))?;
+                if target.is_none() && !written {
+                    target = Some((depth, prefix_of(e.name())));
+                }
+                depth += 1;
+            }
+            Event::Empty(e) if is_rdf(&ns, e.local_name(), b"Description") => {
+                let start = rewrite_description(&reader, &e, &changed);
+                if written || target.is_some() {
+                    writer.write_event(Event::Empty(start))?;
+                } else {
+                    let rdf = prefix_of(e.name());
+                    let end = start.to_end().into_owned();
+                    writer.write_event(Event::Start(start))?;
+                    write_properties(&mut writer, &rdf, update)?;
+                    writer.write_event(Event::End(end))?;
+                    written = true;
+                }
+            }
+            Event::Start(e) => {
+                writer.write_event(Event::Start(e))?;
+                depth += 1;
+            }
+            Event::End(e) => {
+                depth = depth.saturating_sub(1);
+                if let Some((_, rdf)) = target.as_ref().filter(|(d, _)| *d == depth && !written) {
+                    write_properties(&mut writer, rdf, update)?;
+                    written = true;
+                }
+                writer.write_event(Event::End(e))?;
+            }
+            Event::Eof => break,
+            event => writer.write_event(event)?,
+        }
+    }
+
+    if !written {
+        if xml == EMPTY_DOCUMENT {
+            return Err(Error::Xmp("unable to write xmp document".to_string()));
+        }
+        // not a usable XMP document, start from scratch
+        return update_document(EMPTY_DOCUMENT, update);
+    }
+
+    String::from_utf8(writer.into_inner()).map_err(|e| Error::Xmp(e.to_string()))
+}
+
+/// Writes the changed values to the sidecar of the image. If there is no sidecar yet it is
+/// created from the embedded packet, so existing metadata is carried over.
+pub(crate) fn write(path: &Path, update: &SetXmpMetadataRequest) -> crate::Result<XmpMetadata> {
+    let existing = find_sidecar(path);
+    let document = match &existing {
+        Some(sidecar) => fs::read_to_string(sidecar)?,
+        None => read_embedded_packet(path).unwrap_or_else(|| EMPTY_DOCUMENT.to_string()),
+    };
+    let document = update_document(&document, update)?;
+
+    let sidecar = existing.unwrap_or_else(|| path.with_extension("xmp"));
+    let temp_file = sidecar.with_extension("xmp.tmp");
+    let mut out = fs::File::create(&temp_file)?;
+    out.write_all(document.as_bytes())?;
+    out.sync_all()?;
+    fs::rename(temp_file, &sidecar)?;
+
+    Ok(read(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+    xmp:Rating="3" xmp:Label="Red" crs:Exposure2012="+0.50">
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>beach</rdf:li>
+     <rdf:li>holiday &amp; family</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+   <dc:title>
+    <rdf:Alt>
+     <rdf:li xml:lang="de">Strand</rdf:li>
+     <rdf:li xml:lang="x-default">Beach</rdf:li>
+    </rdf:Alt>
+   </dc:title>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    fn jpeg_with_packet() -> Vec<u8> {
+        let mut app1 = JPEG_XMP_HEADER.to_vec();
+        app1.extend_from_slice(SIDECAR.as_bytes());
+
+        let mut content = b"\xff\xd8\xff\xe0\x00\x04\x00\x00\xff\xe1".to_vec();
+        content.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+        content.extend_from_slice(&app1);
+        content.extend_from_slice(b"\xff\xda\x00\x02");
+        // a packet in the image data must not be found
+        content.extend_from_slice(EMPTY_DOCUMENT.as_bytes());
+        content.extend_from_slice(b"\xff\xd9");
+        content
+    }
+
+    #[test]
+    fn test_read_embedded_packet() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let jpeg = dir.join("image.jpg");
+        fs::write(&jpeg, jpeg_with_packet()).unwrap();
+        assert_eq!(read_embedded_packet(&jpeg).as_deref(), Some(SIDECAR));
+
+        let mut itxt = PNG_XMP_KEYWORD.to_vec();
+        itxt.extend_from_slice(b"\0\0\0\0");
+        itxt.extend_from_slice(SIDECAR.as_bytes());
+        let mut png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x01IDATx\x00\x00\x00\x00".to_vec();
+        png.extend_from_slice(&(itxt.len() as u32).to_be_bytes());
+        png.extend_from_slice(b"iTXt");
+        png.extend_from_slice(&itxt);
+        png.extend_from_slice(b"\x00\x00\x00\x00\x00\x00\x00\x00IEND\x00\x00\x00\x00");
+        let path = dir.join("image.png");
+        fs::write(&path, png).unwrap();
+        assert_eq!(read_embedded_packet(&path).as_deref(), Some(SIDECAR));
+
+        // little endian TIFF, the packet follows the first IFD with a single entry
+        let mut tiff = b"II\x2a\x00\x08\x00\x00\x00\x01\x00".to_vec();
+        tiff.extend_from_slice(&TIFF_TAG_XMP.to_le_bytes());
+        tiff.extend_from_slice(&7u16.to_le_bytes());
+        tiff.extend_from_slice(&(SIDECAR.len() as u32).to_le_bytes());
+        tiff.extend_from_slice(&26u32.to_le_bytes());
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(SIDECAR.as_bytes());
+        let path = dir.join("image.tif");
+        fs::write(&path, tiff).unwrap();
+        assert_eq!(read_embedded_packet(&path).as_deref(), Some(SIDECAR));
+
+        let path = dir.join("truncated.jpg");
+        fs::write(&path, b"\xff\xd8\xff\xe1\xff\xff").unwrap();
+        assert_eq!(read_embedded_packet(&path), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let metadata = parse(SIDECAR);
+        assert_eq!(metadata.rating, Some(3));
+        assert_eq!(metadata.label.as_deref(), Some("Red"));
+        assert_eq!(metadata.keywords, vec!["beach", "holiday & family"]);
+        assert_eq!(metadata.title.as_deref(), Some("Beach"));
+        assert_eq!(metadata.description, None);
+
+        // other prefixes and the element form
+        let metadata = parse(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+            <rdf:Description xmlns:a="http://ns.adobe.com/xap/1.0/"><a:Rating>-1</a:Rating></rdf:Description>
+            </rdf:RDF></x:xmpmeta>"#,
+        );
+        assert_eq!(metadata.rating, Some(-1));
+    }
+
+    #[test]
+    fn test_update_document_keeps_unknown_properties() {
+        let update = SetXmpMetadataRequest {
+            rating: Some(5),
+            keywords: Some(vec!["sunset".to_string()]),
+            description: Some("At the beach".to_string()),
+            ..Default::default()
+        };
+        let document = update_document(SIDECAR, &update).unwrap();
+        assert!(document.contains(r#"crs:Exposure2012="+0.50""#));
+
+        let metadata = parse(&document);
+        assert_eq!(metadata.rating, Some(5));
+        assert_eq!(metadata.label.as_deref(), Some("Red"));
+        assert_eq!(metadata.keywords, vec!["sunset"]);
+        assert_eq!(metadata.description.as_deref(), Some("At the beach"));
+        assert_eq!(metadata.title.as_deref(), Some("Beach"));
+
+        let update = SetXmpMetadataRequest {
+            label: Some(String::new()),
+            ..Default::default()
+        };
+        assert_eq!(
+            parse(&update_document(&document, &update).unwrap()).label,
+            None
+        );
+
+        let update = SetXmpMetadataRequest {
+            title: Some("New".to_string()),
+            ..Default::default()
+        };
+        let document = update_document("not xmp", &update).unwrap();
+        assert_eq!(parse(&document).title.as_deref(), Some("New"));
+    }
+
+    #[test]
+    fn test_sidecar_overrides_embedded_packet() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let image = dir.join("image.jpg");
+        fs::write(&image, jpeg_with_packet()).unwrap();
+        let content = fs::read(&image).unwrap();
+
+        assert_eq!(read(&image).rating, Some(3));
+
+        let update = SetXmpMetadataRequest {
+            rating: Some(1),
+            ..Default::default()
+        };
+        let metadata = write(&image, &update).unwrap();
+        assert_eq!(metadata.rating, Some(1));
+        assert_eq!(metadata.keywords, vec!["beach", "holiday & family"]);
+        assert!(dir.join("image.xmp").exists());
+        assert_eq!(fs::read(&image).unwrap(), content);
+
+        let update = SetXmpMetadataRequest {
+            label: Some(String::new()),
+            keywords: Some(Vec::new()),
+            ..Default::default()
+        };
+        let metadata = write(&image, &update).unwrap();
+        assert_eq!(metadata.label, None);
+        assert!(metadata.keywords.is_empty());
+        assert_eq!(read(&image).title.as_deref(), Some("Beach"));
+    }
+}
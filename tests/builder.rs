@@ -0,0 +1,126 @@
+#![cfg(desktop)]
+
+use std::path::Path;
+
+use tauri::test::{mock_builder, mock_context, noop_assets, MockRuntime};
+use tauri_plugin_medialibrary::{
+    Builder, DirectoryReader, FindDuplicatesRequest, GetImagesResult, GetLibraryContentRequest,
+    ImageInfo, MediaLibrarySource, MedialibraryExt,
+};
+
+struct CustomReader;
+
+impl DirectoryReader for CustomReader {
+    fn read_directory(
+        &self,
+        _request: &GetLibraryContentRequest,
+    ) -> tauri_plugin_medialibrary::Result<GetImagesResult> {
+        Ok(GetImagesResult {
+            items: vec![ImageInfo {
+                path: "/custom/image.jpg".to_string(),
+                ..Default::default()
+            }],
+        })
+    }
+}
+
+fn app(builder: Builder, root: &Path) -> tauri::App<MockRuntime> {
+    app_with_config(
+        builder,
+        serde_json::json!({
+            "roots": { "PictureDir": root },
+            "index": false,
+            "pregenerateThumbnails": false,
+        }),
+    )
+}
+
+fn app_with_config(builder: Builder, config: serde_json::Value) -> tauri::App<MockRuntime> {
+    let mut context = mock_context(noop_assets());
+    context
+        .config_mut()
+        .plugins
+        .0
+        .insert("medialibrary".to_string(), config);
+    mock_builder()
+        .plugin(builder.build())
+        .build(context)
+        .unwrap()
+}
+
+fn listed_files(app: &tauri::App<MockRuntime>) -> Vec<String> {
+    let request = GetLibraryContentRequest {
+        limit: 10,
+        ..Default::default()
+    };
+    let mut files: Vec<String> = app
+        .medialibrary()
+        .get_images(request)
+        .unwrap()
+        .items
+        .into_iter()
+        .map(|item| item.path)
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_custom_directory_reader() {
+    let temp = tempfile::tempdir().unwrap();
+    std::fs::write(temp.path().join("a.jpg"), b"image").unwrap();
+
+    let app = app(
+        Builder::new().directory_reader(MediaLibrarySource::PictureDir, CustomReader),
+        temp.path(),
+    );
+    assert_eq!(listed_files(&app), vec!["/custom/image.jpg"]);
+}
+
+#[test]
+fn test_file_filter() {
+    let temp = tempfile::tempdir().unwrap();
+    let root = temp.path();
+    for name in ["a.jpg", "b.jpg", "skip.jpg"] {
+        std::fs::write(root.join(name), b"same").unwrap();
+    }
+
+    let app = app(
+        Builder::new().file_filter(|path, _| !path.ends_with("skip.jpg")),
+        root,
+    );
+    let path = |name: &str| root.join(name).to_string_lossy().to_string();
+    assert_eq!(listed_files(&app), vec![path("a.jpg"), path("b.jpg")]);
+
+    // the scans skip the filtered files as well
+    let duplicates = app
+        .medialibrary()
+        .find_duplicates(FindDuplicatesRequest {
+            source: MediaLibrarySource::PictureDir,
+            job_id: None,
+        })
+        .unwrap();
+    assert_eq!(duplicates.groups.len(), 1);
+    assert_eq!(duplicates.groups[0].items.len(), 2);
+}
+
+#[test]
+fn test_thumbnail_cache_in_cache_dir() {
+    let temp = tempfile::tempdir().unwrap();
+    let cache_dir = temp.path().join("cache");
+
+    let app = app_with_config(
+        Builder::new(),
+        serde_json::json!({
+            "roots": { "PictureDir": temp.path() },
+            "cacheDir": cache_dir,
+            "index": false,
+            "pregenerateThumbnails": false,
+        }),
+    );
+    let stats = app.medialibrary().get_cache_stats().unwrap();
+    assert_eq!(
+        stats.directory,
+        Some(cache_dir.join("thumbnails").to_string_lossy().to_string())
+    );
+}